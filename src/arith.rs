@@ -0,0 +1,626 @@
+use crate::vars::Variables;
+
+// Evaluation of arithmetic expressions like $(( x * 2 + 1 ))
+// Supports the C integer operators with the precedence used by other shells, including
+// assignments, increments, the ternary operator and `**` for exponentiation
+
+// Variables whose value refers to other variables are evaluated recursively up to this depth
+const MAX_DEPTH: usize = 32;
+
+// Binary operators grouped from the lowest to the highest precedence
+const BINARY_OPERATORS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+// Operators sorted so that longer ones are matched first
+const OPERATORS: [&str; 41] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^",
+    "|", "?", ":", ",", "(", ")", "[", "]",
+];
+
+const ASSIGNMENT_OPERATORS: [&str; 11] = [
+    "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+// A variable or an array element that can be assigned
+enum Target {
+    Variable(String),
+    Element(String, i64),
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+// Evaluate `expression` reading and assigning variables in `vars`
+pub fn evaluate(expression: &str, vars: &mut Variables) -> Result<i64, String> {
+    evaluate_nested(expression, vars, 0)
+}
+
+fn evaluate_nested(expression: &str, vars: &mut Variables, depth: usize) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!(
+            "{}: expression recursion level exceeded",
+            expression.trim()
+        ));
+    }
+
+    let tokens = tokenize(expression)?;
+
+    // An empty expression evaluates to zero
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        vars,
+        depth,
+        skip: false,
+    };
+
+    let value = parser.comma()?;
+
+    if let Some(token) = parser.peek() {
+        return Err(format!(
+            "{}: syntax error in expression (error token is \"{}\")",
+            expression.trim(),
+            token_text(&token)
+        ));
+    }
+
+    Ok(value)
+}
+
+fn token_text(token: &Token) -> String {
+    match token {
+        Token::Number(number) => number.to_string(),
+        Token::Name(name) => name.clone(),
+        Token::Operator(operator) => operator.to_string(),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+
+        if c.is_whitespace() {
+            index += 1;
+        } else if c.is_ascii_digit() {
+            let start = index;
+            while index < chars.len()
+                && (chars[index].is_ascii_alphanumeric()
+                    || chars[index] == '#'
+                    || chars[index] == '_')
+            {
+                index += 1;
+            }
+            let literal: String = chars[start..index].iter().collect();
+            tokens.push(Token::Number(parse_number(&literal)?));
+        } else if c == '_' || c.is_ascii_alphabetic() {
+            let start = index;
+            while index < chars.len()
+                && (chars[index] == '_' || chars[index].is_ascii_alphanumeric())
+            {
+                index += 1;
+            }
+            tokens.push(Token::Name(chars[start..index].iter().collect()));
+        } else {
+            let rest: String = chars[index..].iter().take(3).collect();
+            match OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(*operator))
+            {
+                Some(operator) => {
+                    tokens.push(Token::Operator(operator));
+                    index += operator.len();
+                }
+                None => {
+                    let rest: String = chars[index..].iter().collect();
+                    return Err(format!(
+                        "{}: syntax error: operand expected (error token is \"{}\")",
+                        expression.trim(),
+                        rest
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Parse an integer literal: decimal, 0x hexadecimal, 0 octal or base#digits
+fn parse_number(literal: &str) -> Result<i64, String> {
+    let invalid = || {
+        format!(
+            "{}: value too great for base (error token is \"{}\")",
+            literal, literal
+        )
+    };
+
+    let (base, digits) = if let Some((base, digits)) = literal.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base) if (2..=64).contains(&base) => (base, digits),
+            _ => {
+                return Err(format!(
+                    "{}: invalid arithmetic base (error token is \"{}\")",
+                    literal, literal
+                ))
+            }
+        }
+    } else if let Some(digits) = literal
+        .strip_prefix("0x")
+        .or_else(|| literal.strip_prefix("0X"))
+    {
+        (16, digits)
+    } else if literal.len() > 1 && literal.starts_with('0') {
+        (8, &literal[1..])
+    } else {
+        (10, literal)
+    };
+
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            // Upper case letters are the same as lower case ones up to base 36
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+
+        if digit >= base {
+            return Err(invalid());
+        }
+
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+
+    Ok(value)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    vars: &'a mut Variables,
+    depth: usize,
+
+    // Set while parsing an operand that must not be evaluated, like the right side of `0 && x++`
+    skip: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        if self.peek_operator() == Some(operator) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!(
+                "syntax error in expression (expected \"{}\")",
+                operator
+            ))
+        }
+    }
+
+    // Parse an operand with evaluation disabled if `skip` is true
+    fn parse_skipped<F>(&mut self, skip: bool, parse: F) -> Result<i64, String>
+    where
+        F: FnOnce(&mut Self) -> Result<i64, String>,
+    {
+        let previous = self.skip;
+        self.skip = previous || skip;
+        let value = parse(self);
+        self.skip = previous;
+
+        value
+    }
+
+    fn comma(&mut self) -> Result<i64, String> {
+        let mut value = self.assignment()?;
+
+        while self.peek_operator() == Some(",") {
+            self.pos += 1;
+            value = self.assignment()?;
+        }
+
+        Ok(value)
+    }
+
+    fn assignment(&mut self) -> Result<i64, String> {
+        let start = self.pos;
+
+        // Look for an assignment operator after the target without evaluating its subscript
+        let previous = self.skip;
+        self.skip = true;
+        let is_assignment = match self.target() {
+            Ok(Some(_)) => {
+                matches!(self.peek_operator(), Some(operator) if ASSIGNMENT_OPERATORS.contains(&operator))
+            }
+            _ => false,
+        };
+        self.skip = previous;
+        self.pos = start;
+
+        if !is_assignment {
+            return self.ternary();
+        }
+
+        let target = self.target()?.unwrap();
+        let operator = self.peek_operator().unwrap();
+        self.pos += 1;
+
+        let right = self.assignment()?;
+        let value = if operator == "=" {
+            right
+        } else {
+            let left = self.value_of(&target)?;
+            self.apply(&operator[..operator.len() - 1], left, right)?
+        };
+
+        self.assign(&target, value);
+
+        Ok(value)
+    }
+
+    // Parse a variable name or an array element like a[i + 1]
+    fn target(&mut self) -> Result<Option<Target>, String> {
+        let name = match self.peek() {
+            Some(Token::Name(name)) => name,
+            _ => return Ok(None),
+        };
+        self.pos += 1;
+
+        if self.peek_operator() != Some("[") {
+            return Ok(Some(Target::Variable(name)));
+        }
+
+        self.pos += 1;
+        let index = self.comma()?;
+        self.expect("]")?;
+
+        Ok(Some(Target::Element(name, index)))
+    }
+
+    fn ternary(&mut self) -> Result<i64, String> {
+        let condition = self.binary(0)?;
+
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+
+        self.pos += 1;
+        let if_true = self.parse_skipped(condition == 0, |parser| parser.assignment())?;
+        self.expect(":")?;
+        let if_false = self.parse_skipped(condition != 0, |parser| parser.ternary())?;
+
+        Ok(if condition != 0 { if_true } else { if_false })
+    }
+
+    // Parse binary operators of precedence `level` and higher
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == BINARY_OPERATORS.len() {
+            return self.power();
+        }
+
+        let mut left = self.binary(level + 1)?;
+
+        while let Some(operator) = self.peek_operator() {
+            if !BINARY_OPERATORS[level].contains(&operator) {
+                break;
+            }
+            self.pos += 1;
+
+            left = match operator {
+                // Short-circuit the logical operators
+                "&&" => {
+                    let right = self.parse_skipped(left == 0, |parser| parser.binary(level + 1))?;
+                    (left != 0 && right != 0) as i64
+                }
+                "||" => {
+                    let right = self.parse_skipped(left != 0, |parser| parser.binary(level + 1))?;
+                    (left != 0 || right != 0) as i64
+                }
+                _ => {
+                    let right = self.binary(level + 1)?;
+                    self.apply(operator, left, right)?
+                }
+            };
+        }
+
+        Ok(left)
+    }
+
+    // `**` is right associative and binds tighter than the other binary operators
+    fn power(&mut self) -> Result<i64, String> {
+        let base = self.unary()?;
+
+        if self.peek_operator() == Some("**") {
+            self.pos += 1;
+            let exponent = self.power()?;
+            return self.apply("**", base, exponent);
+        }
+
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.peek_operator() {
+            Some("+") => {
+                self.pos += 1;
+                self.unary()
+            }
+            Some("-") => {
+                self.pos += 1;
+                Ok(self.unary()?.wrapping_neg())
+            }
+            Some("!") => {
+                self.pos += 1;
+                Ok((self.unary()? == 0) as i64)
+            }
+            Some("~") => {
+                self.pos += 1;
+                Ok(!self.unary()?)
+            }
+            Some(operator @ "++") | Some(operator @ "--") => {
+                self.pos += 1;
+                match self.target()? {
+                    Some(target) => {
+                        let value = self.value_of(&target)?.wrapping_add(if operator == "++" {
+                            1
+                        } else {
+                            -1
+                        });
+                        self.assign(&target, value);
+                        Ok(value)
+                    }
+                    None => Err(format!(
+                        "syntax error: operand expected (error token is \"{}\")",
+                        operator
+                    )),
+                }
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<i64, String> {
+        match self.peek() {
+            Some(Token::Number(number)) => {
+                self.pos += 1;
+                Ok(number)
+            }
+            Some(Token::Name(_)) => {
+                let target = self.target()?.unwrap();
+                let value = self.value_of(&target)?;
+
+                if let Some(operator @ "++") | Some(operator @ "--") = self.peek_operator() {
+                    self.pos += 1;
+                    self.assign(
+                        &target,
+                        value.wrapping_add(if operator == "++" { 1 } else { -1 }),
+                    );
+                }
+
+                Ok(value)
+            }
+            Some(Token::Operator("(")) => {
+                self.pos += 1;
+                let value = self.comma()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Some(token) => Err(format!(
+                "syntax error: operand expected (error token is \"{}\")",
+                token_text(&token)
+            )),
+            None => Err(String::from("syntax error: operand expected")),
+        }
+    }
+
+    fn apply(&self, operator: &str, left: i64, right: i64) -> Result<i64, String> {
+        if self.skip {
+            return Ok(0);
+        }
+
+        let value = match operator {
+            "|" => left | right,
+            "^" => left ^ right,
+            "&" => left & right,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            "<" => (left < right) as i64,
+            "<=" => (left <= right) as i64,
+            ">" => (left > right) as i64,
+            ">=" => (left >= right) as i64,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => return Err(String::from("division by 0")),
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "**" if right < 0 => return Err(String::from("exponent less than 0")),
+            "**" => left.wrapping_pow(right as u32),
+            _ => return Err(format!("{}: unknown operator", operator)),
+        };
+
+        Ok(value)
+    }
+
+    // Value of a variable or an array element, evaluated as an expression itself
+    fn value_of(&mut self, target: &Target) -> Result<i64, String> {
+        if self.skip {
+            return Ok(0);
+        }
+
+        let value = match target {
+            Target::Variable(name) => self.vars.get(name),
+            Target::Element(name, index) => match self.element_index(name, *index) {
+                Some(index) if self.vars.is_associative(name) => {
+                    self.vars.get_associative(name, &index.to_string())
+                }
+                Some(index) => self.vars.get_indexed(name, index),
+                None => None,
+            },
+        };
+
+        let value = match value {
+            Some(value) => value.trim().to_string(),
+            None => return Ok(0),
+        };
+
+        match value.parse::<i64>() {
+            Ok(number) => Ok(number),
+            Err(_) if value.is_empty() => Ok(0),
+            Err(_) => evaluate_nested(&value, self.vars, self.depth + 1),
+        }
+    }
+
+    fn assign(&mut self, target: &Target, value: i64) {
+        if self.skip {
+            return;
+        }
+
+        match target {
+            Target::Variable(name) => self.vars.set(name, value.to_string()),
+            Target::Element(name, index) => match self.element_index(name, *index) {
+                Some(index) if self.vars.is_associative(name) => {
+                    self.vars
+                        .set_associative(name, index.to_string(), value.to_string())
+                }
+                Some(index) => self.vars.set_indexed(name, index, value.to_string()),
+                None => {}
+            },
+        }
+    }
+
+    // Negative indexes count back from the end of the array
+    fn element_index(&self, name: &str, index: i64) -> Option<usize> {
+        let index = if index < 0 {
+            self.vars.next_index(name) as i64 + index
+        } else {
+            index
+        };
+
+        if index < 0 {
+            None
+        } else {
+            Some(index as usize)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::shell;
+
+    fn eval(expression: &str) -> Result<i64, String> {
+        evaluate(expression, &mut shell().vars)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("-2 ** 2"), Ok(4));
+        assert_eq!(eval("1 + 2 << 3"), Ok(24));
+        assert_eq!(eval("1 < 2 == 1"), Ok(1));
+        assert_eq!(eval("6 & 3 | 8 ^ 1"), Ok(11));
+        assert_eq!(eval("0 || 1 && 0"), Ok(0));
+        assert_eq!(eval("1 ? 2 : 0 ? 3 : 4"), Ok(2));
+        assert_eq!(eval("1, 2, 3"), Ok(3));
+        assert_eq!(eval("!0 + ~0"), Ok(0));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(eval("0x1F + 010 + 2#101"), Ok(31 + 8 + 5));
+        assert_eq!(eval("64#_"), Ok(63));
+        assert!(eval("08").is_err());
+        assert!(eval("65#1").is_err());
+    }
+
+    #[test]
+    fn variables() {
+        let mut shell = shell();
+        shell.vars.set("x", String::from("3"));
+        shell.vars.set("y", String::from("x * 2"));
+
+        assert_eq!(evaluate("y + 1", &mut shell.vars), Ok(7));
+        assert_eq!(evaluate("x++ + ++x", &mut shell.vars), Ok(8));
+        assert_eq!(shell.vars.get("x"), Some("5"));
+        assert_eq!(evaluate("x *= 2, x", &mut shell.vars), Ok(10));
+        assert_eq!(evaluate("unset + 1", &mut shell.vars), Ok(1));
+
+        // The operand that is not evaluated has no side effect
+        assert_eq!(evaluate("0 && x++", &mut shell.vars), Ok(0));
+        assert_eq!(shell.vars.get("x"), Some("10"));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("1 / 0"), Err(String::from("division by 0")));
+        assert_eq!(eval("1 % (2 - 2)"), Err(String::from("division by 0")));
+        assert_eq!(eval("x = 1, x /= 0"), Err(String::from("division by 0")));
+        assert_eq!(eval("0 && 1 / 0"), Ok(0));
+    }
+
+    #[test]
+    fn overflow() {
+        // Integers wrap around like in other shells
+        assert_eq!(eval("9223372036854775807 + 1"), Ok(i64::MIN));
+        assert_eq!(eval("-9223372036854775807 - 2"), Ok(i64::MAX));
+        assert_eq!(eval("2 ** 64"), Ok(0));
+        assert_eq!(eval("-9223372036854775808 / -1"), Ok(i64::MIN));
+        assert_eq!(eval("-9223372036854775808 % -1"), Ok(0));
+        assert_eq!(eval("9223372036854775808"), Ok(i64::MIN));
+        assert_eq!(eval("x = 9223372036854775807, ++x"), Ok(i64::MIN));
+        assert_eq!(eval("x = -9223372036854775808, x--, x"), Ok(i64::MAX));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(eval("1 +").is_err());
+        assert!(eval("(1").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("1 $ 2").is_err());
+        assert_eq!(eval("2 ** -1"), Err(String::from("exponent less than 0")));
+    }
+}
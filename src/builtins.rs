@@ -0,0 +1,1065 @@
+use crate::conditional;
+use crate::dirs;
+use crate::expand;
+use crate::hash;
+use crate::keymap::{self, Binding};
+use crate::parser::KEYWORDS;
+use crate::process::{assign, error_message, flush_stdout, parse_assignment};
+use crate::shell::Shell;
+use crate::vars::{is_valid_name, quote, Value, Variable};
+use std::io::{self, stdout, Write};
+use std::path::{Path, PathBuf};
+
+// Names of all the builtins, including the declaration builtins run by `declare`
+pub const BUILTINS: [&str; 24] = [
+    "[", "bind", "cd", "command", "declare", "dirs", "echo", "exit", "export", "hash", "history",
+    "popd", "pushd", "pwd", "return", "set", "shift", "shopt", "test", "type", "typeset", "unset",
+    "which", "z",
+];
+
+// Run builtin `command` and return its exit status or None if `command` is not a builtin
+pub fn run(command: &str, args: Vec<String>, shell: &mut Shell) -> Option<i32> {
+    let status = match command {
+        "pwd" => dirs::pwd(args, shell),
+        "cd" => dirs::cd(args, shell),
+        "pushd" => dirs::pushd(args, shell),
+        "popd" => dirs::popd(args, shell),
+        "dirs" => dirs::dirs(args, shell),
+        "z" => dirs::z(args, shell),
+        "echo" => echo(args),
+        "exit" => exit(args, shell),
+        "return" => return_(args, shell),
+        "history" => history(shell.history.get_history_elements()),
+        "shopt" => shopt(args, shell),
+        "unset" => unset(args, shell),
+        "set" => set(args, shell),
+        "shift" => shift(args, shell),
+        "test" | "[" => conditional::test(command, &args, shell),
+        "type" => type_(args, shell),
+        "which" => which(args, shell),
+        "command" => command_builtin(args, shell),
+        "hash" => hash(args, shell),
+        "bind" => bind(args, shell),
+        _ => return None,
+    };
+
+    Some(status)
+}
+
+// Report that builtin `name` could not write its output, like to a full disk, and return the
+// status it fails with
+pub(crate) fn write_error(name: &str, err: io::Error) -> i32 {
+    eprintln!("fsh: {}: write error: {}", name, error_message(&err));
+
+    1
+}
+
+// Write `text` and a new line to the standard output in a single write
+// Nothing is left buffered when it fails, so the text is not written later to where the standard
+// output goes after a redirection
+pub(crate) fn write_line(text: &str) -> io::Result<()> {
+    stdout().write_all(format!("{}\n", text).as_bytes())
+}
+
+fn echo(args: Vec<String>) -> i32 {
+    if let Err(err) = write_line(&args.join(" ")) {
+        return write_error("echo", err);
+    }
+
+    0
+}
+
+fn exit(args: Vec<String>, shell: &mut Shell) -> i32 {
+    // Exit with the status of the last command if none is given
+    let status = match args.first() {
+        Some(status) => match status.parse::<i32>() {
+            Ok(status) => status,
+            Err(_) => {
+                eprintln!("fsh: exit: {}: numeric argument required", status);
+                2
+            }
+        },
+        None => shell.last_status,
+    };
+
+    // Only the interactive shell owns the history file
+    if !shell.subshell {
+        shell.history.save();
+        shell.directories.save();
+    }
+
+    flush_stdout();
+    std::process::exit(status)
+}
+
+fn history(history_elements: Vec<&String>) -> i32 {
+    for element in history_elements {
+        if let Err(err) = write_line(element) {
+            return write_error("history", err);
+        }
+    }
+
+    0
+}
+
+// Enable, disable or display shell options
+// Usage: shopt [-s|-u] [-p] [-q] [optname...]
+fn shopt(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let mut set = None;
+    let mut reusable_format = false;
+    let mut quiet = false;
+    let mut names = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "-s" => set = Some(true),
+            "-u" => set = Some(false),
+            "-p" => reusable_format = true,
+            "-q" => quiet = true,
+            _ => names.push(arg),
+        }
+    }
+
+    // Set or unset the named options
+    if let Some(value) = set {
+        let mut status = 0;
+        for name in names {
+            if let Err(msg) = shell.options.set_shopt(&name, value) {
+                eprintln!("fsh: shopt: {}", msg);
+                status = 1;
+            }
+        }
+        return status;
+    }
+
+    // Display the named options or all of them if none is named
+    let options: Vec<(String, bool)> = if names.is_empty() {
+        shell
+            .options
+            .shopt_options()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    } else {
+        names
+            .into_iter()
+            .map(|name| {
+                let value = shell.options.shopt(&name);
+                (name, value)
+            })
+            .collect()
+    };
+
+    // With -q the exit status tells whether all the named options are enabled
+    let status = if options.iter().all(|(_, value)| *value) {
+        0
+    } else {
+        1
+    };
+
+    if quiet {
+        return status;
+    }
+
+    for (name, value) in options {
+        let written = if reusable_format {
+            write_line(&format!(
+                "shopt {} {}",
+                if value { "-s" } else { "-u" },
+                name
+            ))
+        } else {
+            write_line(&format!(
+                "{:<15}\t{}",
+                name,
+                if value { "on" } else { "off" }
+            ))
+        };
+        if let Err(err) = written {
+            return write_error("shopt", err);
+        }
+    }
+
+    status
+}
+
+// Declare variables and their attributes or display them
+// `export` is the same as `declare -x` but lists variables in its own format
+// Usage: declare [-aAxp] [name[=value]...]
+//        export [-p] [name[=value]...]
+// The arguments are not expanded yet, so the values of assignments are not split into fields
+pub fn declare(command: &str, words: &[String], shell: &mut Shell) -> i32 {
+    let mut indexed = false;
+    let mut associative = false;
+    let mut export = if command == "export" {
+        Some(true)
+    } else {
+        None
+    };
+    let mut print = false;
+    let mut status = 0;
+
+    // Names and assignments in the order they are given
+    let mut targets: Vec<String> = Vec::new();
+
+    for word in words {
+        if parse_assignment(word).is_some() {
+            targets.push(word.clone());
+            continue;
+        }
+
+        let fields = match expand::expand_word(word, shell) {
+            Ok(fields) => fields,
+            Err(msg) => {
+                eprintln!("fsh: {}", msg);
+                return 1;
+            }
+        };
+
+        for field in fields {
+            if field.len() > 1
+                && (field.starts_with('-') || field.starts_with('+'))
+                && targets.is_empty()
+            {
+                let enable = field.starts_with('-');
+                for flag in field.chars().skip(1) {
+                    match flag {
+                        'a' => indexed = enable,
+                        'A' => associative = enable,
+                        'x' => export = Some(enable),
+                        'p' => print = true,
+                        _ => {
+                            eprintln!("fsh: {}: -{}: invalid option", command, flag);
+                            return 2;
+                        }
+                    }
+                }
+            } else {
+                targets.push(field);
+            }
+        }
+    }
+
+    // Without names display the variables
+    if targets.is_empty() {
+        for (name, var) in shell.vars.sorted() {
+            if command != "export" || var.exported {
+                if let Err(err) = print_declaration(command, name, var) {
+                    return write_error(command, err);
+                }
+            }
+        }
+        return 0;
+    }
+
+    for target in targets {
+        let name = match parse_assignment(&target) {
+            Some(assignment) => assignment.name.to_string(),
+            None => target.clone(),
+        };
+
+        if !is_valid_name(&name) {
+            eprintln!("fsh: {}: `{}': not a valid identifier", command, target);
+            status = 1;
+            continue;
+        }
+
+        if print {
+            match shell.vars.get_var(&name) {
+                Some(var) => {
+                    if let Err(err) = print_declaration(command, &name, var) {
+                        return write_error(command, err);
+                    }
+                }
+                None => {
+                    eprintln!("fsh: {}: {}: not found", command, name);
+                    status = 1;
+                }
+            }
+            continue;
+        }
+
+        if associative {
+            if let Some(Value::Indexed(_)) = shell.vars.get_var(&name).map(|var| &var.value) {
+                eprintln!(
+                    "fsh: {}: {}: cannot convert indexed to associative array",
+                    command, name
+                );
+                status = 1;
+                continue;
+            }
+            shell.vars.declare_associative(&name);
+        } else if indexed {
+            shell.vars.declare_indexed(&name);
+        }
+
+        if let Some(assignment) = parse_assignment(&target) {
+            if let Err(msg) = assign(&assignment, shell) {
+                eprintln!("fsh: {}", msg);
+                status = 1;
+                continue;
+            }
+        }
+
+        match export {
+            Some(true) => shell.vars.export(&name, None),
+            Some(false) => shell.vars.unexport(&name),
+            None => {}
+        }
+    }
+
+    status
+}
+
+// Print a variable the way it is declared, so the output can be read back by the shell
+fn print_declaration(command: &str, name: &str, var: &Variable) -> io::Result<()> {
+    if command == "export" {
+        return write_line(&format!("export {}={}", name, var.quoted_value()));
+    }
+
+    let mut flags = String::new();
+    match var.value {
+        Value::Indexed(_) => flags.push('a'),
+        Value::Associative(_) => flags.push('A'),
+        Value::Scalar(_) => {}
+    }
+    if var.exported {
+        flags.push('x');
+    }
+    if flags.is_empty() {
+        flags.push('-');
+    }
+
+    write_line(&format!(
+        "declare -{} {}={}",
+        flags,
+        name,
+        var.quoted_value()
+    ))
+}
+
+// Usage: unset [-v] name... or unset name[subscript]... or unset -f name...
+fn unset(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let mut status = 0;
+
+    if args.first().map(|arg| arg.as_str()) == Some("-f") {
+        for name in &args[1..] {
+            shell.functions.remove(name);
+        }
+        return 0;
+    }
+
+    for arg in args.iter().filter(|arg| *arg != "-v") {
+        // Unset one element of an array
+        if let Some((name, subscript)) = arg.strip_suffix(']').and_then(|arg| arg.split_once('[')) {
+            if subscript == "@" || subscript == "*" {
+                shell.vars.unset(name);
+            } else if shell.vars.is_associative(name) {
+                shell.vars.unset_element(name, subscript);
+            } else {
+                match expand::element_index(name, subscript, shell) {
+                    Ok(Some(index)) => shell.vars.unset_element(name, &index.to_string()),
+                    Ok(None) => {
+                        eprintln!("fsh: unset: [{}]: bad array subscript", subscript);
+                        status = 1;
+                    }
+                    Err(msg) => {
+                        eprintln!("fsh: unset: {}", msg);
+                        status = 1;
+                    }
+                }
+            }
+            continue;
+        }
+
+        // Without -v a name that is not a variable can be a function
+        if shell.vars.get_var(arg).is_none() && args.first().map(|arg| arg.as_str()) != Some("-v") {
+            shell.functions.remove(arg);
+        }
+        shell.vars.unset(arg);
+    }
+
+    status
+}
+
+// Without arguments print all variables, otherwise set options and the positional parameters
+// -o without a name prints the options, +o prints them as the commands that set them
+// Usage: set [-o name] [+o name] [--] [arg...]
+fn set(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let mut args = args.as_slice();
+
+    while let Some(option) = args.first().filter(|arg| *arg == "-o" || *arg == "+o") {
+        let value = option == "-o";
+
+        match args.get(1) {
+            Some(name) => {
+                if let Err(msg) = shell.options.set(name, value) {
+                    eprintln!("fsh: set: {}", msg);
+                    return 1;
+                }
+                args = &args[2..];
+            }
+            None => {
+                for (name, enabled) in shell.options.set_options() {
+                    let written = if value {
+                        write_line(&format!(
+                            "{:<15}\t{}",
+                            name,
+                            if enabled { "on" } else { "off" }
+                        ))
+                    } else {
+                        write_line(&format!(
+                            "set {}o {}",
+                            if enabled { "-" } else { "+" },
+                            name
+                        ))
+                    };
+                    if let Err(err) = written {
+                        return write_error("set", err);
+                    }
+                }
+                return 0;
+            }
+        }
+
+        // Options alone do not change the positional parameters
+        if args.is_empty() {
+            return 0;
+        }
+    }
+
+    if args.is_empty() {
+        for (name, var) in shell.vars.sorted() {
+            let written = match &var.value {
+                Value::Scalar(value) => write_line(&format!("{}={}", name, value)),
+                _ => write_line(&format!("{}={}", name, var.quoted_value())),
+            };
+            if let Err(err) = written {
+                return write_error("set", err);
+            }
+        }
+        return 0;
+    }
+
+    let args = match args.first().map(|arg| arg.as_str()) {
+        Some("--") => &args[1..],
+        _ => args,
+    };
+    shell.vars.positional = args.to_vec();
+
+    0
+}
+
+// Leave the function being run with status `n`, or the status of the last command
+// Usage: return [n]
+fn return_(args: Vec<String>, shell: &mut Shell) -> i32 {
+    if shell.function_depth == 0 {
+        eprintln!("fsh: return: can only `return' from a function");
+        return 1;
+    }
+
+    let status = match args.first() {
+        Some(status) => match status.parse::<i32>() {
+            Ok(status) => status,
+            Err(_) => {
+                eprintln!("fsh: return: {}: numeric argument required", status);
+                2
+            }
+        },
+        None => shell.last_status,
+    };
+
+    // The lists of the function body stop at this command
+    shell.returning = true;
+
+    status
+}
+
+// Remove the first `n` positional parameters
+// Usage: shift [n]
+fn shift(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let count = match args.first().map(|count| count.parse::<usize>()) {
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            eprintln!("fsh: shift: {}: numeric argument required", args[0]);
+            return 1;
+        }
+        None => 1,
+    };
+
+    if count > shell.vars.positional.len() {
+        return 1;
+    }
+
+    shell.vars.positional.drain(..count);
+
+    0
+}
+
+// What a command name runs
+// fsh has no aliases, so unlike other shells type never reports one
+enum CommandKind {
+    Keyword,
+    Function,
+    Builtin,
+
+    // A program and whether its path is in the hash table
+    File(PathBuf, bool),
+}
+
+// What `name` runs, in the order they are looked for
+// With `all` every program named `name` in PATH is included, otherwise only the one that runs
+fn command_kinds(name: &str, all: bool, shell: &mut Shell) -> Vec<CommandKind> {
+    let mut kinds = Vec::new();
+
+    if KEYWORDS.contains(&name) {
+        kinds.push(CommandKind::Keyword);
+    }
+    if shell.functions.contains_key(name) {
+        kinds.push(CommandKind::Function);
+    }
+    if BUILTINS.contains(&name) {
+        kinds.push(CommandKind::Builtin);
+    }
+
+    let path = shell.vars.get("PATH").unwrap_or("").to_string();
+
+    if name.contains('/') {
+        let program = PathBuf::from(name);
+        if program.is_file() && hash::is_executable(&program) {
+            kinds.push(CommandKind::File(program, false));
+        }
+    } else if let (Some(program), false) = (shell.hash.get(name, &path), all) {
+        kinds.push(CommandKind::File(program.to_path_buf(), true));
+    } else {
+        let programs = hash::search_path(name, &path);
+        let count = if all { programs.len() } else { 1 };
+        kinds.extend(
+            programs
+                .into_iter()
+                .take(count)
+                .map(|program| CommandKind::File(program, false)),
+        );
+    }
+
+    if !all {
+        kinds.truncate(1);
+    }
+
+    kinds
+}
+
+// Describe how each name would be interpreted as a command
+// Usage: type [-a] [-t|-p|-P] name...
+fn type_(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let mut all = false;
+    let mut kind_only = false;
+    let mut path_only = false;
+    let mut force_path = false;
+    let mut status = 0;
+
+    let (options, names) = split_options(&args);
+    for option in options {
+        for flag in option.chars().skip(1) {
+            match flag {
+                'a' => all = true,
+                't' => kind_only = true,
+                'p' => path_only = true,
+                'P' => force_path = true,
+                _ => {
+                    eprintln!("fsh: type: -{}: invalid option", flag);
+                    return 2;
+                }
+            }
+        }
+    }
+
+    for name in names {
+        let mut kinds = command_kinds(name, all || force_path, shell);
+
+        // -P only looks for programs
+        if force_path {
+            kinds.retain(|kind| matches!(kind, CommandKind::File(..)));
+            if !all {
+                kinds.truncate(1);
+            }
+        }
+
+        if kinds.is_empty() {
+            if !kind_only && !path_only && !force_path {
+                eprintln!("fsh: type: {}: not found", name);
+            }
+            status = 1;
+            continue;
+        }
+
+        for kind in kinds {
+            let line = match (kind, kind_only, path_only || force_path) {
+                (CommandKind::Keyword, true, _) => String::from("keyword"),
+                (CommandKind::Function, true, _) => String::from("function"),
+                (CommandKind::Builtin, true, _) => String::from("builtin"),
+                (CommandKind::File(..), true, _) => String::from("file"),
+                (CommandKind::File(program, _), false, true) => program.display().to_string(),
+                (_, false, true) => continue,
+                (kind, false, false) => describe(name, &kind),
+            };
+            if let Err(err) = write_line(&line) {
+                return write_error("type", err);
+            }
+        }
+    }
+
+    status
+}
+
+// The description of `type` for `name` interpreted as `kind`
+fn describe(name: &str, kind: &CommandKind) -> String {
+    match kind {
+        CommandKind::Keyword => format!("{} is a shell keyword", name),
+        CommandKind::Function => format!("{} is a function", name),
+        CommandKind::Builtin => format!("{} is a shell builtin", name),
+        CommandKind::File(program, true) => format!("{} is hashed ({})", name, program.display()),
+        CommandKind::File(program, false) => format!("{} is {}", name, program.display()),
+    }
+}
+
+// Show what each name runs: the path of a program or what kind of command it is
+// Usage: which [-a] name...
+fn which(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let (options, names) = split_options(&args);
+    let all = options.iter().any(|option| option.as_str() == "-a");
+    let mut status = 0;
+
+    for name in names {
+        let kinds = command_kinds(name, all, shell);
+
+        if kinds.is_empty() {
+            eprintln!("fsh: which: {}: not found", name);
+            status = 1;
+        }
+
+        for kind in kinds {
+            let line = match kind {
+                CommandKind::Keyword => format!("{}: shell keyword", name),
+                CommandKind::Function => format!("{}: shell function", name),
+                CommandKind::Builtin => format!("{}: shell builtin", name),
+                CommandKind::File(program, _) => program.display().to_string(),
+            };
+            if let Err(err) = write_line(&line) {
+                return write_error("which", err);
+            }
+        }
+    }
+
+    status
+}
+
+// `command name [arg...]` is run by the shell itself, only its descriptions are a builtin
+// Usage: command -v|-V name...
+fn command_builtin(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let (options, names) = split_options(&args);
+    let mut status = 0;
+
+    let verbose = match options.first().map(|option| option.as_str()) {
+        Some("-v") => false,
+        Some("-V") => true,
+        Some(option) => {
+            eprintln!("fsh: command: {}: invalid option", option);
+            return 2;
+        }
+        None => return 0,
+    };
+
+    for name in names {
+        let line = match command_kinds(name, false, shell).pop() {
+            Some(kind) if verbose => describe(name, &kind),
+            Some(CommandKind::File(program, _)) => program.display().to_string(),
+            Some(_) => name.to_string(),
+            None => {
+                if verbose {
+                    eprintln!("fsh: command: {}: not found", name);
+                }
+                status = 1;
+                continue;
+            }
+        };
+        if let Err(err) = write_line(&line) {
+            return write_error("command", err);
+        }
+    }
+
+    status
+}
+
+// Display or change the table of the paths of programs
+// Usage: hash [-lr] [-p path] [-dt] [name...]
+fn hash(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let path = shell.vars.get("PATH").unwrap_or("").to_string();
+    let mut reusable_format = false;
+    let mut program = None;
+    let mut remove = false;
+    let mut show = false;
+    let mut reset = false;
+    let mut status = 0;
+
+    let mut args = args.into_iter();
+    let mut names = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-r" => {
+                shell.hash.clear();
+                reset = true;
+            }
+            "-l" => reusable_format = true,
+            "-d" => remove = true,
+            "-t" => show = true,
+            "-p" => match args.next() {
+                Some(path) => program = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("fsh: hash: -p: option requires an argument");
+                    return 2;
+                }
+            },
+            option if option.starts_with('-') && option.len() > 1 => {
+                eprintln!("fsh: hash: {}: invalid option", option);
+                return 2;
+            }
+            _ => names.push(arg),
+        }
+    }
+
+    if names.is_empty() {
+        if program.is_some() || remove || show {
+            eprintln!("fsh: hash: name argument required");
+            return 1;
+        }
+
+        // hash -r alone only empties the table
+        if reset {
+            return 0;
+        }
+
+        if let Err(err) = print_hash_table(shell.hash.entries(&path), reusable_format) {
+            return write_error("hash", err);
+        }
+        return 0;
+    }
+
+    for name in &names {
+        if let Some(program) = &program {
+            shell.hash.insert(name, program.clone(), &path);
+        } else if remove {
+            if !shell.hash.remove(name) {
+                eprintln!("fsh: hash: {}: not found", name);
+                status = 1;
+            }
+        } else if show {
+            let written = match shell.hash.get(name, &path) {
+                Some(program) if names.len() > 1 => {
+                    write_line(&format!("{}\t{}", name, program.display()))
+                }
+                Some(program) => write_line(&program.display().to_string()),
+                None => {
+                    eprintln!("fsh: hash: {}: not found", name);
+                    status = 1;
+                    continue;
+                }
+            };
+            if let Err(err) = written {
+                return write_error("hash", err);
+            }
+        } else if !BUILTINS.contains(&name.as_str()) {
+            match hash::search_path(name, &path).into_iter().next() {
+                Some(program) => shell.hash.insert(name, program, &path),
+                None => {
+                    eprintln!("fsh: hash: {}: not found", name);
+                    status = 1;
+                }
+            }
+        }
+    }
+
+    status
+}
+
+// Print the remembered programs with their number of hits, or as hash commands that remember them
+fn print_hash_table(entries: Vec<(&str, &Path, usize)>, reusable_format: bool) -> io::Result<()> {
+    if entries.is_empty() {
+        if !reusable_format {
+            write_line("hash: hash table empty")?;
+        }
+        return Ok(());
+    }
+
+    if !reusable_format {
+        write_line("hits\tcommand")?;
+    }
+    for (name, program, hits) in entries {
+        if reusable_format {
+            write_line(&format!("builtin hash -p {} {}", program.display(), name))?;
+        } else {
+            write_line(&format!("{:>4}\t{}", hits, program.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Display or change the key bindings of the line editor
+// Usage: bind [-lp] [-r keys] [-x keys command] [keys function]
+// A binding can also be written like in bash and inputrc: bind '"\C-x\C-e": function'
+fn bind(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let mut status = 0;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-l" => {
+                for name in keymap::function_names() {
+                    if let Err(err) = write_line(name) {
+                        return write_error("bind", err);
+                    }
+                }
+            }
+            // Print the bindings as the bind commands that make them
+            "-p" => {
+                for (keys, binding) in shell.keymap.bindings() {
+                    let keys = quote(&keymap::format_keys(keys));
+                    let written = match binding {
+                        Binding::Function(function) => write_line(&format!(
+                            "bind {} {}",
+                            keys,
+                            keymap::function_name(*function)
+                        )),
+                        Binding::Command(command) => {
+                            write_line(&format!("bind -x {} {}", keys, quote(command)))
+                        }
+                    };
+                    if let Err(err) = written {
+                        return write_error("bind", err);
+                    }
+                }
+            }
+            "-r" => {
+                let keys = match args.next() {
+                    Some(keys) => keys,
+                    None => {
+                        eprintln!("fsh: bind: -r: option requires an argument");
+                        return 2;
+                    }
+                };
+
+                match keymap::parse_keys(&keys) {
+                    Ok(keys) => {
+                        shell.keymap.unbind(&keys);
+                    }
+                    Err(msg) => {
+                        eprintln!("fsh: bind: {}", msg);
+                        status = 1;
+                    }
+                }
+            }
+            "-x" => {
+                let binding = match args.next() {
+                    Some(arg) => match split_binding(&arg) {
+                        // The command can be in double quotes too
+                        Some((keys, command)) => {
+                            let command = command
+                                .strip_prefix('"')
+                                .and_then(|command| command.strip_suffix('"'))
+                                .unwrap_or(command);
+                            Some((keys.to_string(), command.to_string()))
+                        }
+                        None => args.next().map(|command| (arg, command)),
+                    },
+                    None => None,
+                };
+                let (keys, command) = match binding {
+                    Some(binding) => binding,
+                    None => {
+                        eprintln!("fsh: bind: -x: option requires a key sequence and a command");
+                        return 2;
+                    }
+                };
+
+                match keymap::parse_keys(&keys) {
+                    Ok(keys) => shell.keymap.bind(keys, Binding::Command(command)),
+                    Err(msg) => {
+                        eprintln!("fsh: bind: {}", msg);
+                        status = 1;
+                    }
+                }
+            }
+            option if option.starts_with('-') && option.len() > 1 => {
+                eprintln!("fsh: bind: {}: invalid option", option);
+                return 2;
+            }
+            _ => {
+                let (keys, name) = match split_binding(&arg) {
+                    Some((keys, name)) => (keys.to_string(), name.to_string()),
+                    None => match args.next() {
+                        Some(name) => (arg, name),
+                        None => {
+                            eprintln!("fsh: bind: {}: function name required", arg);
+                            return 2;
+                        }
+                    },
+                };
+
+                let function = match keymap::function(&name) {
+                    Some(function) => function,
+                    None => {
+                        eprintln!("fsh: bind: {}: unknown function name", name);
+                        status = 1;
+                        continue;
+                    }
+                };
+
+                match keymap::parse_keys(&keys) {
+                    Ok(keys) => shell.keymap.bind(keys, Binding::Function(function)),
+                    Err(msg) => {
+                        eprintln!("fsh: bind: {}", msg);
+                        status = 1;
+                    }
+                }
+            }
+        }
+    }
+
+    status
+}
+
+// Split a binding written like "\C-x\C-e": function into the keys and what they are bound to
+fn split_binding(binding: &str) -> Option<(&str, &str)> {
+    let rest = binding.strip_prefix('"')?;
+
+    // The keys end at the first double quote that is not escaped
+    let mut escaped = false;
+    let (end, _) = rest.char_indices().find(|&(_, c)| {
+        let found = !escaped && c == '"';
+        escaped = !escaped && c == '\\';
+        found
+    })?;
+
+    let value = rest[end + 1..].trim_start().strip_prefix(':')?.trim();
+    Some((&rest[..end], value))
+}
+
+// Split leading options like -a from the operands that follow them, -- ends the options
+fn split_options(args: &[String]) -> (&[String], &[String]) {
+    let count = args
+        .iter()
+        .take_while(|arg| arg.len() > 1 && arg.starts_with('-') && *arg != "--")
+        .count();
+
+    match args.get(count).map(|arg| arg.as_str()) {
+        Some("--") => (&args[..count], &args[count + 1..]),
+        _ => (&args[..count], &args[count..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keymap::{self, Binding, Lookup};
+    use crate::process::{capture_output, run_line};
+    use crate::shell::Shell;
+    use crate::testing::{shell, TempDir};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn hash_table() {
+        let dir = TempDir::new("builtins-hash-test", &["prog"]);
+        let program = dir.path().join("prog");
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut shell = shell();
+        shell.vars.set("PATH", dir.path().display().to_string());
+
+        assert_eq!(run_line("hash prog", &mut shell), 0);
+        assert_eq!(
+            capture_output("hash -t prog", &mut shell),
+            format!("{}\n", program.display())
+        );
+        assert_eq!(
+            capture_output("hash -l", &mut shell),
+            format!("builtin hash -p {} prog\n", program.display())
+        );
+
+        // hash -r forgets every program
+        assert_eq!(run_line("hash -r", &mut shell), 0);
+        assert_eq!(run_line("hash -t prog", &mut shell), 1);
+        assert_eq!(
+            capture_output("hash", &mut shell),
+            "hash: hash table empty\n"
+        );
+
+        // Changing PATH forgets them too
+        assert_eq!(run_line("hash prog", &mut shell), 0);
+        assert_eq!(run_line("PATH=/nonexistent; hash -t prog", &mut shell), 1);
+        assert_eq!(run_line("hash missing", &mut shell), 1);
+    }
+
+    #[test]
+    fn type_of_commands() {
+        let mut shell = shell();
+        shell.vars.set("PATH", String::from("/nonexistent"));
+        assert_eq!(run_line("f() { true; }", &mut shell), 0);
+
+        assert_eq!(capture_output("type f", &mut shell), "f is a function\n");
+        assert_eq!(
+            capture_output("type -t f cd { missing", &mut shell),
+            "function\nbuiltin\nkeyword\n"
+        );
+        assert_eq!(shell.substitution_status, Some(1));
+        assert_eq!(
+            capture_output("type case", &mut shell),
+            "case is a shell keyword\n"
+        );
+
+        // A function is found before the builtin of the same name
+        assert_eq!(run_line("cd() { true; }", &mut shell), 0);
+        assert_eq!(
+            capture_output("type -a cd", &mut shell),
+            "cd is a function\ncd is a shell builtin\n"
+        );
+    }
+
+    #[test]
+    fn bind_keys() {
+        let mut shell = shell();
+        let bound = |shell: &Shell, keys: &str| match shell
+            .keymap
+            .lookup(&keymap::parse_keys(keys).unwrap())
+        {
+            Lookup::Bound(Binding::Function(function)) => {
+                keymap::function_name(*function).to_string()
+            }
+            Lookup::Bound(Binding::Command(command)) => format!("-x {}", command),
+            _ => String::new(),
+        };
+
+        assert_eq!(run_line(r"bind '\C-o' kill-line", &mut shell), 0);
+        assert_eq!(bound(&shell, r"\C-o"), "kill-line");
+        assert_eq!(run_line(r"bind -x '\C-xl' 'ls -l'", &mut shell), 0);
+        assert_eq!(bound(&shell, r"\C-xl"), "-x ls -l");
+
+        // Like in bash and inputrc
+        assert_eq!(run_line(r#"bind '"\C-o": undo'"#, &mut shell), 0);
+        assert_eq!(bound(&shell, r"\C-o"), "undo");
+        assert_eq!(run_line(r#"bind '"\"\e":yank'"#, &mut shell), 0);
+        assert_eq!(bound(&shell, r#""\e"#), "yank");
+        assert_eq!(run_line(r#"bind -x '"\C-xl": "ls -a"'"#, &mut shell), 0);
+        assert_eq!(bound(&shell, r"\C-xl"), "-x ls -a");
+        assert_eq!(run_line(r#"bind -x '"\C-xm": pwd'"#, &mut shell), 0);
+        assert_eq!(bound(&shell, r"\C-xm"), "-x pwd");
+
+        assert_eq!(run_line(r"bind -r '\C-o'", &mut shell), 0);
+        assert_eq!(bound(&shell, r"\C-o"), "");
+
+        assert_eq!(run_line(r#"bind '"\C-o": missing'"#, &mut shell), 1);
+        assert_eq!(run_line(r#"bind '"\C-o"'"#, &mut shell), 2);
+        assert_eq!(run_line("bind -x '\\C-o'", &mut shell), 2);
+    }
+}
@@ -0,0 +1,555 @@
+use crate::arith;
+use crate::expand;
+use crate::pattern::Pattern;
+use crate::shell::Shell;
+use regex::Regex;
+use std::ffi::CString;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+// Conditional expressions of the `test` and `[` builtins and of the `[[ ... ]]` command
+// `test` works on expanded arguments and combines expressions with -a, -o, ! and parentheses
+// `[[ ... ]]` expands its operands itself without field splitting and filename expansion,
+// combines expressions with &&, || and !, matches patterns with == and != and regular
+// expressions with =~ whose matched groups are stored in the BASH_REMATCH array
+
+const UNARY_OPERATORS: [&str; 25] = [
+    "-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-N", "-O", "-G", "-p", "-r", "-s",
+    "-S", "-t", "-u", "-w", "-x", "-z", "-n", "-v", "-o",
+];
+
+const BINARY_OPERATORS: [&str; 15] = [
+    "=", "==", "!=", "<", ">", "=~", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+enum Expression {
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Unary(String, String),
+    Binary(String, String, String),
+
+    // A single operand is true if it is not empty
+    Operand(String),
+}
+
+// Run the `test` or `[` builtin on expanded `args`
+// Returns 0 if the expression is true, 1 if it is false and 2 if it is invalid
+pub fn test(command: &str, args: &[String], shell: &mut Shell) -> i32 {
+    let mut args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    if command == "[" {
+        if args.last() != Some(&"]") {
+            eprintln!("fsh: [: missing `]'");
+            return 2;
+        }
+        args.pop();
+    }
+
+    let expression = match parse_test(&args) {
+        Ok(expression) => expression,
+        Err(msg) => {
+            eprintln!("fsh: {}: {}", command, msg);
+            return 2;
+        }
+    };
+
+    let mut evaluator = Evaluator {
+        shell,
+        extended: false,
+    };
+    match evaluator.evaluate(&expression) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(msg) => {
+            eprintln!("fsh: {}: {}", command, msg);
+            2
+        }
+    }
+}
+
+// Run `[[ ... ]]`, `words` are the words after [[ as typed, including the closing ]]
+pub fn extended_test(words: &[String], shell: &mut Shell) -> i32 {
+    let mut words: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+
+    if words.last() != Some(&"]]") {
+        eprintln!("fsh: syntax error: expected `]]'");
+        return 2;
+    }
+    words.pop();
+
+    let mut parser = Parser {
+        tokens: &words,
+        pos: 0,
+        extended: true,
+    };
+
+    let expression = match parser.parse() {
+        Ok(expression) => expression,
+        Err(msg) => {
+            eprintln!("fsh: syntax error in conditional expression: {}", msg);
+            return 2;
+        }
+    };
+
+    let mut evaluator = Evaluator {
+        shell,
+        extended: true,
+    };
+    match evaluator.evaluate(&expression) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(msg) => {
+            eprintln!("fsh: {}", msg);
+            2
+        }
+    }
+}
+
+// `test` decides how to read its arguments from their number, as specified by POSIX
+// so that for example `test -f` tests whether the string "-f" is not empty
+fn parse_test(args: &[&str]) -> Result<Expression, String> {
+    let operand = |arg: &str| Expression::Operand(arg.to_string());
+    let not = |expression: Expression| Expression::Not(Box::new(expression));
+
+    match args.len() {
+        0 => Ok(Expression::Operand(String::new())),
+        1 => Ok(operand(args[0])),
+        2 if args[0] == "!" => Ok(not(operand(args[1]))),
+        2 if UNARY_OPERATORS.contains(&args[0]) => {
+            Ok(Expression::Unary(args[0].to_string(), args[1].to_string()))
+        }
+        2 => Err(format!("{}: unary operator expected", args[0])),
+        3 if BINARY_OPERATORS.contains(&args[1]) && args[1] != "=~" => Ok(Expression::Binary(
+            args[0].to_string(),
+            args[1].to_string(),
+            args[2].to_string(),
+        )),
+        3 if args[0] == "!" => Ok(not(parse_test(&args[1..])?)),
+        3 if args[0] == "(" && args[2] == ")" => Ok(operand(args[1])),
+        4 if args[0] == "!" => Ok(not(parse_test(&args[1..])?)),
+        4 if args[0] == "(" && args[3] == ")" => parse_test(&args[1..3]),
+        _ => {
+            let mut parser = Parser {
+                tokens: args,
+                pos: 0,
+                extended: false,
+            };
+            parser.parse()
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [&'a str],
+    pos: usize,
+
+    // Whether this is a [[ ... ]] expression
+    extended: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(&mut self) -> Result<Expression, String> {
+        let expression = self.or()?;
+
+        match self.tokens.get(self.pos) {
+            Some(token) => Err(format!("{}: unexpected argument", token)),
+            None => Ok(expression),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let token = self
+            .peek()
+            .ok_or_else(|| String::from("argument expected"))?;
+        self.pos += 1;
+
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Expression, String> {
+        let or = if self.extended { "||" } else { "-o" };
+        let mut left = self.and()?;
+
+        while self.peek() == Some(or) {
+            self.pos += 1;
+            let right = self.and()?;
+            left = Expression::Or(Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let and = if self.extended { "&&" } else { "-a" };
+        let mut left = self.not()?;
+
+        while self.peek() == Some(and) {
+            self.pos += 1;
+            let right = self.not()?;
+            left = Expression::And(Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expression, String> {
+        if self.peek() == Some("!") && self.tokens.len() > self.pos + 1 {
+            self.pos += 1;
+            return Ok(Expression::Not(Box::new(self.not()?)));
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        let token = self.next()?;
+
+        if token == "(" {
+            let expression = self.or()?;
+            if self.next()? != ")" {
+                return Err(String::from("`)' expected"));
+            }
+            return Ok(expression);
+        }
+
+        // A binary operator after the token makes it the left operand
+        if let Some(operator) = self.peek() {
+            let is_binary =
+                BINARY_OPERATORS.contains(&operator) && (self.extended || operator != "=~");
+
+            if is_binary && self.tokens.len() > self.pos + 1 {
+                self.pos += 1;
+                let right = self.next()?;
+                return Ok(Expression::Binary(
+                    token.to_string(),
+                    operator.to_string(),
+                    right.to_string(),
+                ));
+            }
+        }
+
+        if UNARY_OPERATORS.contains(&token) && self.peek().is_some() {
+            let operand = self.next()?;
+            return Ok(Expression::Unary(token.to_string(), operand.to_string()));
+        }
+
+        Ok(Expression::Operand(token.to_string()))
+    }
+}
+
+struct Evaluator<'a> {
+    shell: &'a mut Shell,
+    extended: bool,
+}
+
+impl<'a> Evaluator<'a> {
+    fn evaluate(&mut self, expression: &Expression) -> Result<bool, String> {
+        match expression {
+            Expression::Not(expression) => Ok(!self.evaluate(expression)?),
+            Expression::And(left, right) => Ok(self.evaluate(left)? && self.evaluate(right)?),
+            Expression::Or(left, right) => Ok(self.evaluate(left)? || self.evaluate(right)?),
+            Expression::Operand(operand) => Ok(!self.operand(operand)?.is_empty()),
+            Expression::Unary(operator, operand) => {
+                let operand = self.operand(operand)?;
+                self.unary(operator, &operand)
+            }
+            Expression::Binary(left, operator, right) => {
+                let left = self.operand(left)?;
+                self.binary(&left, operator, right)
+            }
+        }
+    }
+
+    // Operands of [[ ... ]] are expanded when they are evaluated
+    fn operand(&mut self, operand: &str) -> Result<String, String> {
+        if self.extended {
+            expand::expand_string(operand, self.shell)
+        } else {
+            Ok(operand.to_string())
+        }
+    }
+
+    fn unary(&mut self, operator: &str, operand: &str) -> Result<bool, String> {
+        let metadata = || fs::metadata(operand).ok();
+        let file_type =
+            |check: fn(&Metadata) -> bool| metadata().is_some_and(|metadata| check(&metadata));
+
+        let result = match operator {
+            "-a" | "-e" => metadata().is_some(),
+            "-f" => file_type(|metadata| metadata.is_file()),
+            "-d" => file_type(|metadata| metadata.is_dir()),
+            "-b" => file_type(|metadata| metadata.file_type().is_block_device()),
+            "-c" => file_type(|metadata| metadata.file_type().is_char_device()),
+            "-p" => file_type(|metadata| metadata.file_type().is_fifo()),
+            "-S" => file_type(|metadata| metadata.file_type().is_socket()),
+            "-h" | "-L" => fs::symlink_metadata(operand)
+                .is_ok_and(|metadata| metadata.file_type().is_symlink()),
+            "-s" => file_type(|metadata| metadata.len() > 0),
+            "-g" => file_type(|metadata| metadata.mode() & libc::S_ISGID != 0),
+            "-u" => file_type(|metadata| metadata.mode() & libc::S_ISUID != 0),
+            "-k" => file_type(|metadata| metadata.mode() & libc::S_ISVTX != 0),
+            "-O" => file_type(|metadata| metadata.uid() == unsafe { libc::geteuid() }),
+            "-G" => file_type(|metadata| metadata.gid() == unsafe { libc::getegid() }),
+            "-N" => file_type(|metadata| metadata.mtime() > metadata.atime()),
+            "-r" => has_access(operand, libc::R_OK),
+            "-w" => has_access(operand, libc::W_OK),
+            "-x" => has_access(operand, libc::X_OK),
+            "-t" => match operand.trim().parse::<i32>() {
+                Ok(fd) => unsafe { libc::isatty(fd) == 1 },
+                Err(_) => return Err(format!("{}: integer expression expected", operand)),
+            },
+            "-z" => operand.is_empty(),
+            "-n" => !operand.is_empty(),
+            "-v" => self.is_variable_set(operand),
+            "-o" => self.shell.options.is_set(operand),
+            _ => return Err(format!("{}: unary operator expected", operator)),
+        };
+
+        Ok(result)
+    }
+
+    // `right` is not expanded yet because its quoted parts must match literally for == and =~
+    fn binary(&mut self, left: &str, operator: &str, right: &str) -> Result<bool, String> {
+        if self.extended {
+            match operator {
+                "=" | "==" | "!=" => {
+                    let pattern = Pattern::new(&expand::expand_pattern(right, self.shell)?, true);
+                    return Ok(pattern.matches(left) == (operator != "!="));
+                }
+                "=~" => return self.regex_match(left, right),
+                _ => {}
+            }
+        }
+
+        let right = self.operand(right)?;
+
+        let result = match operator {
+            "=" | "==" => left == right,
+            "!=" => left != right,
+            "<" => left < right.as_str(),
+            ">" => left > right.as_str(),
+            "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+                let left = self.integer(left)?;
+                let right = self.integer(&right)?;
+
+                match operator {
+                    "-eq" => left == right,
+                    "-ne" => left != right,
+                    "-lt" => left < right,
+                    "-le" => left <= right,
+                    "-gt" => left > right,
+                    _ => left >= right,
+                }
+            }
+            "-nt" | "-ot" => {
+                let modified = |path: &str| {
+                    fs::metadata(path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                };
+
+                match (modified(left), modified(&right)) {
+                    (Some(left), Some(right)) if operator == "-nt" => left > right,
+                    (Some(left), Some(right)) => left < right,
+                    (Some(_), None) => operator == "-nt",
+                    (None, Some(_)) => operator == "-ot",
+                    (None, None) => false,
+                }
+            }
+            "-ef" => match (fs::metadata(left), fs::metadata(&right)) {
+                (Ok(left), Ok(right)) => left.dev() == right.dev() && left.ino() == right.ino(),
+                _ => false,
+            },
+            _ => return Err(format!("{}: binary operator expected", operator)),
+        };
+
+        Ok(result)
+    }
+
+    // Integer operands of [[ ... ]] are arithmetic expressions
+    fn integer(&mut self, operand: &str) -> Result<i64, String> {
+        if self.extended {
+            return arith::evaluate(operand, &mut self.shell.vars);
+        }
+
+        operand
+            .trim()
+            .parse()
+            .map_err(|_| format!("{}: integer expression expected", operand))
+    }
+
+    // Match `left` against the regular expression `right` and store the matched groups in BASH_REMATCH
+    fn regex_match(&mut self, left: &str, right: &str) -> Result<bool, String> {
+        let expression = expand::expand_regex(right, self.shell)?;
+        let regex = Regex::new(&expression)
+            .map_err(|_| format!("{}: invalid regular expression", expression))?;
+
+        self.shell.vars.clear_array("BASH_REMATCH");
+
+        match regex.captures(left) {
+            Some(captures) => {
+                let groups = captures
+                    .iter()
+                    .map(|group| group.map_or(String::new(), |group| group.as_str().to_string()))
+                    .collect();
+                self.shell.vars.append_indexed("BASH_REMATCH", groups);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // Whether variable `name` or array element name[subscript] is set
+    fn is_variable_set(&mut self, name: &str) -> bool {
+        let (name, subscript) = match name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+            Some((name, subscript)) => (name, Some(subscript)),
+            None => (name, None),
+        };
+
+        match subscript {
+            None => self.shell.vars.get_var(name).is_some(),
+            Some("@") | Some("*") => !self.shell.vars.items(name).is_empty(),
+            Some(key) if self.shell.vars.is_associative(name) => {
+                self.shell.vars.get_associative(name, key).is_some()
+            }
+            Some(subscript) => match expand::element_index(name, subscript, self.shell) {
+                Ok(Some(index)) => self.shell.vars.get_indexed(name, index).is_some(),
+                _ => false,
+            },
+        }
+    }
+}
+
+// Whether the current user can access `path` with `mode`
+fn has_access(path: &str, mode: libc::c_int) -> bool {
+    match CString::new(path) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::process::run_line;
+    use crate::testing::{shell, TempDir};
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    // Exit status of `line` in a new shell
+    fn status(line: &str) -> i32 {
+        run_line(line, &mut shell())
+    }
+
+    #[test]
+    fn regex_match() {
+        let mut shell = shell();
+        assert_eq!(run_line("[[ ab12 =~ ^([a-z]+)([0-9]+)$ ]]", &mut shell), 0);
+        assert_eq!(shell.vars.items("BASH_REMATCH"), ["ab12", "ab", "12"]);
+
+        assert_eq!(run_line("[[ ab =~ ^[0-9]+$ ]]", &mut shell), 1);
+        assert!(shell.vars.items("BASH_REMATCH").is_empty());
+
+        // Quoted parts match literally
+        assert_eq!(status("[[ a.c =~ \"a.c\" ]]"), 0);
+        assert_eq!(status("[[ abc =~ \"a.c\" ]]"), 1);
+        assert_eq!(status("[[ abc =~ a.c ]]"), 0);
+        assert_eq!(status("[[ a =~ ( ]]"), 2);
+    }
+
+    #[test]
+    fn pattern_match() {
+        assert_eq!(status("[[ main.rs == *.rs ]]"), 0);
+        assert_eq!(status("[[ main.rs = m???.rs ]]"), 0);
+        assert_eq!(status("[[ main.rs != *.rs ]]"), 1);
+        assert_eq!(status("[[ main.rs == \"*.rs\" ]]"), 1);
+        assert_eq!(status("[[ '*.rs' == \"*.rs\" ]]"), 0);
+        assert_eq!(status("[[ bar == @(foo|bar) ]]"), 0);
+        assert_eq!(status("[[ baz == !(foo|bar) ]]"), 0);
+
+        // The left operand is not a pattern
+        assert_eq!(status("[[ *.rs == main.rs ]]"), 1);
+
+        // test compares strings
+        assert_eq!(status("test main.rs = *.rs"), 1);
+        assert_eq!(status("[ '*.rs' = '*.rs' ]"), 0);
+    }
+
+    #[test]
+    fn file_times() {
+        let dir = TempDir::new("conditional-test", &["old", "new"]);
+        let now = SystemTime::now();
+        for (file, time) in [("old", now - Duration::from_secs(60)), ("new", now)].iter() {
+            let file = File::options()
+                .write(true)
+                .open(dir.path().join(file))
+                .unwrap();
+            file.set_modified(*time).unwrap();
+        }
+
+        let mut shell = shell();
+        shell.vars.set("dir", dir.path().display().to_string());
+        let mut status = |line: &str| run_line(line, &mut shell);
+
+        assert_eq!(status("[[ $dir/new -nt $dir/old ]]"), 0);
+        assert_eq!(status("[[ $dir/old -nt $dir/new ]]"), 1);
+        assert_eq!(status("[[ $dir/old -ot $dir/new ]]"), 0);
+        assert_eq!(status("test $dir/new -ot $dir/old"), 1);
+
+        // An existing file is newer than a missing one
+        assert_eq!(status("[[ $dir/old -nt $dir/missing ]]"), 0);
+        assert_eq!(status("[[ $dir/missing -ot $dir/old ]]"), 0);
+        assert_eq!(status("[[ $dir/missing -nt $dir/missing ]]"), 1);
+    }
+
+    #[test]
+    fn precedence() {
+        // && binds tighter than ||, ! applies to the next term only
+        assert_eq!(status("[[ -n a || -z a && -z a ]]"), 0);
+        assert_eq!(status("[[ ( -n a || -z a ) && -z a ]]"), 1);
+        assert_eq!(status("[[ ! -n a || -n a ]]"), 0);
+        assert_eq!(status("[[ ! ( -n a || -n a ) ]]"), 1);
+        assert_eq!(status("[[ ! ! -n a ]]"), 0);
+        assert_eq!(status("[ a -o '' -a '' ]"), 0);
+        assert_eq!(status("[ ! '' -a a ]"), 0);
+
+        // && and || short circuit
+        let mut shell = shell();
+        assert_eq!(run_line("[[ -z a && $((x = 1)) ]]", &mut shell), 1);
+        assert_eq!(shell.vars.get("x"), None);
+    }
+
+    #[test]
+    fn shell_options() {
+        let mut shell = shell();
+        assert_eq!(run_line("[[ -o emacs ]]", &mut shell), 0);
+        assert_eq!(run_line("[[ -o vi ]]", &mut shell), 1);
+
+        // shopt options are not set -o options
+        assert_eq!(
+            run_line("shopt -s extglob; [[ -o extglob ]]", &mut shell),
+            1
+        );
+
+        assert_eq!(run_line("set -o vi; test -o vi", &mut shell), 0);
+        assert_eq!(run_line("[ -o emacs ]", &mut shell), 1);
+    }
+
+    #[test]
+    fn number_of_arguments() {
+        // test decides how to read its arguments from their number
+        assert_eq!(status("test"), 1);
+        assert_eq!(status("test -f"), 0);
+        assert_eq!(status("test ! -f"), 1);
+        assert_eq!(status("test -z ''"), 0);
+        assert_eq!(status("test '(' -n ')'"), 0);
+        assert_eq!(status("[ 1 -lt 2 ]"), 0);
+        assert_eq!(status("[ a -lt 2 ]"), 2);
+        assert_eq!(status("[ a"), 2);
+        assert_eq!(status("[[ 1+1 -eq 2 ]]"), 0);
+    }
+}
@@ -0,0 +1,585 @@
+use crate::builtins::{write_error, write_line};
+use crate::process::error_message;
+use crate::shell::Shell;
+use crate::suggest;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+// Builtins that change the current directory or show it: cd, pwd and the directory stack
+// The directory stack is the current directory followed by the directories saved by pushd,
+// its entries are counted from 0 at the left with +N or from 0 at the right with -N
+
+// Print the current directory, by default the logical one that keeps the symbolic links it was
+// reached through, with -P the physical one where they are resolved
+// Usage: pwd [-L|-P]
+pub fn pwd(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let mut physical = false;
+
+    for arg in args {
+        match arg.as_str() {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            _ => {
+                eprintln!("fsh: pwd: {}: invalid option", arg);
+                return 2;
+            }
+        }
+    }
+
+    let pwd = match shell.vars.get("PWD") {
+        Some(pwd) if !physical && is_current_directory(pwd) => pwd.to_string(),
+        _ => match env::current_dir() {
+            Ok(path) => path.display().to_string(),
+            Err(err) => {
+                eprintln!("fsh: pwd: {}", error_message(&err));
+                return 1;
+            }
+        },
+    };
+
+    if let Err(err) = write_line(&pwd) {
+        return write_error("pwd", err);
+    }
+
+    0
+}
+
+// Usage: cd [-L|-P] [dir|-]
+pub fn cd(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let mut physical = false;
+    let mut args = args.as_slice();
+
+    while let Some(option) = args.first() {
+        match option.as_str() {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            "--" => {
+                args = &args[1..];
+                break;
+            }
+            _ => break,
+        }
+        args = &args[1..];
+    }
+
+    let target = match args.first().map(|arg| arg.as_str()) {
+        // cd - goes back to the previous directory and prints it
+        Some("-") => match shell.vars.get("OLDPWD") {
+            Some(old_pwd) => {
+                let old_pwd = old_pwd.to_string();
+                if let Err(msg) = change_directory_to(&old_pwd, physical, shell) {
+                    eprintln!("fsh: cd: {}", msg);
+                    return 1;
+                }
+                if let Err(err) = write_line(&current_directory(shell)) {
+                    return write_error("cd", err);
+                }
+                return 0;
+            }
+            None => {
+                eprintln!("fsh: cd: OLDPWD not set");
+                return 1;
+            }
+        },
+        Some(dir) => dir.to_string(),
+        None => match shell.vars.get("HOME").or(shell.user.home.as_deref()) {
+            Some(home) => home.to_string(),
+            None => {
+                eprintln!("fsh: cd: HOME not set");
+                return 1;
+            }
+        },
+    };
+
+    // A directory found with CDPATH is printed since it is not where the user may expect
+    if let Some(dir) = search_cdpath(&target, shell) {
+        if let Err(msg) = change_directory_to(&dir, physical, shell) {
+            eprintln!("fsh: cd: {}", msg);
+            return 1;
+        }
+        if let Err(err) = write_line(&current_directory(shell)) {
+            return write_error("cd", err);
+        }
+        return 0;
+    }
+
+    if let Err(msg) = change_directory_to(&target, physical, shell) {
+        // With cdspell a misspelled directory is corrected and the correction printed
+        if shell.options.shopt("cdspell") {
+            if let Some(corrected) = correct_spelling(&target) {
+                if change_directory_to(&corrected, physical, shell).is_ok() {
+                    if let Err(err) = write_line(&corrected) {
+                        return write_error("cd", err);
+                    }
+                    return 0;
+                }
+            }
+        }
+
+        eprintln!("fsh: cd: {}", msg);
+        return 1;
+    }
+
+    0
+}
+
+// Usage: pushd [-n] [dir|+N|-N]
+pub fn pushd(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let no_cd = args.iter().any(|arg| arg == "-n");
+    let arg = args.into_iter().find(|arg| arg != "-n");
+
+    match arg {
+        // Exchange the top two directories
+        None => {
+            if shell.dir_stack.is_empty() {
+                eprintln!("fsh: pushd: no other directory");
+                return 1;
+            }
+
+            let current = current_directory(shell);
+            if !no_cd {
+                let top = shell.dir_stack[0].clone();
+                if let Err(msg) = change_directory(&top, shell) {
+                    eprintln!("fsh: pushd: {}", msg);
+                    return 1;
+                }
+                shell.dir_stack[0] = current;
+            }
+        }
+        // Rotate the stack so that its Nth directory is on top
+        Some(arg) if is_stack_index(&arg) => {
+            let mut stack = full_stack(shell);
+            let index = match stack_index(&arg, stack.len()) {
+                Some(index) => index,
+                None => {
+                    eprintln!("fsh: pushd: {}: directory stack index out of range", arg);
+                    return 1;
+                }
+            };
+
+            stack.rotate_left(index);
+            if !no_cd {
+                if let Err(msg) = change_directory(&stack[0], shell) {
+                    eprintln!("fsh: pushd: {}", msg);
+                    return 1;
+                }
+            }
+            shell.dir_stack = stack.split_off(1);
+        }
+        // Save the current directory and change to `dir`, with -n only add `dir` to the stack
+        Some(dir) => {
+            if no_cd {
+                shell.dir_stack.insert(0, dir);
+            } else {
+                let current = current_directory(shell);
+                if let Err(msg) = change_directory(&dir, shell) {
+                    eprintln!("fsh: pushd: {}", msg);
+                    return 1;
+                }
+                shell.dir_stack.insert(0, current);
+            }
+        }
+    }
+
+    if let Err(err) = print_stack(shell, false, false, false) {
+        return write_error("pushd", err);
+    }
+
+    0
+}
+
+// Usage: popd [-n] [+N|-N]
+pub fn popd(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let no_cd = args.iter().any(|arg| arg == "-n");
+    let arg = args.into_iter().find(|arg| arg != "-n");
+
+    if shell.dir_stack.is_empty() {
+        eprintln!("fsh: popd: directory stack empty");
+        return 1;
+    }
+
+    let stack_len = shell.dir_stack.len() + 1;
+
+    // Without an index popd removes the top directory, with -n the one below it
+    let index = match arg {
+        Some(arg) if is_stack_index(&arg) => match stack_index(&arg, stack_len) {
+            Some(index) => index,
+            None => {
+                eprintln!("fsh: popd: {}: directory stack index out of range", arg);
+                return 1;
+            }
+        },
+        Some(arg) => {
+            eprintln!("fsh: popd: {}: invalid argument", arg);
+            return 1;
+        }
+        None if no_cd => 1,
+        None => 0,
+    };
+
+    // Removing the current directory changes to the next one
+    if index == 0 {
+        let top = shell.dir_stack[0].clone();
+        if let Err(msg) = change_directory(&top, shell) {
+            eprintln!("fsh: popd: {}", msg);
+            return 1;
+        }
+        shell.dir_stack.remove(0);
+    } else {
+        shell.dir_stack.remove(index - 1);
+    }
+
+    if let Err(err) = print_stack(shell, false, false, false) {
+        return write_error("popd", err);
+    }
+
+    0
+}
+
+// Usage: dirs [-clpv] [+N|-N]
+pub fn dirs(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let mut long = false;
+    let mut per_line = false;
+    let mut numbered = false;
+    let mut index = None;
+
+    for arg in args {
+        if is_stack_index(&arg) {
+            index = Some(arg);
+            continue;
+        }
+
+        for flag in arg.chars().skip(1) {
+            match flag {
+                'c' => shell.dir_stack.clear(),
+                'l' => long = true,
+                'p' => per_line = true,
+                'v' => {
+                    per_line = true;
+                    numbered = true;
+                }
+                _ => {
+                    eprintln!("fsh: dirs: {}: invalid option", arg);
+                    return 1;
+                }
+            }
+        }
+    }
+
+    if let Some(arg) = index {
+        let stack = full_stack(shell);
+        let index = match stack_index(&arg, stack.len()) {
+            Some(index) => index,
+            None => {
+                eprintln!("fsh: dirs: {}: directory stack index out of range", arg);
+                return 1;
+            }
+        };
+        if let Err(err) = write_line(&display_path(&stack[index], long, shell)) {
+            return write_error("dirs", err);
+        }
+        return 0;
+    }
+
+    if let Err(err) = print_stack(shell, long, per_line, numbered) {
+        return write_error("dirs", err);
+    }
+
+    0
+}
+
+// Jump to the best ranked directory that matches all the terms, or list the matching directories
+// with their score, the best one last
+// Usage: z [-l] [term ...]
+pub fn z(args: Vec<String>, shell: &mut Shell) -> i32 {
+    let list = args.first().is_some_and(|arg| arg == "-l");
+    let terms = if list { &args[1..] } else { &args[..] };
+
+    if !list && !terms.is_empty() {
+        // A path to a directory is changed into like with cd
+        if let [dir] = terms {
+            if (dir.contains('/') && Path::new(dir).is_dir()) || dir == "." || dir == ".." {
+                return cd(vec![String::from("--"), dir.clone()], shell);
+            }
+        }
+
+        let current = current_directory(shell);
+        let best = shell
+            .directories
+            .search(terms)
+            .into_iter()
+            .map(|(dir, _)| dir)
+            .find(|dir| *dir != current);
+
+        return match best {
+            Some(dir) => match change_directory(&dir, shell) {
+                Ok(()) => 0,
+                Err(msg) => {
+                    eprintln!("fsh: z: {}", msg);
+                    1
+                }
+            },
+            None => {
+                eprintln!("fsh: z: {}: no matching directory", terms.join(" "));
+                1
+            }
+        };
+    }
+
+    for (dir, score) in shell.directories.search(terms).iter().rev() {
+        if let Err(err) = write_line(&format!("{:<8} {}", score, dir)) {
+            return write_error("z", err);
+        }
+    }
+
+    0
+}
+
+// Change the current directory to `dir` following its logical path and update PWD and OLDPWD
+pub fn change_directory(dir: &str, shell: &mut Shell) -> Result<(), String> {
+    change_directory_to(dir, false, shell)
+}
+
+// Change the current directory to `dir`
+// The logical path resolves .. by removing the previous component of the path as typed, so
+// cd link/.. goes back to where it started, the physical path resolves the symbolic links first
+fn change_directory_to(dir: &str, physical: bool, shell: &mut Shell) -> Result<(), String> {
+    let old_pwd = current_directory(shell);
+
+    let new_pwd = if physical {
+        None
+    } else {
+        let logical = logical_path(dir, &old_pwd);
+        env::set_current_dir(&logical).ok().map(|_| logical)
+    };
+
+    // The physical path is used with -P or when the logical one can not be reached
+    let new_pwd = match new_pwd {
+        Some(new_pwd) => new_pwd,
+        None => {
+            if let Err(err) = env::set_current_dir(dir) {
+                return Err(format!("{}: {}", dir, error_message(&err)));
+            }
+            env::current_dir()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default()
+        }
+    };
+
+    // Only the directories the user changes into are ranked, not those of scripts and subshells
+    if !shell.subshell {
+        shell.directories.visit(&new_pwd);
+    }
+
+    shell.vars.export("OLDPWD", Some(old_pwd));
+    shell.vars.export("PWD", Some(new_pwd));
+
+    Ok(())
+}
+
+// Absolute path of `dir` relative to `pwd` with . and .. components removed without
+// resolving symbolic links
+pub fn logical_path(dir: &str, pwd: &str) -> String {
+    let path = if dir.starts_with('/') {
+        dir.to_string()
+    } else {
+        format!("{}/{}", pwd, dir)
+    };
+
+    let mut components: Vec<&str> = Vec::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+
+    format!("/{}", components.join("/"))
+}
+
+// The directory `dir` is found in with CDPATH, only relative paths that do not start with
+// . or .. are searched
+// An empty entry of CDPATH is the current directory, which is not reported as found with CDPATH
+fn search_cdpath(dir: &str, shell: &Shell) -> Option<String> {
+    if dir.starts_with('/')
+        || dir == "."
+        || dir == ".."
+        || dir.starts_with("./")
+        || dir.starts_with("../")
+    {
+        return None;
+    }
+
+    let cdpath = shell.vars.get("CDPATH")?;
+
+    for entry in cdpath.split(':') {
+        if entry.is_empty() || entry == "." {
+            if Path::new(dir).is_dir() {
+                return None;
+            }
+            continue;
+        }
+
+        let candidate = format!("{}/{}", entry.trim_end_matches('/'), dir);
+        if Path::new(&candidate).is_dir() {
+            return Some(candidate);
+        }
+    }
+
+    None
+}
+
+// `dir` with each component that is not a directory replaced by a similar directory name, one
+// with a wrong, missing or extra character or with two characters transposed
+// Returns None if some component has no similar directory or if nothing was corrected
+pub fn correct_spelling(dir: &str) -> Option<String> {
+    let mut corrected = if dir.starts_with('/') {
+        String::from("/")
+    } else {
+        String::new()
+    };
+
+    for component in dir.split('/').filter(|component| !component.is_empty()) {
+        let parent = if corrected.is_empty() {
+            "."
+        } else {
+            corrected.as_str()
+        };
+
+        let name = if Path::new(parent).join(component).is_dir() {
+            component.to_string()
+        } else {
+            similar_directory(parent, component)?
+        };
+
+        if !corrected.is_empty() && !corrected.ends_with('/') {
+            corrected.push('/');
+        }
+        corrected.push_str(&name);
+    }
+
+    if corrected.trim_end_matches('/') == dir.trim_end_matches('/') {
+        return None;
+    }
+
+    Some(corrected)
+}
+
+// The first directory in `parent`, by name, that is one edit away from `name`
+fn similar_directory(parent: &str, name: &str) -> Option<String> {
+    let mut names: Vec<String> = fs::read_dir(parent)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|entry| suggest::edit_distance(name, entry) == 1)
+        .collect();
+    names.sort();
+
+    names.into_iter().next()
+}
+
+// Whether `path` is an absolute path of the current directory
+pub fn is_current_directory(path: &str) -> bool {
+    if !path.starts_with('/') {
+        return false;
+    }
+
+    match (fs::metadata(path), fs::metadata(".")) {
+        (Ok(path), Ok(current)) => path.dev() == current.dev() && path.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+// The current directory as PWD tells it, or as the system does if PWD is not set
+fn current_directory(shell: &Shell) -> String {
+    match shell.vars.get("PWD") {
+        Some(pwd) => pwd.to_string(),
+        None => env::current_dir()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+// The current directory followed by the saved directories
+fn full_stack(shell: &Shell) -> Vec<String> {
+    let mut stack = vec![current_directory(shell)];
+    stack.extend(shell.dir_stack.iter().cloned());
+
+    stack
+}
+
+fn is_stack_index(arg: &str) -> bool {
+    (arg.starts_with('+') || arg.starts_with('-'))
+        && arg.len() > 1
+        && arg[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+// Index in a stack of `len` directories of +N, counted from the left, or -N, counted from the right
+fn stack_index(arg: &str, len: usize) -> Option<usize> {
+    let n: usize = arg[1..].parse().ok()?;
+
+    if n >= len {
+        return None;
+    }
+
+    if arg.starts_with('+') {
+        Some(n)
+    } else {
+        Some(len - 1 - n)
+    }
+}
+
+fn print_stack(shell: &Shell, long: bool, per_line: bool, numbered: bool) -> io::Result<()> {
+    let stack: Vec<String> = full_stack(shell)
+        .iter()
+        .map(|dir| display_path(dir, long, shell))
+        .collect();
+
+    if numbered {
+        for (index, dir) in stack.iter().enumerate() {
+            write_line(&format!("{:>2}  {}", index, dir))?;
+        }
+    } else if per_line {
+        for dir in stack {
+            write_line(&dir)?;
+        }
+    } else {
+        write_line(&stack.join(" "))?;
+    }
+
+    Ok(())
+}
+
+// `dir` with the home directory replaced by ~, unless the long format is asked for
+fn display_path(dir: &str, long: bool, shell: &Shell) -> String {
+    match shell.vars.get("HOME") {
+        Some(home) if !long => abbreviate_home(dir, home),
+        _ => dir.to_string(),
+    }
+}
+
+// Replace `home` at the start of `path` with ~
+pub fn abbreviate_home(path: &str, home: &str) -> String {
+    match strip_home(path, home) {
+        Some(rest) => format!("~{}", rest),
+        None => path.to_string(),
+    }
+}
+
+// The rest of `path` after `home` if `path` is inside `home`, it is empty or starts with /
+pub fn strip_home<'a>(path: &'a str, home: &str) -> Option<&'a str> {
+    let home = home.trim_end_matches('/');
+
+    match path.strip_prefix(home) {
+        Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => Some(rest),
+        _ => None,
+    }
+}
@@ -0,0 +1,1363 @@
+use crate::dirs;
+use crate::graphemes;
+use crate::keymap::{Binding, Function, Lookup};
+use crate::parser;
+use crate::shell::Shell;
+use crate::vi::{Vi, ViMode, ViResult};
+use glob::glob;
+use termion::event::Key;
+
+// State of the command being typed, changed by the keys the user presses
+// It does not draw anything, the renderer draws it after every key that returns Action::Redraw
+pub struct LineEditor {
+    // Line of the command being edited
+    buffer: Vec<char>,
+
+    // Position of the cursor in `buffer`, from 0 before the first char to its length after the last one
+    cursor: usize,
+
+    // Lines already entered of a command that is not complete yet, like one with an unclosed quote
+    // The line being edited in `buffer` continues them
+    lines: Vec<String>,
+    // Lines of the command after the one being edited, when the cursor was moved up to an earlier line
+    next_lines: Vec<String>,
+
+    mode: Mode,
+
+    // Commands from history or directories matching the buffer in search and jump mode
+    suggestions: Vec<String>,
+    // Indicates what suggestion should be displayed
+    suggestion_index: usize,
+
+    // Indicates what command in history buffer is displayed, None for the command being typed
+    history_index: Option<usize>,
+
+    // Text removed by the kill keys, the last one is inserted again by Ctrl-Y
+    kill_ring: Vec<String>,
+    // Whether the previous key killed text, the text of consecutive kills is joined
+    last_kill: bool,
+    // Text inserted by the previous key if it was Ctrl-Y or Alt-Y, Alt-Y replaces it
+    last_yank: Option<Yank>,
+
+    // States of the command before its last edits, the last one is restored by undo
+    undo_stack: Vec<Snapshot>,
+    // States left by undo, the last one is restored by redo until the command is edited again
+    redo_stack: Vec<Snapshot>,
+    // Whether the previous key inserted a character, consecutive inserts are undone together
+    last_insert: bool,
+    // State of the command before the key being handled, or before search mode started
+    edit_start: Snapshot,
+
+    // Keys typed so far of a sequence bound in the keymap, like Ctrl-X of Ctrl-X Ctrl-E
+    pending_keys: Vec<Key>,
+
+    // State of vi mode, None in emacs mode
+    vi: Option<Vi>,
+}
+
+// Number of killed texts kept in the kill ring
+const KILL_RING_SIZE: usize = 10;
+
+// The command being edited and the cursor at some point, kept by the undo stack
+#[derive(Default)]
+struct Snapshot {
+    lines: Vec<String>,
+    buffer: Vec<char>,
+    next_lines: Vec<String>,
+    cursor: usize,
+}
+
+impl Snapshot {
+    // The whole command, whichever of its lines the cursor is on
+    fn command(&self) -> String {
+        let buffer: String = self.buffer.iter().collect();
+        let mut lines = self.lines.clone();
+        lines.push(buffer);
+        lines.extend(self.next_lines.iter().cloned());
+
+        lines.join("\n")
+    }
+}
+
+// Where yanked text was inserted in the buffer and which entry of the kill ring it is
+struct Yank {
+    start: usize,
+    end: usize,
+    index: usize,
+}
+
+enum Case {
+    Upper,
+    Lower,
+    // The first letter in upper case and the other ones in lower case
+    Capitalized,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    // Typing a command
+    Edit,
+
+    // Searching history for a command starting with the buffer, started with Ctrl-R
+    Search,
+
+    // Selecting a directory to jump to that matches the terms in the buffer, started with Tab
+    // after z and its terms
+    Jump,
+}
+
+// What the line editor asks the read loop to do after a key
+pub enum Action {
+    // Nothing changed
+    None,
+
+    // The command, the cursor or the mode changed and must be drawn again
+    Redraw,
+
+    // Show the entries below the command, then draw the command again under them
+    List(Vec<String>),
+
+    // Run the command, it is complete or the input ended
+    Execute(String),
+
+    // The command was abandoned with Ctrl-C
+    Cancel,
+
+    // Run a shell command bound to the keys, then draw the command being typed again
+    Run(String),
+
+    // Open the command in an editor and run it as it is when the editor exits
+    EditAndExecute(String),
+
+    // Change to the directory selected in jump mode, if one matched
+    Jump(Option<String>),
+
+    // Clear the screen and draw the command at its top
+    ClearScreen,
+
+    // End of input on an empty line
+    Exit,
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        LineEditor {
+            buffer: Vec::new(),
+            cursor: 0,
+            lines: Vec::new(),
+            next_lines: Vec::new(),
+            mode: Mode::Edit,
+            suggestions: Vec::new(),
+            suggestion_index: 0,
+            history_index: None,
+            kill_ring: Vec::new(),
+            last_kill: false,
+            last_yank: None,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            last_insert: false,
+            edit_start: Snapshot::default(),
+            pending_keys: Vec::new(),
+            vi: None,
+        }
+    }
+}
+
+impl LineEditor {
+    // A line editor in vi mode if `vi`, otherwise in emacs mode
+    pub fn new(vi: bool) -> Self {
+        let mut editor = LineEditor::default();
+        editor.reset(vi);
+        editor
+    }
+
+    pub fn buffer(&self) -> &[char] {
+        &self.buffer
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    // Lines of the command before the one being edited
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    // Lines of the command after the one being edited
+    pub fn next_lines(&self) -> &[String] {
+        &self.next_lines
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // Suggestion displayed in search or jump mode
+    pub fn suggestion(&self) -> Option<&String> {
+        self.suggestions.get(self.suggestion_index)
+    }
+
+    // Mode of vi mode, None in emacs mode
+    pub fn vi_mode(&self) -> Option<ViMode> {
+        self.vi.as_ref().map(Vi::mode)
+    }
+
+    // Range of the buffer selected in visual mode
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        self.vi.as_ref()?.selection(&self.buffer, self.cursor)
+    }
+
+    // Forget the command and start a new one in vi mode if `vi`, since set -o vi may have changed
+    // The kill ring and the vi register are kept for the next commands
+    pub fn reset(&mut self, vi: bool) {
+        let kill_ring = std::mem::take(&mut self.kill_ring);
+        let previous_vi = self.vi.take();
+        *self = LineEditor::default();
+        self.kill_ring = kill_ring;
+
+        if vi {
+            let mut vi = previous_vi.unwrap_or_default();
+            vi.start_command();
+            self.vi = Some(vi);
+        }
+    }
+
+    // Update the state for `key`, `shell` gives the key bindings, the history, the directories
+    // and the options
+    pub fn handle_key(&mut self, key: Key, shell: &Shell) -> Action {
+        // Whether the previous key killed or yanked text, the kills and yanks of this one set them again
+        let last_kill = std::mem::replace(&mut self.last_kill, false);
+        let last_yank = self.last_yank.take();
+        let last_insert = std::mem::replace(&mut self.last_insert, false);
+
+        // Search mode edits the text searched for, the command before it is what undo goes back to
+        if self.mode == Mode::Edit {
+            self.edit_start = self.snapshot();
+        }
+
+        // In vi mode, keys that are vi commands edit the buffer, the other ones go through the keymap
+        let vi_result = match &mut self.vi {
+            Some(vi) if self.mode == Mode::Edit => {
+                vi.handle_key(key, &mut self.buffer, &mut self.cursor)
+            }
+            _ => ViResult::Pass(key),
+        };
+
+        let function = match vi_result {
+            ViResult::Handled => {
+                // The text typed after a change like cw is undone with it
+                self.last_insert =
+                    self.record_undo(false) && self.vi_mode() == Some(ViMode::Insert);
+                return self.edited(shell);
+            }
+            ViResult::Function(function) => function,
+            ViResult::Pass(key) => {
+                self.pending_keys.push(key);
+
+                let function = loop {
+                    match shell.keymap.lookup(&self.pending_keys) {
+                        Lookup::Prefix => return Action::None,
+                        Lookup::Bound(Binding::Function(function)) => break *function,
+                        Lookup::Bound(Binding::Command(command)) => {
+                            self.pending_keys.clear();
+                            return Action::Run(command.clone());
+                        }
+                        // The keys before did not start a bound sequence after all, like Ctrl-X
+                        // then a, so the last key does what it does on its own
+                        Lookup::Unbound if self.pending_keys.len() > 1 => {
+                            self.pending_keys = vec![key];
+                        }
+                        // Characters that are not bound are inserted, other keys are ignored
+                        Lookup::Unbound if matches!(key, Key::Char(_)) => {
+                            break Function::SelfInsert
+                        }
+                        Lookup::Unbound => {
+                            self.pending_keys.clear();
+                            return Action::None;
+                        }
+                    }
+                };
+
+                self.pending_keys.clear();
+                function
+            }
+        };
+
+        let action = self.run(function, key, last_kill, last_yank, shell);
+
+        // Outside of insert mode the cursor stays on a character, like after recalling history
+        if let Some(vi) = &self.vi {
+            vi.clamp(&self.buffer, &mut self.cursor);
+        }
+
+        // Entering a line is not an edit that can be undone, and neither are undo and redo
+        if !matches!(
+            function,
+            Function::AcceptLine | Function::Undo | Function::Redo
+        ) {
+            let inserting = function == Function::SelfInsert;
+            self.last_insert = self.record_undo(inserting && last_insert) && inserting;
+        }
+
+        action
+    }
+
+    // Insert `text` pasted in the terminal as it is, without running the functions bound to its
+    // characters. Its new lines start continuation lines instead of entering the command, which
+    // runs when the user presses Enter
+    pub fn paste(&mut self, text: &str, shell: &Shell) -> Action {
+        self.last_kill = false;
+        self.last_yank = None;
+        self.last_insert = false;
+        self.pending_keys.clear();
+
+        // The text searched for and the terms of z are a single line
+        if self.mode != Mode::Edit {
+            self.insert(&text.replace('\n', " "));
+            return self.edited(shell);
+        }
+
+        self.edit_start = self.snapshot();
+
+        let rest: Vec<char> = self.buffer.drain(self.cursor..).collect();
+        let mut pasted_lines = text.split('\n');
+        self.buffer
+            .extend(pasted_lines.next().unwrap_or("").chars());
+        for line in pasted_lines {
+            self.lines.push(self.buffer.drain(..).collect());
+            self.buffer.extend(line.chars());
+        }
+        self.cursor = self.buffer.len();
+        self.buffer.extend(rest);
+
+        if let Some(vi) = &self.vi {
+            vi.clamp(&self.buffer, &mut self.cursor);
+        }
+
+        // The whole paste is undone at once
+        self.record_undo(false);
+
+        Action::Redraw
+    }
+
+    // Run `function` bound to `key`, the last key of its sequence
+    fn run(
+        &mut self,
+        function: Function,
+        key: Key,
+        last_kill: bool,
+        last_yank: Option<Yank>,
+        shell: &Shell,
+    ) -> Action {
+        match function {
+            Function::AcceptLine => self.enter(),
+            Function::Complete => self.tab(shell),
+            Function::SelfInsert => match key {
+                Key::Char(c) => {
+                    self.insert(&c.to_string());
+                    self.edited(shell)
+                }
+                _ => Action::None,
+            },
+            Function::Abort => Action::Cancel,
+            Function::DeleteChar | Function::DeleteCharOrEof if self.cursor < self.buffer.len() => {
+                let end = graphemes::next_boundary(&self.buffer, self.cursor);
+                self.buffer.drain(self.cursor..end);
+                self.edited(shell)
+            }
+            // At the end of a line followed by another one, join them
+            Function::DeleteChar | Function::DeleteCharOrEof if !self.next_lines.is_empty() => {
+                let next_line = self.next_lines.remove(0);
+                self.buffer.extend(next_line.chars());
+                self.edited(shell)
+            }
+            // End of input in the middle of a command reports what it was missing
+            Function::DeleteCharOrEof if self.buffer.is_empty() && !self.lines.is_empty() => {
+                Action::Execute(self.lines.join("\n"))
+            }
+            Function::DeleteCharOrEof if self.buffer.is_empty() => Action::Exit,
+            Function::DeleteChar | Function::DeleteCharOrEof => Action::None,
+            Function::HistorySearchBackward => {
+                self.mode = Mode::Search;
+                self.edited(shell)
+            }
+            Function::ClearScreen => Action::ClearScreen,
+            Function::EditAndExecuteCommand if self.mode == Mode::Edit => {
+                Action::EditAndExecute(self.command())
+            }
+            Function::EditAndExecuteCommand => Action::None,
+            Function::Undo if self.mode == Mode::Edit => self.undo(shell),
+            Function::Redo if self.mode == Mode::Edit => self.redo(shell),
+            Function::Undo | Function::Redo => Action::None,
+
+            // Motions
+            // The cursor goes from the start of a line to the end of the line above and back
+            Function::BackwardChar
+                if self.cursor == 0 && self.mode == Mode::Edit && !self.lines.is_empty() =>
+            {
+                self.previous_line();
+                self.cursor = self.buffer.len();
+                Action::Redraw
+            }
+            Function::ForwardChar
+                if self.cursor == self.buffer.len() && !self.next_lines.is_empty() =>
+            {
+                self.next_line();
+                self.cursor = 0;
+                Action::Redraw
+            }
+            Function::BackwardChar => {
+                self.move_to(graphemes::previous_boundary(&self.buffer, self.cursor))
+            }
+            Function::ForwardChar => {
+                self.move_to(graphemes::next_boundary(&self.buffer, self.cursor))
+            }
+            Function::BeginningOfLine => self.move_to(0),
+            Function::EndOfLine => self.move_to(self.buffer.len()),
+            Function::BackwardWord => self.move_to(self.previous_word_start()),
+            Function::ForwardWord => self.move_to(self.next_word_end()),
+
+            // Kills and yanks
+            Function::KillLine => self.kill(self.cursor, self.buffer.len(), last_kill, shell),
+            Function::UnixLineDiscard => self.kill(0, self.cursor, last_kill, shell),
+            Function::UnixWordRubout => self.kill(
+                self.previous_blank_word_start(),
+                self.cursor,
+                last_kill,
+                shell,
+            ),
+            Function::KillWord => self.kill(self.cursor, self.next_word_end(), last_kill, shell),
+            Function::BackwardKillWord => {
+                self.kill(self.previous_word_start(), self.cursor, last_kill, shell)
+            }
+            Function::Yank => self.yank(shell),
+            Function::YankPop => self.yank_pop(last_yank, shell),
+
+            // Changes
+            Function::TransposeChars => self.transpose(shell),
+            Function::UpcaseWord => self.change_case(Case::Upper, shell),
+            Function::DowncaseWord => self.change_case(Case::Lower, shell),
+            Function::CapitalizeWord => self.change_case(Case::Capitalized, shell),
+
+            Function::PreviousHistory => self.previous(shell),
+            Function::NextHistory => self.next(shell),
+            Function::BackwardDeleteChar => self.backspace(shell),
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            lines: self.lines.clone(),
+            buffer: self.buffer.clone(),
+            next_lines: self.next_lines.clone(),
+            cursor: self.cursor,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.lines = snapshot.lines;
+        self.buffer = snapshot.buffer;
+        self.next_lines = snapshot.next_lines;
+        self.cursor = snapshot.cursor;
+
+        if let Some(vi) = &self.vi {
+            vi.clamp(&self.buffer, &mut self.cursor);
+        }
+    }
+
+    // If the key changed the command, remember how it was before so that it can be undone
+    // With `join` the change is undone with the previous one instead, like consecutive inserts
+    // Returns whether the command changed, moving the cursor to another line does not change it
+    fn record_undo(&mut self, join: bool) -> bool {
+        if self.mode != Mode::Edit || self.command() == self.edit_start.command() {
+            return false;
+        }
+
+        let before = std::mem::take(&mut self.edit_start);
+        if !join {
+            self.undo_stack.push(before);
+        }
+        self.redo_stack.clear();
+
+        true
+    }
+
+    fn undo(&mut self, shell: &Shell) -> Action {
+        match self.undo_stack.pop() {
+            Some(snapshot) => {
+                self.redo_stack.push(self.snapshot());
+                self.restore(snapshot);
+                self.edited(shell)
+            }
+            None => Action::None,
+        }
+    }
+
+    fn redo(&mut self, shell: &Shell) -> Action {
+        match self.redo_stack.pop() {
+            Some(snapshot) => {
+                self.undo_stack.push(self.snapshot());
+                self.restore(snapshot);
+                self.edited(shell)
+            }
+            None => Action::None,
+        }
+    }
+
+    // Move the cursor to `position` if it is in the buffer
+    fn move_to(&mut self, position: usize) -> Action {
+        if position > self.buffer.len() || position == self.cursor {
+            return Action::None;
+        }
+
+        self.cursor = position;
+        Action::Redraw
+    }
+
+    // After the buffer changed, find the suggestions for it again in search and jump mode
+    fn edited(&mut self, shell: &Shell) -> Action {
+        self.update_suggestions(shell);
+        Action::Redraw
+    }
+
+    // Start of the word before the cursor, words are made of letters and digits
+    // A letter with combining marks is part of the word, since a grapheme cluster is classified by
+    // its first char
+    fn previous_word_start(&self) -> usize {
+        let mut position = self.cursor;
+        while position > 0 && !self.char_before(position).is_alphanumeric() {
+            position = graphemes::previous_boundary(&self.buffer, position);
+        }
+        while position > 0 && self.char_before(position).is_alphanumeric() {
+            position = graphemes::previous_boundary(&self.buffer, position);
+        }
+
+        position
+    }
+
+    // End of the word after the cursor, words are made of letters and digits
+    fn next_word_end(&self) -> usize {
+        let mut position = self.cursor;
+        while position < self.buffer.len() && !self.buffer[position].is_alphanumeric() {
+            position = graphemes::next_boundary(&self.buffer, position);
+        }
+        while position < self.buffer.len() && self.buffer[position].is_alphanumeric() {
+            position = graphemes::next_boundary(&self.buffer, position);
+        }
+
+        position
+    }
+
+    // Start of the word before the cursor for Ctrl-W, where words are separated by blanks
+    fn previous_blank_word_start(&self) -> usize {
+        let mut position = self.cursor;
+        while position > 0 && self.char_before(position).is_whitespace() {
+            position = graphemes::previous_boundary(&self.buffer, position);
+        }
+        while position > 0 && !self.char_before(position).is_whitespace() {
+            position = graphemes::previous_boundary(&self.buffer, position);
+        }
+
+        position
+    }
+
+    // First char of the grapheme cluster before `position`
+    fn char_before(&self, position: usize) -> char {
+        self.buffer[graphemes::previous_boundary(&self.buffer, position)]
+    }
+
+    // Remove the text from `start` to `end` and save it in the kill ring
+    // Text killed by consecutive kills is saved as one entry, so it is yanked back at once
+    fn kill(&mut self, start: usize, end: usize, last_kill: bool, shell: &Shell) -> Action {
+        if start >= end {
+            return Action::None;
+        }
+
+        let text: String = self.buffer.drain(start..end).collect();
+        let backward = end == self.cursor;
+        self.cursor = start;
+
+        match self.kill_ring.last_mut() {
+            Some(killed) if last_kill && backward => killed.insert_str(0, &text),
+            Some(killed) if last_kill => killed.push_str(&text),
+            _ => {
+                self.kill_ring.push(text);
+                if self.kill_ring.len() > KILL_RING_SIZE {
+                    self.kill_ring.remove(0);
+                }
+            }
+        }
+        self.last_kill = true;
+
+        self.edited(shell)
+    }
+
+    // Insert the last killed text at the cursor
+    fn yank(&mut self, shell: &Shell) -> Action {
+        let index = match self.kill_ring.len() {
+            0 => return Action::None,
+            len => len - 1,
+        };
+
+        self.insert_yank(index, self.cursor);
+        self.edited(shell)
+    }
+
+    // Right after a yank, replace the yanked text with the text killed before it
+    fn yank_pop(&mut self, last_yank: Option<Yank>, shell: &Shell) -> Action {
+        let yank = match last_yank {
+            Some(yank) => yank,
+            None => return Action::None,
+        };
+
+        self.buffer.drain(yank.start..yank.end);
+        let index = (yank.index + self.kill_ring.len() - 1) % self.kill_ring.len();
+        self.insert_yank(index, yank.start);
+
+        self.edited(shell)
+    }
+
+    fn insert_yank(&mut self, index: usize, start: usize) {
+        self.cursor = start;
+        self.insert(&self.kill_ring[index].clone());
+        self.last_yank = Some(Yank {
+            start,
+            end: self.cursor,
+            index,
+        });
+    }
+
+    // Exchange the character before the cursor with the one under it and move the cursor after
+    // both, at the end of the line the last two characters are exchanged
+    fn transpose(&mut self, shell: &Shell) -> Action {
+        let second = if self.cursor == self.buffer.len() {
+            graphemes::previous_boundary(&self.buffer, self.cursor)
+        } else {
+            self.cursor
+        };
+        if second == 0 {
+            return Action::None;
+        }
+
+        let first = graphemes::previous_boundary(&self.buffer, second);
+        let end = graphemes::next_boundary(&self.buffer, second);
+        let mut swapped = self.buffer[second..end].to_vec();
+        swapped.extend_from_slice(&self.buffer[first..second]);
+        self.buffer.splice(first..end, swapped);
+        self.cursor = end;
+
+        self.edited(shell)
+    }
+
+    // Change the case of the word after the cursor and move the cursor after it
+    fn change_case(&mut self, case: Case, shell: &Shell) -> Action {
+        let start = self.cursor;
+        let end = self.next_word_end();
+        if start == end {
+            return Action::None;
+        }
+
+        let mut first_letter = true;
+        let mut changed = String::new();
+        for &c in &self.buffer[start..end] {
+            let upper = match case {
+                Case::Upper => true,
+                Case::Lower => false,
+                Case::Capitalized => first_letter && c.is_alphanumeric(),
+            };
+            if c.is_alphanumeric() {
+                first_letter = false;
+            }
+
+            if upper {
+                changed.extend(c.to_uppercase());
+            } else {
+                changed.extend(c.to_lowercase());
+            }
+        }
+
+        self.buffer.splice(start..end, changed.chars());
+        self.cursor = start + changed.chars().count();
+
+        self.edited(shell)
+    }
+
+    // All the lines of the command
+    fn command(&self) -> String {
+        self.snapshot().command()
+    }
+
+    fn enter(&mut self) -> Action {
+        if self.mode == Mode::Jump {
+            return Action::Jump(self.suggestion().cloned());
+        }
+
+        // Leaving search mode keeps the typed text as the command
+        self.mode = Mode::Edit;
+        self.suggestions.clear();
+        self.suggestion_index = 0;
+
+        let command = self.command();
+
+        // Keep reading lines with the continuation prompt until the command is complete, the new
+        // line is after the last one whichever line the cursor is on
+        if !parser::is_complete(&command) {
+            self.lines.push(self.buffer.drain(..).collect());
+            self.lines.append(&mut self.next_lines);
+            self.cursor = 0;
+
+            // Each line is undone on its own
+            self.undo_stack.clear();
+            self.redo_stack.clear();
+            return Action::Redraw;
+        }
+
+        Action::Execute(command)
+    }
+
+    fn tab(&mut self, shell: &Shell) -> Action {
+        if self.mode == Mode::Jump {
+            return Action::Jump(self.suggestion().cloned());
+        }
+
+        if self.mode == Mode::Search {
+            // Set suggestion as current command
+            if let Some(suggestion) = self.suggestion() {
+                self.buffer = suggestion.chars().collect();
+                self.cursor = self.buffer.len();
+                self.mode = Mode::Edit;
+                self.suggestions.clear();
+                self.suggestion_index = 0;
+            }
+            return Action::Redraw;
+        }
+
+        let command: String = self.buffer.iter().collect();
+
+        // Enable jump mode for the directories matching the terms
+        if let Some(terms) = command.strip_prefix("z ") {
+            self.buffer = terms.chars().collect();
+            self.cursor = self.buffer.len();
+            self.mode = Mode::Jump;
+            self.update_suggestions(shell);
+            return Action::Redraw;
+        }
+
+        self.complete_path(shell)
+    }
+
+    // Complete the path before the cursor, the command name itself is not completed
+    fn complete_path(&mut self, shell: &Shell) -> Action {
+        let start = match self.buffer[..self.cursor].iter().rposition(|&c| c == ' ') {
+            Some(space) => space + 1,
+            None => return Action::None,
+        };
+
+        let mut path: String = self.buffer[start..self.cursor].iter().collect();
+        let mut action = Action::None;
+
+        // With dirspell a misspelled directory in the path is corrected before completing
+        if shell.options.shopt("dirspell") {
+            if let Some(corrected) = correct_directory(&path) {
+                self.buffer.splice(start..self.cursor, corrected.chars());
+                self.cursor = start + corrected.chars().count();
+                path = corrected;
+                action = Action::Redraw;
+            }
+        }
+
+        let mut entries: Vec<String> = get_entries_of_glob(&path)
+            .into_iter()
+            .map(|entry| entry.display().to_string())
+            .collect();
+
+        // Fill the rest of user typed text if there is only one suggestion
+        if entries.len() == 1 {
+            let entry = entries.pop().unwrap();
+            self.insert(&entry.chars().skip(path.chars().count()).collect::<String>());
+            return Action::Redraw;
+        }
+
+        if entries.len() > 1 {
+            return Action::List(entries);
+        }
+
+        action
+    }
+
+    fn previous(&mut self, shell: &Shell) -> Action {
+        if self.mode != Mode::Edit {
+            // If there exists a suggestion after current one, move index to the next one
+            if self.suggestion_index + 1 < self.suggestions.len() {
+                self.suggestion_index += 1;
+            }
+            return Action::Redraw;
+        }
+
+        // In a command of several lines the cursor goes up a line before going back in history
+        if !self.lines.is_empty() {
+            self.previous_line();
+            return Action::Redraw;
+        }
+
+        let index = self.history_index.map_or(0, |index| index + 1);
+        match shell.history.get(index) {
+            Some(command) => {
+                self.history_index = Some(index);
+                self.replace(command);
+                Action::Redraw
+            }
+            // If there is no more command in history, keep the current one
+            None => Action::None,
+        }
+    }
+
+    fn next(&mut self, shell: &Shell) -> Action {
+        if self.mode != Mode::Edit {
+            self.suggestion_index = self.suggestion_index.saturating_sub(1);
+            return Action::Redraw;
+        }
+
+        if !self.next_lines.is_empty() {
+            self.next_line();
+            return Action::Redraw;
+        }
+
+        match self.history_index {
+            Some(0) | None => {
+                self.history_index = None;
+                self.replace("");
+            }
+            Some(index) => {
+                self.history_index = Some(index - 1);
+                if let Some(command) = shell.history.get(index - 1) {
+                    self.replace(command);
+                }
+            }
+        }
+
+        Action::Redraw
+    }
+
+    // Edit the line above the one being edited, with the cursor as far into it as before
+    fn previous_line(&mut self) {
+        if let Some(line) = self.lines.pop() {
+            let buffer = std::mem::replace(&mut self.buffer, line.chars().collect());
+            self.next_lines.insert(0, buffer.into_iter().collect());
+            self.keep_column();
+        }
+    }
+
+    // Edit the line below the one being edited, with the cursor as far into it as before
+    fn next_line(&mut self) {
+        if !self.next_lines.is_empty() {
+            let line = self.next_lines.remove(0);
+            let buffer = std::mem::replace(&mut self.buffer, line.chars().collect());
+            self.lines.push(buffer.into_iter().collect());
+            self.keep_column();
+        }
+    }
+
+    // Keep the cursor in the buffer after it changed to another line, at the start of a grapheme
+    // cluster
+    fn keep_column(&mut self) {
+        self.cursor = self.cursor.min(self.buffer.len());
+        if self.cursor < self.buffer.len() {
+            self.cursor = graphemes::previous_boundary(
+                &self.buffer,
+                graphemes::next_boundary(&self.buffer, self.cursor),
+            );
+        }
+    }
+
+    fn backspace(&mut self, shell: &Shell) -> Action {
+        if self.cursor > 0 {
+            // Remove the character before the cursor with the marks combined with it
+            let start = graphemes::previous_boundary(&self.buffer, self.cursor);
+            self.buffer.drain(start..self.cursor);
+            self.cursor = start;
+        } else if self.mode == Mode::Edit {
+            // At the start of a continuation line, join it with the previous line
+            match self.lines.pop() {
+                Some(previous_line) => {
+                    self.cursor = previous_line.chars().count();
+                    self.buffer.splice(0..0, previous_line.chars());
+                }
+                None => return Action::None,
+            }
+        }
+
+        self.update_suggestions(shell);
+        Action::Redraw
+    }
+
+    // Insert `text` at the cursor and move the cursor after it
+    fn insert(&mut self, text: &str) {
+        let count = text.chars().count();
+        self.buffer.splice(self.cursor..self.cursor, text.chars());
+        self.cursor += count;
+    }
+
+    // Replace the command being edited, which can span several lines, with `text`
+    fn replace(&mut self, text: &str) {
+        let mut lines: Vec<String> = text.split('\n').map(String::from).collect();
+        self.buffer = lines.pop().unwrap_or_default().chars().collect();
+        self.cursor = self.buffer.len();
+        self.lines = lines;
+        self.next_lines.clear();
+    }
+
+    // Find the suggestions for the buffer again in search and jump mode
+    fn update_suggestions(&mut self, shell: &Shell) {
+        let command: String = self.buffer.iter().collect();
+        self.suggestion_index = 0;
+
+        self.suggestions = match self.mode {
+            Mode::Edit => Vec::new(),
+            Mode::Search => shell.history.search(&command),
+            // Directories other than the current one that match the terms, the best one first
+            Mode::Jump => {
+                let terms: Vec<String> = command.split_whitespace().map(String::from).collect();
+                let current = shell.vars.get("PWD").unwrap_or("");
+
+                shell
+                    .directories
+                    .search(&terms)
+                    .into_iter()
+                    .map(|(directory, _)| directory)
+                    .filter(|directory| directory != current)
+                    .collect()
+            }
+        };
+    }
+}
+
+// `path` with its directory corrected if it does not exist and a similar one does
+fn correct_directory(path: &str) -> Option<String> {
+    let (dir, name) = path.split_at(path.rfind('/')? + 1);
+
+    if std::path::Path::new(dir).is_dir() {
+        return None;
+    }
+
+    let corrected = dirs::correct_spelling(dir)?;
+    Some(format!("{}/{}", corrected.trim_end_matches('/'), name))
+}
+
+// Get entries based on `path_str`, none if it is not a valid pattern like [
+fn get_entries_of_glob(path_str: &str) -> Vec<std::path::PathBuf> {
+    // If user already used '*', don't add it
+    let postfix = if path_str.contains('*') {
+        String::new()
+    } else {
+        String::from("*")
+    };
+
+    let path = std::path::Path::new(path_str);
+
+    let pattern = if path.is_relative() {
+        format!("./{}{postfix}", path_str, postfix = postfix)
+    } else {
+        format!("{}{postfix}", path_str, postfix = postfix)
+    };
+
+    match glob(&pattern) {
+        Ok(paths) => paths.filter_map(Result::ok).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{shell, TempDir};
+
+    fn type_keys(editor: &mut LineEditor, keys: &[Key], shell: &Shell) {
+        for key in keys {
+            editor.handle_key(*key, shell);
+        }
+    }
+
+    fn type_text(editor: &mut LineEditor, text: &str, shell: &Shell) {
+        for c in text.chars() {
+            editor.handle_key(Key::Char(c), shell);
+        }
+    }
+
+    fn buffer(editor: &LineEditor) -> String {
+        editor.buffer().iter().collect()
+    }
+
+    #[test]
+    fn insert_at_end() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "echo", &shell);
+
+        assert_eq!(buffer(&editor), "echo");
+        assert_eq!(editor.cursor(), 4);
+    }
+
+    #[test]
+    fn insert_in_middle() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "ecto", &shell);
+        type_keys(&mut editor, &[Key::Left, Key::Left], &shell);
+        type_text(&mut editor, "h", &shell);
+
+        assert_eq!(buffer(&editor), "echto");
+        assert_eq!(editor.cursor(), 3);
+    }
+
+    #[test]
+    fn insert_at_start() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "cho", &shell);
+        type_keys(&mut editor, &[Key::Home], &shell);
+        type_text(&mut editor, "e", &shell);
+
+        assert_eq!(buffer(&editor), "echo");
+        assert_eq!(editor.cursor(), 1);
+    }
+
+    #[test]
+    fn insert_multibyte_text() {
+        let mut editor = LineEditor::new(false);
+
+        editor.insert("echo ");
+        editor.insert("日本");
+        editor.cursor = 5;
+        editor.insert("é");
+
+        assert_eq!(buffer(&editor), "echo é日本");
+        assert_eq!(editor.cursor(), 6);
+    }
+
+    #[test]
+    fn backspace_in_middle() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "echxo", &shell);
+        type_keys(&mut editor, &[Key::Left, Key::Backspace], &shell);
+
+        assert_eq!(buffer(&editor), "echo");
+        assert_eq!(editor.cursor(), 3);
+    }
+
+    #[test]
+    fn complete_inserts_at_cursor() {
+        let shell = shell();
+        let dir = TempDir::new("editor-test", &["unique-file"]);
+        let dir = dir.path();
+
+        // The rest of the only matching file is inserted before the text after the cursor
+        let mut editor = LineEditor::new(false);
+        editor.insert(&format!("cat {}/uniq > out", dir.display()));
+        editor.cursor -= " > out".len();
+        type_keys(&mut editor, &[Key::Char('\t')], &shell);
+
+        let expected = format!("cat {}/unique-file", dir.display());
+        assert_eq!(buffer(&editor), format!("{} > out", expected));
+        assert_eq!(editor.cursor(), expected.chars().count());
+    }
+
+    #[test]
+    fn undo_after_insert() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        // Consecutive characters are undone together, a motion starts a new group
+        type_text(&mut editor, "echo", &shell);
+        type_keys(&mut editor, &[Key::Left], &shell);
+        type_text(&mut editor, "xy", &shell);
+        type_keys(&mut editor, &[Key::Ctrl('7')], &shell);
+
+        assert_eq!(buffer(&editor), "echo");
+        assert_eq!(editor.cursor(), 3);
+
+        type_keys(&mut editor, &[Key::Ctrl('7')], &shell);
+
+        assert_eq!(buffer(&editor), "");
+        assert_eq!(editor.cursor(), 0);
+    }
+
+    #[test]
+    fn redo_after_undo() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "echo ", &shell);
+        type_keys(&mut editor, &[Key::Left, Key::Right], &shell);
+        type_text(&mut editor, "one", &shell);
+        type_keys(&mut editor, &[Key::Ctrl('7'), Key::Ctrl('7')], &shell);
+        assert_eq!(buffer(&editor), "");
+
+        // Redo goes forward again through the undone edits
+        type_keys(&mut editor, &[Key::Alt('_')], &shell);
+        assert_eq!(buffer(&editor), "echo ");
+        assert_eq!(editor.cursor(), 5);
+        type_keys(&mut editor, &[Key::Alt('_')], &shell);
+        assert_eq!(buffer(&editor), "echo one");
+        assert_eq!(editor.cursor(), 8);
+        type_keys(&mut editor, &[Key::Alt('_')], &shell);
+        assert_eq!(buffer(&editor), "echo one");
+
+        // An edit after undo leaves nothing to redo
+        type_keys(&mut editor, &[Key::Ctrl('7')], &shell);
+        type_text(&mut editor, "two", &shell);
+        type_keys(&mut editor, &[Key::Alt('_')], &shell);
+        assert_eq!(buffer(&editor), "echo two");
+    }
+
+    #[test]
+    fn undo_completion() {
+        let shell = shell();
+        let dir = TempDir::new("editor-undo", &["unique-file"]);
+
+        // The whole completion is one step
+        let mut editor = LineEditor::new(false);
+        let command = format!("cat {}/uniq", dir.path().display());
+        type_text(&mut editor, &command, &shell);
+        type_keys(&mut editor, &[Key::Char('\t')], &shell);
+        assert_eq!(buffer(&editor), format!("{}ue-file", command));
+
+        type_keys(&mut editor, &[Key::Ctrl('7')], &shell);
+        assert_eq!(buffer(&editor), command);
+        type_keys(&mut editor, &[Key::Alt('_')], &shell);
+        assert_eq!(buffer(&editor), format!("{}ue-file", command));
+    }
+
+    #[test]
+    fn undo_history_recall() {
+        let mut shell = shell();
+        shell.history.add_command(String::from("echo one"));
+        shell.history.add_command(String::from("echo two"));
+
+        // Each command recalled from history is one step
+        let mut editor = LineEditor::new(false);
+        type_text(&mut editor, "ls", &shell);
+        type_keys(&mut editor, &[Key::Up], &shell);
+        assert_eq!(buffer(&editor), "echo two");
+        type_keys(&mut editor, &[Key::Ctrl('p')], &shell);
+        assert_eq!(buffer(&editor), "echo one");
+
+        type_keys(&mut editor, &[Key::Ctrl('7')], &shell);
+        assert_eq!(buffer(&editor), "echo two");
+        type_keys(&mut editor, &[Key::Ctrl('7')], &shell);
+        assert_eq!(buffer(&editor), "ls");
+        type_keys(&mut editor, &[Key::Alt('_')], &shell);
+        assert_eq!(buffer(&editor), "echo two");
+    }
+
+    #[test]
+    fn undo_kills() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        // Each kill is one step, even when the killed text is yanked together
+        type_text(&mut editor, "echo one two", &shell);
+        type_keys(&mut editor, &[Key::Ctrl('w'), Key::Ctrl('w')], &shell);
+        assert_eq!(buffer(&editor), "echo ");
+
+        type_keys(&mut editor, &[Key::Ctrl('7')], &shell);
+        assert_eq!(buffer(&editor), "echo one ");
+        type_keys(&mut editor, &[Key::Ctrl('7')], &shell);
+        assert_eq!(buffer(&editor), "echo one two");
+        assert_eq!(editor.cursor(), 12);
+
+        // And so is a yank
+        type_keys(&mut editor, &[Key::Ctrl('u'), Key::Ctrl('y')], &shell);
+        assert_eq!(buffer(&editor), "echo one two");
+        type_keys(&mut editor, &[Key::Ctrl('7')], &shell);
+        assert_eq!(buffer(&editor), "");
+    }
+
+    #[test]
+    fn unbound_key_after_prefix() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        // Ctrl-X only starts sequences like Ctrl-X Ctrl-E, the key after it is not lost
+        type_text(&mut editor, "ech", &shell);
+        type_keys(&mut editor, &[Key::Ctrl('x'), Key::Char('o')], &shell);
+        assert_eq!(buffer(&editor), "echo");
+
+        type_keys(&mut editor, &[Key::Ctrl('x'), Key::Ctrl('a')], &shell);
+        assert_eq!(editor.cursor(), 0);
+
+        // A second Ctrl-X starts the sequence again
+        type_keys(&mut editor, &[Key::Ctrl('x'), Key::Ctrl('x')], &shell);
+        type_text(&mut editor, "x", &shell);
+        assert_eq!(buffer(&editor), "xecho");
+    }
+
+    #[test]
+    fn word_motions_keep_combining_marks() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        // The combining acute accent belongs to the word, so it is skipped with the e
+        editor.insert("xe\u{301}y z");
+        type_keys(&mut editor, &[Key::Home, Key::Alt('f')], &shell);
+        type_text(&mut editor, "Z", &shell);
+
+        assert_eq!(buffer(&editor), "xe\u{301}yZ z");
+
+        let mut editor = LineEditor::new(false);
+        editor.insert("ab xe\u{301}");
+        type_keys(&mut editor, &[Key::Alt('\x7f')], &shell);
+
+        assert_eq!(buffer(&editor), "ab ");
+        assert_eq!(editor.cursor(), 3);
+    }
+
+    #[test]
+    fn complete_invalid_pattern() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        editor.insert("ls [");
+        type_keys(&mut editor, &[Key::Char('\t')], &shell);
+
+        assert_eq!(buffer(&editor), "ls [");
+    }
+
+    #[test]
+    fn edit_earlier_line() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "echo 'one", &shell);
+        type_keys(&mut editor, &[Key::Char('\n')], &shell);
+        type_text(&mut editor, "two'", &shell);
+
+        // Up goes to the line above before going back in history, at the same position
+        type_keys(&mut editor, &[Key::Up], &shell);
+        assert_eq!(buffer(&editor), "echo 'one");
+        assert_eq!(editor.next_lines(), ["two'"]);
+        assert_eq!(editor.cursor(), 4);
+
+        type_text(&mut editor, "s", &shell);
+        assert_eq!(editor.command(), "echos 'one\ntwo'");
+
+        // Right at the end of a line goes to the start of the next one
+        type_keys(&mut editor, &[Key::End, Key::Right], &shell);
+        assert_eq!(buffer(&editor), "two'");
+        assert_eq!(editor.cursor(), 0);
+
+        // Moving between lines is not undone, the edit is
+        type_keys(&mut editor, &[Key::Ctrl('7')], &shell);
+        assert_eq!(editor.command(), "echo 'one\ntwo'");
+    }
+
+    #[test]
+    fn yank_pop_rotates_the_kill_ring() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        for text in ["one", "two", "three"].iter() {
+            type_text(&mut editor, text, &shell);
+            type_keys(&mut editor, &[Key::Ctrl('u')], &shell);
+        }
+        type_text(&mut editor, "> ", &shell);
+
+        // Each yank-pop replaces the yanked text with the one killed before it
+        type_keys(&mut editor, &[Key::Ctrl('y')], &shell);
+        assert_eq!(buffer(&editor), "> three");
+        type_keys(&mut editor, &[Key::Alt('y')], &shell);
+        assert_eq!(buffer(&editor), "> two");
+        type_keys(&mut editor, &[Key::Alt('y')], &shell);
+        assert_eq!(buffer(&editor), "> one");
+        assert_eq!(editor.cursor(), 5);
+
+        // And it starts again with the last killed text after the oldest one
+        type_keys(&mut editor, &[Key::Alt('y')], &shell);
+        assert_eq!(buffer(&editor), "> three");
+
+        // Only right after a yank
+        type_text(&mut editor, "!", &shell);
+        type_keys(&mut editor, &[Key::Alt('y')], &shell);
+        assert_eq!(buffer(&editor), "> three!");
+    }
+
+    #[test]
+    fn consecutive_kills_are_yanked_together() {
+        let shell = shell();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "echo one two", &shell);
+        type_keys(&mut editor, &[Key::Ctrl('w'), Key::Ctrl('w')], &shell);
+        assert_eq!(buffer(&editor), "echo ");
+
+        type_keys(&mut editor, &[Key::Ctrl('y'), Key::Ctrl('y')], &shell);
+        assert_eq!(buffer(&editor), "echo one twoone two");
+    }
+
+    #[test]
+    fn transpose_chars() {
+        let shell = shell();
+        let transposed = |text: &str, cursor: usize| {
+            let mut editor = LineEditor::new(false);
+            editor.insert(text);
+            editor.cursor = cursor;
+            type_keys(&mut editor, &[Key::Ctrl('t')], &shell);
+            (buffer(&editor), editor.cursor())
+        };
+
+        // At the start of the line there is nothing before the cursor
+        assert_eq!(transposed("abc", 0), (String::from("abc"), 0));
+        assert_eq!(transposed("abc", 1), (String::from("bac"), 2));
+
+        // At the end of the line the last two characters are exchanged
+        assert_eq!(transposed("abc", 3), (String::from("acb"), 3));
+        assert_eq!(transposed("a", 1), (String::from("a"), 1));
+
+        // A character with a combining mark moves as a whole
+        assert_eq!(transposed("ae\u{301}", 3), (String::from("e\u{301}a"), 3));
+    }
+
+    #[test]
+    fn change_case_of_words() {
+        let shell = shell();
+        let changed = |text: &str, cursor: usize, keys: &[Key]| {
+            let mut editor = LineEditor::new(false);
+            editor.insert(text);
+            editor.cursor = cursor;
+            type_keys(&mut editor, keys, &shell);
+            (buffer(&editor), editor.cursor())
+        };
+
+        assert_eq!(
+            changed("echo hello world", 4, &[Key::Alt('u')]),
+            (String::from("echo HELLO world"), 10)
+        );
+        assert_eq!(
+            changed("ECHO Hello", 0, &[Key::Alt('l'), Key::Alt('l')]),
+            (String::from("echo hello"), 10)
+        );
+        assert_eq!(
+            changed("hELLO wORLD", 0, &[Key::Alt('c'), Key::Alt('c')]),
+            (String::from("Hello World"), 11)
+        );
+
+        // From the cursor to the end of the word
+        assert_eq!(
+            changed("hello", 2, &[Key::Alt('c')]),
+            (String::from("heLlo"), 5)
+        );
+        assert_eq!(
+            changed("straße", 0, &[Key::Alt('u')]),
+            (String::from("STRASSE"), 7)
+        );
+
+        // At the end of the line nothing changes
+        assert_eq!(changed("ab", 2, &[Key::Alt('u')]), (String::from("ab"), 2));
+    }
+}
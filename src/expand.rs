@@ -0,0 +1,1656 @@
+use crate::arith;
+use crate::lexer::{skip_backquoted, skip_dollar, skip_double_quoted, skip_single_quoted};
+use crate::pattern::{self, Pattern};
+use crate::process::capture_output;
+use crate::shell::Shell;
+use crate::user;
+use crate::vars::{is_valid_name, DEFAULT_IFS};
+
+// Expansion of the words of a command
+// A word goes through these stages in order:
+//  1. tilde expansion:       ~/src -> /home/user/src
+//  2. parameter expansion:   $HOME, ${name:-default}, ${file%.rs}, "$@"
+//  3. command substitution:  $(date) and `date`
+//  4. arithmetic expansion:  $((1 + 2))
+//  5. field splitting of the unquoted results of stages 2 to 4 on the characters of $IFS
+//  6. filename expansion of the fields that contain unquoted pattern characters
+//  7. quote removal
+// Each stage is a function from the output of the previous stage to the input of the next one
+
+// A part of a word, as parsed before expansion and as produced by the stages 1 to 4
+#[derive(Clone, Debug, PartialEq)]
+pub enum Part {
+    // Text typed as is, `quoted` if it was inside quotes or escaped by a backslash
+    Literal { text: String, quoted: bool },
+
+    // ~ or ~name at the start of a word
+    Tilde(String),
+
+    Parameter { expression: Parameter, quoted: bool },
+    Command { source: String, quoted: bool },
+    Arithmetic { expression: String, quoted: bool },
+
+    // Result of an expansion, only unquoted results are split into fields
+    Expanded { text: String, quoted: bool },
+
+    // Separates the fields produced by "$@"
+    FieldBreak,
+}
+
+// A parsed parameter expansion like ${name:-word}
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+
+    // Subscript of an array element like ${name[1]}, or @ and * for all elements
+    pub subscript: Option<String>,
+
+    // ${!name} expands the variable whose name is the value of name
+    // ${!name[@]} expands to the indexes or keys of array name
+    pub indirect: bool,
+
+    pub operation: Operation,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    Value,
+
+    // ${#name}
+    Length,
+
+    // ${name-word} or ${name:-word} if `null` is true which also applies to an empty value
+    Default {
+        null: bool,
+        word: String,
+    },
+    Assign {
+        null: bool,
+        word: String,
+    },
+    Alternative {
+        null: bool,
+        word: String,
+    },
+    Error {
+        null: bool,
+        word: String,
+    },
+
+    // ${name#pattern} and ${name##pattern} if `longest` is true
+    RemovePrefix {
+        longest: bool,
+        pattern: String,
+    },
+    RemoveSuffix {
+        longest: bool,
+        pattern: String,
+    },
+
+    // ${name/pattern/replacement}, ${name//...}, ${name/#...} and ${name/%...}
+    Replace {
+        mode: ReplaceMode,
+        pattern: String,
+        replacement: String,
+    },
+
+    // ${name:offset} and ${name:offset:length}
+    Substring {
+        offset: String,
+        length: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaceMode {
+    First,
+    All,
+    Prefix,
+    Suffix,
+}
+
+// A piece of a field and whether its characters were quoted
+#[derive(Clone, Debug, PartialEq)]
+pub struct Piece {
+    pub text: String,
+    pub quoted: bool,
+}
+
+pub type Field = Vec<Piece>;
+
+// Value of a parameter before an operation is applied to it
+enum Value {
+    Unset,
+    Scalar(String),
+
+    // Value of $@ and $*, `joined` is true for $*
+    List { items: Vec<String>, joined: bool },
+}
+
+// Expand `words` into the fields that make up a command
+pub fn expand_words(words: &[String], shell: &mut Shell) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+
+    for word in words {
+        fields.extend(expand_word(word, shell)?);
+    }
+
+    Ok(fields)
+}
+
+// Expand one word, it can result in zero, one or more fields
+pub fn expand_word(word: &str, shell: &mut Shell) -> Result<Vec<String>, String> {
+    let parts = parse_word(word)?;
+    let parts = expand_tilde(parts, shell);
+    let parts = expand_parameters(parts, shell)?;
+    let parts = substitute_commands(parts, shell);
+    let parts = expand_arithmetic(parts, shell)?;
+    let fields = split_fields(parts, &ifs(shell));
+    let fields = expand_filenames(fields, shell.options.shopt("extglob"));
+
+    Ok(remove_quotes(fields))
+}
+
+// Expand a word without field splitting and filename expansion
+// Used for the value of assignments
+pub fn expand_string(word: &str, shell: &mut Shell) -> Result<String, String> {
+    let parts = parse_word(word)?;
+    let parts = expand_tilde(parts, shell);
+    let parts = expand_parameters(parts, shell)?;
+    let parts = substitute_commands(parts, shell);
+    let parts = expand_arithmetic(parts, shell)?;
+
+    Ok(join_parts(&parts, shell))
+}
+
+// Expand `text` as if it was inside double quotes
+fn expand_quoted(text: &str, shell: &mut Shell) -> Result<String, String> {
+    let parts = expand_operand(text, true, shell)?;
+
+    Ok(join_parts(&parts, shell))
+}
+
+// Expand a word used as a pattern, quoted pattern characters are escaped so they match literally
+pub fn expand_pattern(word: &str, shell: &mut Shell) -> Result<String, String> {
+    expand_escaping_quoted(word, shell, escape_pattern)
+}
+
+// Expand a word used as a regular expression, quoted characters match literally
+pub fn expand_regex(word: &str, shell: &mut Shell) -> Result<String, String> {
+    expand_escaping_quoted(word, shell, regex::escape)
+}
+
+// Expand a word without field splitting and filename expansion and escape its quoted parts with `escape`
+fn expand_escaping_quoted<F>(word: &str, shell: &mut Shell, escape: F) -> Result<String, String>
+where
+    F: Fn(&str) -> String,
+{
+    let parts = parse_word(word)?;
+    let parts = expand_tilde(parts, shell);
+    let parts = expand_parameters(parts, shell)?;
+    let parts = substitute_commands(parts, shell);
+    let parts = expand_arithmetic(parts, shell)?;
+
+    Ok(parts
+        .into_iter()
+        .map(|part| match part {
+            Part::Literal { text, quoted } | Part::Expanded { text, quoted } if quoted => {
+                escape(&text)
+            }
+            Part::Literal { text, .. } | Part::Expanded { text, .. } => text,
+            _ => String::new(),
+        })
+        .collect())
+}
+
+fn ifs(shell: &Shell) -> String {
+    match shell.vars.get("IFS") {
+        Some(ifs) => ifs.to_string(),
+        None => DEFAULT_IFS.to_string(),
+    }
+}
+
+// Parse a word into literal parts and the expansions it contains
+pub fn parse_word(word: &str) -> Result<Vec<Part>, String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut index = 0;
+
+    // A tilde prefix goes until the first slash
+    if chars.first() == Some(&'~') {
+        let end = chars.iter().position(|&c| c == '/').unwrap_or(chars.len());
+        let name: String = chars[1..end].iter().collect();
+
+        if !name.contains(['\'', '"', '\\', '$', '`']) {
+            parts.push(Part::Tilde(name));
+            index = end;
+        }
+    }
+
+    while index < chars.len() {
+        match chars[index] {
+            '\'' => {
+                let end = skip_single_quoted(&chars, index)?;
+                flush_literal(&mut literal, &mut parts);
+                parts.push(Part::Literal {
+                    text: chars[index + 1..end - 1].iter().collect(),
+                    quoted: true,
+                });
+                index = end;
+            }
+            '"' => {
+                let end = skip_double_quoted(&chars, index)?;
+                flush_literal(&mut literal, &mut parts);
+
+                let inner = parse_double_quoted(&chars[index + 1..end - 1])?;
+
+                // "" is an empty field and not nothing
+                if inner.is_empty() {
+                    parts.push(Part::Literal {
+                        text: String::new(),
+                        quoted: true,
+                    });
+                }
+                parts.extend(inner);
+                index = end;
+            }
+            '\\' => {
+                flush_literal(&mut literal, &mut parts);
+                match chars.get(index + 1) {
+                    // A backslash-newline pair is removed
+                    Some('\n') => {}
+                    Some(&escaped) => parts.push(Part::Literal {
+                        text: escaped.to_string(),
+                        quoted: true,
+                    }),
+                    None => literal.push('\\'),
+                }
+                index += 2;
+            }
+            '$' | '`' => match parse_expansion(&chars, index, false)? {
+                Some((part, end)) => {
+                    flush_literal(&mut literal, &mut parts);
+                    parts.push(part);
+                    index = end;
+                }
+                None => {
+                    literal.push(chars[index]);
+                    index += 1;
+                }
+            },
+            c => {
+                literal.push(c);
+                index += 1;
+            }
+        }
+    }
+
+    flush_literal(&mut literal, &mut parts);
+
+    Ok(parts)
+}
+
+// Parse the content of double quotes, every part of it is quoted
+fn parse_double_quoted(chars: &[char]) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        match chars[index] {
+            // Inside double quotes a backslash only escapes these chars
+            '\\' if matches!(
+                chars.get(index + 1),
+                Some('$') | Some('`') | Some('"') | Some('\\')
+            ) =>
+            {
+                literal.push(chars[index + 1]);
+                index += 2;
+            }
+            '\\' if chars.get(index + 1) == Some(&'\n') => index += 2,
+            '$' | '`' => match parse_expansion(chars, index, true)? {
+                Some((part, end)) => {
+                    flush_quoted_literal(&mut literal, &mut parts);
+                    parts.push(part);
+                    index = end;
+                }
+                None => {
+                    literal.push(chars[index]);
+                    index += 1;
+                }
+            },
+            c => {
+                literal.push(c);
+                index += 1;
+            }
+        }
+    }
+
+    flush_quoted_literal(&mut literal, &mut parts);
+
+    Ok(parts)
+}
+
+fn flush_literal(literal: &mut String, parts: &mut Vec<Part>) {
+    if !literal.is_empty() {
+        parts.push(Part::Literal {
+            text: std::mem::take(literal),
+            quoted: false,
+        });
+    }
+}
+
+fn flush_quoted_literal(literal: &mut String, parts: &mut Vec<Part>) {
+    if !literal.is_empty() {
+        parts.push(Part::Literal {
+            text: std::mem::take(literal),
+            quoted: true,
+        });
+    }
+}
+
+// Parse the expansion starting with the `$` or backquote at `start`
+// Returns the parsed part and the index after it or None if the `$` is a literal dollar sign
+fn parse_expansion(
+    chars: &[char],
+    start: usize,
+    quoted: bool,
+) -> Result<Option<(Part, usize)>, String> {
+    if chars[start] == '`' {
+        let end = skip_backquoted(chars, start)?;
+        let source = unescape_backquoted(&chars[start + 1..end - 1]);
+
+        return Ok(Some((Part::Command { source, quoted }, end)));
+    }
+
+    match chars.get(start + 1) {
+        Some('(') => {
+            let end = skip_dollar(chars, start)?;
+
+            // $((...)) is an arithmetic expansion unless it is a command substitution of a subshell
+            if chars.get(start + 2) == Some(&'(')
+                && chars[end - 2] == ')'
+                && arithmetic_end(chars, start + 3) == Some(end - 2)
+            {
+                let expression = chars[start + 3..end - 2].iter().collect();
+                return Ok(Some((Part::Arithmetic { expression, quoted }, end)));
+            }
+
+            let source = chars[start + 2..end - 1].iter().collect();
+            Ok(Some((Part::Command { source, quoted }, end)))
+        }
+        Some('{') => {
+            let end = skip_dollar(chars, start)?;
+            let inner: String = chars[start + 2..end - 1].iter().collect();
+            let expression = parse_parameter(&inner)?;
+
+            Ok(Some((Part::Parameter { expression, quoted }, end)))
+        }
+        Some(&c) if c == '_' || c.is_ascii_alphabetic() => {
+            let mut end = start + 1;
+            while end < chars.len() && (chars[end] == '_' || chars[end].is_ascii_alphanumeric()) {
+                end += 1;
+            }
+            let name = chars[start + 1..end].iter().collect();
+
+            Ok(Some((parameter_part(name, quoted), end)))
+        }
+        Some(&c) if c.is_ascii_digit() || is_special_parameter(c) => {
+            Ok(Some((parameter_part(c.to_string(), quoted), start + 2)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn parameter_part(name: String, quoted: bool) -> Part {
+    Part::Parameter {
+        expression: Parameter {
+            name,
+            subscript: None,
+            indirect: false,
+            operation: Operation::Value,
+        },
+        quoted,
+    }
+}
+
+// Index of the first `)` of the `))` closing an arithmetic expansion whose content starts at `start`
+fn arithmetic_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+
+    for (index, &c) in chars.iter().enumerate().skip(start) {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                return if chars.get(index + 1) == Some(&')') {
+                    Some(index)
+                } else {
+                    None
+                };
+            }
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// Inside backquotes a backslash only escapes $, ` and \
+fn unescape_backquoted(chars: &[char]) -> String {
+    let mut source = String::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        if chars[index] == '\\'
+            && matches!(chars.get(index + 1), Some('$') | Some('`') | Some('\\'))
+        {
+            index += 1;
+        }
+        source.push(chars[index]);
+        index += 1;
+    }
+
+    source
+}
+
+fn is_special_parameter(c: char) -> bool {
+    matches!(c, '@' | '*' | '#' | '?' | '$' | '!' | '-' | '0')
+}
+
+// Parse the content of ${...}
+fn parse_parameter(inner: &str) -> Result<Parameter, String> {
+    let bad_substitution = || format!("${{{}}}: bad substitution", inner);
+
+    // ${#name} is the length of name but ${#} alone is the number of positional parameters
+    if let Some(rest) = inner.strip_prefix('#') {
+        if let Some((name, subscript, "")) = split_parameter_name(rest) {
+            return Ok(Parameter {
+                name,
+                subscript,
+                indirect: false,
+                operation: Operation::Length,
+            });
+        }
+    }
+
+    // ${!name} is an indirect expansion but ${!} alone is the pid of the last background command
+    let (indirect, name_and_rest) = match inner.strip_prefix('!') {
+        Some(rest) if !rest.is_empty() => (true, rest),
+        _ => (false, inner),
+    };
+
+    let (name, subscript, rest) =
+        split_parameter_name(name_and_rest).ok_or_else(bad_substitution)?;
+
+    let operation = if rest.is_empty() {
+        Operation::Value
+    } else if let Some(operation) = parse_word_operation(rest) {
+        operation
+    } else if let Some(pattern) = rest.strip_prefix("##") {
+        Operation::RemovePrefix {
+            longest: true,
+            pattern: pattern.to_string(),
+        }
+    } else if let Some(pattern) = rest.strip_prefix('#') {
+        Operation::RemovePrefix {
+            longest: false,
+            pattern: pattern.to_string(),
+        }
+    } else if let Some(pattern) = rest.strip_prefix("%%") {
+        Operation::RemoveSuffix {
+            longest: true,
+            pattern: pattern.to_string(),
+        }
+    } else if let Some(pattern) = rest.strip_prefix('%') {
+        Operation::RemoveSuffix {
+            longest: false,
+            pattern: pattern.to_string(),
+        }
+    } else if let Some(rest) = rest.strip_prefix('/') {
+        let (mode, rest) = match rest.chars().next() {
+            Some('/') => (ReplaceMode::All, &rest[1..]),
+            Some('#') => (ReplaceMode::Prefix, &rest[1..]),
+            Some('%') => (ReplaceMode::Suffix, &rest[1..]),
+            _ => (ReplaceMode::First, rest),
+        };
+
+        let (pattern, replacement) = split_unescaped(rest, '/');
+        Operation::Replace {
+            mode,
+            pattern,
+            replacement: replacement.unwrap_or_default(),
+        }
+    } else if let Some(rest) = rest.strip_prefix(':') {
+        let (offset, length) = split_unescaped(rest, ':');
+        Operation::Substring { offset, length }
+    } else {
+        return Err(bad_substitution());
+    };
+
+    Ok(Parameter {
+        name,
+        subscript,
+        indirect,
+        operation,
+    })
+}
+
+// Parse operations like :-word and +word
+fn parse_word_operation(rest: &str) -> Option<Operation> {
+    let (null, rest) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+
+    let mut chars = rest.chars();
+    let operator = chars.next()?;
+    let word = chars.as_str().to_string();
+
+    match operator {
+        '-' => Some(Operation::Default { null, word }),
+        '=' => Some(Operation::Assign { null, word }),
+        '+' => Some(Operation::Alternative { null, word }),
+        '?' => Some(Operation::Error { null, word }),
+        _ => None,
+    }
+}
+
+// Split `text` at the first `separator` that is not escaped or quoted
+fn split_unescaped(text: &str, separator: char) -> (String, Option<String>) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut index = 0;
+
+    while index < chars.len() {
+        if chars[index] == separator {
+            let before = chars[..index].iter().collect();
+            let after = chars[index + 1..].iter().collect();
+            return (before, Some(after));
+        }
+
+        index = crate::lexer::skip_quoted(&chars, index).unwrap_or(chars.len());
+    }
+
+    (text.to_string(), None)
+}
+
+// Length of the parameter name at the start of `text`
+fn parameter_name_len(text: &str) -> usize {
+    let mut chars = text.chars();
+
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            1 + chars
+                .take_while(|&c| c == '_' || c.is_ascii_alphanumeric())
+                .count()
+        }
+        Some(c) if c.is_ascii_digit() => 1 + chars.take_while(|c| c.is_ascii_digit()).count(),
+        Some(c) if is_special_parameter(c) => 1,
+        _ => 0,
+    }
+}
+
+// Split the parameter name and the optional subscript at the start of `text` from the rest of it
+fn split_parameter_name(text: &str) -> Option<(String, Option<String>, &str)> {
+    let name_len = parameter_name_len(text);
+    if name_len == 0 {
+        return None;
+    }
+
+    let (name, rest) = text.split_at(name_len);
+
+    if !rest.starts_with('[') || !is_valid_name(name) {
+        return Some((name.to_string(), None, rest));
+    }
+
+    // Find the `]` that closes the subscript, subscripts can contain other subscripts like a[b[0]]
+    let mut depth = 0;
+    for (index, c) in rest.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((
+                        name.to_string(),
+                        Some(rest[1..index].to_string()),
+                        &rest[index + 1..],
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// Stage 1: replace ~ with $HOME, ~+ and ~- with $PWD and $OLDPWD and ~name with the home
+// directory of user name
+pub fn expand_tilde(parts: Vec<Part>, shell: &Shell) -> Vec<Part> {
+    parts
+        .into_iter()
+        .map(|part| match part {
+            Part::Tilde(name) => {
+                let directory = match name.as_str() {
+                    "" => shell
+                        .vars
+                        .get("HOME")
+                        .or(shell.user.home.as_deref())
+                        .map(String::from),
+                    "+" => shell.vars.get("PWD").map(String::from),
+                    "-" => shell.vars.get("OLDPWD").map(String::from),
+                    name => user::home_of(name),
+                };
+
+                match directory {
+                    Some(directory) => Part::Expanded {
+                        text: directory,
+                        quoted: true,
+                    },
+                    None => Part::Literal {
+                        text: format!("~{}", name),
+                        quoted: false,
+                    },
+                }
+            }
+            part => part,
+        })
+        .collect()
+}
+
+// Stage 2: expand the parameters
+pub fn expand_parameters(parts: Vec<Part>, shell: &mut Shell) -> Result<Vec<Part>, String> {
+    let mut expanded = Vec::new();
+
+    for part in parts {
+        match part {
+            Part::Parameter { expression, quoted } => {
+                expanded.extend(expand_parameter(&expression, quoted, shell)?);
+            }
+            part => expanded.push(part),
+        }
+    }
+
+    Ok(expanded)
+}
+
+// Stage 3: replace command substitutions with the output of the commands
+pub fn substitute_commands(parts: Vec<Part>, shell: &mut Shell) -> Vec<Part> {
+    parts
+        .into_iter()
+        .map(|part| match part {
+            Part::Command { source, quoted } => {
+                let mut text = capture_output(&source, shell);
+
+                // Trailing newlines are removed from the output
+                let trimmed_len = text.trim_end_matches('\n').len();
+                text.truncate(trimmed_len);
+
+                Part::Expanded { text, quoted }
+            }
+            part => part,
+        })
+        .collect()
+}
+
+// Stage 4: evaluate the arithmetic expressions
+pub fn expand_arithmetic(parts: Vec<Part>, shell: &mut Shell) -> Result<Vec<Part>, String> {
+    let mut expanded = Vec::new();
+
+    for part in parts {
+        match part {
+            Part::Arithmetic { expression, quoted } => {
+                // The expression is expanded like inside double quotes before it is evaluated
+                let expression = expand_quoted(&expression, shell)?;
+                let value = arith::evaluate(&expression, &mut shell.vars)?;
+
+                expanded.push(Part::Expanded {
+                    text: value.to_string(),
+                    quoted,
+                });
+            }
+            part => expanded.push(part),
+        }
+    }
+
+    Ok(expanded)
+}
+
+// Stage 5: split the unquoted results of expansions into fields
+// White space characters of `ifs` separate fields and are ignored at the start and the end
+// Any other character of `ifs` separates two fields, so it can delimit an empty field
+pub fn split_fields(parts: Vec<Part>, ifs: &str) -> Vec<Field> {
+    let mut fields = Vec::new();
+    let mut field = Field::new();
+
+    // A field exists once it has a char or a quoted part, even an empty one like ""
+    let mut started = false;
+
+    // Whether the last field was ended by white space
+    // A non white space separator that follows it belongs to the same separator
+    let mut ended_by_white_space = false;
+
+    for part in parts {
+        match part {
+            Part::Expanded {
+                text,
+                quoted: false,
+            } => {
+                for c in text.chars() {
+                    if !ifs.contains(c) {
+                        push_char(&mut field, c);
+                        started = true;
+                        ended_by_white_space = false;
+                    } else if is_ifs_white_space(c) {
+                        if started {
+                            fields.push(std::mem::take(&mut field));
+                            started = false;
+                            ended_by_white_space = true;
+                        }
+                    } else if ended_by_white_space {
+                        ended_by_white_space = false;
+                    } else {
+                        fields.push(std::mem::take(&mut field));
+                        started = false;
+                    }
+                }
+            }
+            Part::Literal { text, quoted } | Part::Expanded { text, quoted } => {
+                started = started || quoted || !text.is_empty();
+                ended_by_white_space = false;
+                field.push(Piece { text, quoted });
+            }
+            Part::FieldBreak => {
+                if started {
+                    fields.push(std::mem::take(&mut field));
+                    started = false;
+                }
+                field.clear();
+                ended_by_white_space = false;
+            }
+            _ => {}
+        }
+    }
+
+    if started {
+        fields.push(field);
+    }
+
+    fields
+}
+
+fn is_ifs_white_space(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n'
+}
+
+// Append an unquoted char to the last piece of `field`
+fn push_char(field: &mut Field, c: char) {
+    match field.last_mut() {
+        Some(piece) if !piece.quoted => piece.text.push(c),
+        _ => field.push(Piece {
+            text: c.to_string(),
+            quoted: false,
+        }),
+    }
+}
+
+// Stage 6: replace each field that is a pattern with the paths it matches
+// A pattern that matches nothing is kept as it is
+pub fn expand_filenames(fields: Vec<Field>, extglob: bool) -> Vec<Field> {
+    let mut expanded = Vec::new();
+
+    for field in fields {
+        let pattern: String = field
+            .iter()
+            .map(|piece| {
+                if piece.quoted {
+                    escape_pattern(&piece.text)
+                } else {
+                    piece.text.clone()
+                }
+            })
+            .collect();
+
+        if !pattern::has_magic(&pattern, extglob) {
+            expanded.push(field);
+            continue;
+        }
+
+        let paths = pattern::expand(&pattern, extglob);
+
+        if paths.is_empty() {
+            expanded.push(field);
+        } else {
+            // Paths are not subject to further expansion
+            expanded.extend(paths.into_iter().map(|path| {
+                vec![Piece {
+                    text: path,
+                    quoted: true,
+                }]
+            }));
+        }
+    }
+
+    expanded
+}
+
+// Stage 7: join the pieces of each field into its final text
+pub fn remove_quotes(fields: Vec<Field>) -> Vec<String> {
+    fields
+        .into_iter()
+        .map(|field| field.into_iter().map(|piece| piece.text).collect())
+        .collect()
+}
+
+// Escape the chars of `text` that have a special meaning in patterns
+pub fn escape_pattern(text: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in text.chars() {
+        if matches!(
+            c,
+            '*' | '?' | '[' | ']' | '\\' | '(' | ')' | '|' | '!' | '@' | '+'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+// Join expanded parts into one string, the fields of "$@" are separated by the first char of
+// IFS, a space when IFS is unset, and joined without separator when IFS is empty
+fn join_parts(parts: &[Part], shell: &Shell) -> String {
+    let separator = ifs(shell)
+        .chars()
+        .next()
+        .map(|c| c.to_string())
+        .unwrap_or_default();
+
+    parts
+        .iter()
+        .map(|part| match part {
+            Part::Literal { text, .. } | Part::Expanded { text, .. } => text.as_str(),
+            Part::FieldBreak => separator.as_str(),
+            _ => "",
+        })
+        .collect()
+}
+
+// Value of a parameter, following indirections and array subscripts
+fn lookup(expression: &Parameter, shell: &mut Shell) -> Result<Value, String> {
+    let name = expression.name.as_str();
+    let subscript = expression.subscript.as_deref();
+    let all_elements = matches!(subscript, Some("@") | Some("*"));
+
+    if expression.indirect {
+        // ${!name[@]} lists the indexes or keys of the array
+        if all_elements {
+            return Ok(Value::List {
+                items: shell.vars.keys(name),
+                joined: subscript == Some("*"),
+            });
+        }
+
+        // The value of name is the name of the expanded parameter, it can have a subscript
+        let target = match lookup_element(name, subscript, shell)? {
+            Value::Scalar(target) => target,
+            _ => return Ok(Value::Unset),
+        };
+
+        let (name, subscript, rest) = match split_parameter_name(&target) {
+            Some(split) => split,
+            None => return Err(format!("{}: invalid indirect expansion", target)),
+        };
+        if !rest.is_empty() {
+            return Err(format!("{}: invalid variable name", target));
+        }
+
+        return lookup_element(&name, subscript.as_deref(), shell);
+    }
+
+    lookup_element(name, subscript, shell)
+}
+
+fn lookup_element(name: &str, subscript: Option<&str>, shell: &mut Shell) -> Result<Value, String> {
+    let scalar = |value: Option<&str>| match value {
+        Some(value) => Value::Scalar(value.to_string()),
+        None => Value::Unset,
+    };
+
+    let subscript = match subscript {
+        Some(subscript) => subscript,
+        None => return Ok(lookup_parameter(name, shell)),
+    };
+
+    if subscript == "@" || subscript == "*" {
+        return Ok(Value::List {
+            items: shell.vars.items(name),
+            joined: subscript == "*",
+        });
+    }
+
+    if shell.vars.is_associative(name) {
+        let key = expand_string(subscript, shell)?;
+        return Ok(scalar(shell.vars.get_associative(name, &key)));
+    }
+
+    match element_index(name, subscript, shell)? {
+        Some(index) => Ok(scalar(shell.vars.get_indexed(name, index))),
+        None => Ok(Value::Unset),
+    }
+}
+
+// Evaluate the subscript of an indexed array, negative indexes count back from the end
+// Returns None if the index is before the first element
+pub fn element_index(
+    name: &str,
+    subscript: &str,
+    shell: &mut Shell,
+) -> Result<Option<usize>, String> {
+    let index = arith::evaluate(&expand_quoted(subscript, shell)?, &mut shell.vars)?;
+
+    if index >= 0 {
+        return Ok(Some(index as usize));
+    }
+
+    let index = shell.vars.next_index(name) as i64 + index;
+    if index < 0 {
+        Ok(None)
+    } else {
+        Ok(Some(index as usize))
+    }
+}
+
+// Value of a variable or special parameter without subscript
+fn lookup_parameter(name: &str, shell: &Shell) -> Value {
+    let scalar = |value: String| Value::Scalar(value);
+
+    match name {
+        "@" | "*" => Value::List {
+            items: shell.vars.positional.clone(),
+            joined: name == "*",
+        },
+        "#" => scalar(shell.vars.positional.len().to_string()),
+        "?" => scalar(shell.last_status.to_string()),
+        "$" => scalar(shell.pid.to_string()),
+        "0" => scalar(shell.name.clone()),
+        "-" => scalar(String::new()),
+        "!" => match shell.last_background {
+            Some(pid) => scalar(pid.to_string()),
+            None => Value::Unset,
+        },
+        _ if name.chars().all(|c| c.is_ascii_digit()) => {
+            let index: usize = name.parse().unwrap_or(0);
+            match shell.vars.positional.get(index.wrapping_sub(1)) {
+                Some(value) => scalar(value.clone()),
+                None => Value::Unset,
+            }
+        }
+        _ => match shell.vars.get(name) {
+            Some(value) => scalar(value.to_string()),
+            None => Value::Unset,
+        },
+    }
+}
+
+// Expand the word of an operation like ${name:-word} as if it was written in place of the expansion
+fn expand_operand(word: &str, quoted: bool, shell: &mut Shell) -> Result<Vec<Part>, String> {
+    let parts = if quoted {
+        parse_double_quoted(&word.chars().collect::<Vec<char>>())?
+    } else {
+        parse_word(word)?
+    };
+
+    let parts = expand_tilde(parts, shell);
+    let parts = expand_parameters(parts, shell)?;
+    let parts = substitute_commands(parts, shell);
+
+    expand_arithmetic(parts, shell)
+}
+
+fn expand_parameter(
+    expression: &Parameter,
+    quoted: bool,
+    shell: &mut Shell,
+) -> Result<Vec<Part>, String> {
+    let name = expression.name.as_str();
+    let value = lookup(expression, shell)?;
+
+    // Whether the parameter is set and, if `null` is true, also not empty
+    let is_set = |null: bool| match &value {
+        Value::Unset => false,
+        Value::Scalar(text) => !null || !text.is_empty(),
+        Value::List { items, .. } => {
+            !items.is_empty() && (!null || items.iter().any(|item| !item.is_empty()))
+        }
+    };
+
+    let value = match &expression.operation {
+        Operation::Value => value,
+        Operation::Length => {
+            let length = match &value {
+                Value::Unset => 0,
+                Value::Scalar(text) => text.chars().count(),
+                Value::List { items, .. } => items.len(),
+            };
+            Value::Scalar(length.to_string())
+        }
+        Operation::Default { null, word } => {
+            if !is_set(*null) {
+                return expand_operand(word, quoted, shell);
+            }
+            value
+        }
+        Operation::Assign { null, word } => {
+            if !is_set(*null) {
+                if !is_valid_name(name) || expression.indirect {
+                    return Err(format!("${}: cannot assign in this way", name));
+                }
+
+                let parts = expand_operand(word, quoted, shell)?;
+                let text = join_parts(&parts, shell);
+                match expression.subscript.as_deref() {
+                    None => shell.vars.set(name, text.clone()),
+                    Some("@") | Some("*") => return Err(format!("{}: bad array subscript", name)),
+                    Some(subscript) if shell.vars.is_associative(name) => {
+                        let key = expand_string(subscript, shell)?;
+                        shell.vars.set_associative(name, key, text.clone());
+                    }
+                    Some(subscript) => match element_index(name, subscript, shell)? {
+                        Some(index) => shell.vars.set_indexed(name, index, text.clone()),
+                        None => return Err(format!("{}: bad array subscript", name)),
+                    },
+                }
+                Value::Scalar(text)
+            } else {
+                value
+            }
+        }
+        Operation::Alternative { null, word } => {
+            if is_set(*null) {
+                return expand_operand(word, quoted, shell);
+            }
+            Value::Unset
+        }
+        Operation::Error { null, word } => {
+            if !is_set(*null) {
+                let message = if word.is_empty() {
+                    String::from("parameter null or not set")
+                } else {
+                    expand_string(word, shell)?
+                };
+                return Err(format!("{}: {}", name, message));
+            }
+            value
+        }
+        Operation::RemovePrefix { longest, pattern } => {
+            let pattern = Pattern::new(
+                &expand_pattern(pattern, shell)?,
+                shell.options.shopt("extglob"),
+            );
+            map_value(value, |text| remove_prefix(text, &pattern, *longest))
+        }
+        Operation::RemoveSuffix { longest, pattern } => {
+            let pattern = Pattern::new(
+                &expand_pattern(pattern, shell)?,
+                shell.options.shopt("extglob"),
+            );
+            map_value(value, |text| remove_suffix(text, &pattern, *longest))
+        }
+        Operation::Replace {
+            mode,
+            pattern,
+            replacement,
+        } => {
+            let pattern = Pattern::new(
+                &expand_pattern(pattern, shell)?,
+                shell.options.shopt("extglob"),
+            );
+            let replacement = expand_string(replacement, shell)?;
+            map_value(value, |text| replace(text, &pattern, &replacement, *mode))
+        }
+        Operation::Substring { offset, length } => {
+            let offset = arith::evaluate(&expand_quoted(offset, shell)?, &mut shell.vars)?;
+            let length = match length {
+                Some(length) => Some(arith::evaluate(
+                    &expand_quoted(length, shell)?,
+                    &mut shell.vars,
+                )?),
+                None => None,
+            };
+
+            match value {
+                Value::Unset => Value::Unset,
+                Value::Scalar(text) => {
+                    let chars: Vec<char> = text.chars().collect();
+                    Value::Scalar(slice(&chars, offset, length, name)?.iter().collect())
+                }
+                Value::List { items, joined }
+                    if expression.subscript.is_none() && !expression.indirect =>
+                {
+                    // Positional parameters are sliced starting from $0
+                    let mut all = vec![shell.name.clone()];
+                    all.extend(items);
+                    Value::List {
+                        items: slice(&all, offset, length, name)?.to_vec(),
+                        joined,
+                    }
+                }
+                Value::List { items, joined }
+                    if !expression.indirect && !shell.vars.is_associative(name) =>
+                {
+                    Value::List {
+                        items: slice_elements(name, &items, offset, length, shell)?,
+                        joined,
+                    }
+                }
+                Value::List { items, joined } => Value::List {
+                    items: slice(&items, offset, length, name)?.to_vec(),
+                    joined,
+                },
+            }
+        }
+    };
+
+    Ok(value_parts(value, quoted, shell))
+}
+
+// Apply `operation` to a scalar value or to each item of a list
+fn map_value<F>(value: Value, operation: F) -> Value
+where
+    F: Fn(&str) -> String,
+{
+    match value {
+        Value::Unset => Value::Unset,
+        Value::Scalar(text) => Value::Scalar(operation(&text)),
+        Value::List { items, joined } => Value::List {
+            items: items.iter().map(|item| operation(item)).collect(),
+            joined,
+        },
+    }
+}
+
+// Convert a value into parts
+// "$@" is one field per item while "$*" joins the items with the first char of $IFS
+fn value_parts(value: Value, quoted: bool, shell: &Shell) -> Vec<Part> {
+    match value {
+        Value::Unset => Vec::new(),
+        Value::Scalar(text) => vec![Part::Expanded { text, quoted }],
+        Value::List { items, joined } if joined && quoted => {
+            let separator: String = ifs(shell).chars().take(1).collect();
+            vec![Part::Expanded {
+                text: items.join(&separator),
+                quoted,
+            }]
+        }
+        Value::List { items, .. } => {
+            let mut parts = Vec::new();
+            for (index, text) in items.into_iter().enumerate() {
+                if index > 0 {
+                    parts.push(Part::FieldBreak);
+                }
+                parts.push(Part::Expanded { text, quoted });
+            }
+            parts
+        }
+    }
+}
+
+// Take `length` items from `offset`, negative values count from the end
+fn slice<'a, T>(
+    items: &'a [T],
+    offset: i64,
+    length: Option<i64>,
+    name: &str,
+) -> Result<&'a [T], String> {
+    let count = items.len() as i64;
+
+    let start = if offset < 0 { count + offset } else { offset };
+    if start < 0 || start > count {
+        return Ok(&items[0..0]);
+    }
+
+    let end = match length {
+        None => count,
+        Some(length) if length < 0 => {
+            let end = count + length;
+            if end < start {
+                return Err(format!("{}: substring expression < 0", name));
+            }
+            end
+        }
+        Some(length) => std::cmp::min(start + length, count),
+    };
+
+    Ok(&items[start as usize..end as usize])
+}
+
+// Take `length` of the `items` of indexed array `name` whose index is at least `offset`
+// A negative offset counts back from the index after the last element
+fn slice_elements(
+    name: &str,
+    items: &[String],
+    offset: i64,
+    length: Option<i64>,
+    shell: &Shell,
+) -> Result<Vec<String>, String> {
+    let start = if offset < 0 {
+        shell.vars.next_index(name) as i64 + offset
+    } else {
+        offset
+    };
+    if start < 0 {
+        return Ok(Vec::new());
+    }
+
+    let first = shell
+        .vars
+        .keys(name)
+        .iter()
+        .position(|index| index.parse::<i64>().is_ok_and(|index| index >= start))
+        .unwrap_or(items.len());
+
+    Ok(slice(&items[first..], 0, length, name)?.to_vec())
+}
+
+fn remove_prefix(text: &str, pattern: &Pattern, longest: bool) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut ends: Vec<usize> = (0..=chars.len()).collect();
+    if longest {
+        ends.reverse();
+    }
+
+    match ends
+        .into_iter()
+        .find(|&end| pattern.matches_chars(&chars[..end]))
+    {
+        Some(end) => chars[end..].iter().collect(),
+        None => text.to_string(),
+    }
+}
+
+fn remove_suffix(text: &str, pattern: &Pattern, longest: bool) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut starts: Vec<usize> = (0..=chars.len()).collect();
+    if !longest {
+        starts.reverse();
+    }
+
+    match starts
+        .into_iter()
+        .find(|&start| pattern.matches_chars(&chars[start..]))
+    {
+        Some(start) => chars[..start].iter().collect(),
+        None => text.to_string(),
+    }
+}
+
+// Replace the longest matches of `pattern` in `text`
+fn replace(text: &str, pattern: &Pattern, replacement: &str, mode: ReplaceMode) -> String {
+    let chars: Vec<char> = text.chars().collect();
+
+    // End of the longest match starting at `start`
+    let longest_match = |start: usize| {
+        (start..=chars.len())
+            .rev()
+            .find(|&end| pattern.matches_chars(&chars[start..end]))
+    };
+
+    match mode {
+        ReplaceMode::Prefix => match longest_match(0) {
+            Some(end) => format!("{}{}", replacement, chars[end..].iter().collect::<String>()),
+            None => text.to_string(),
+        },
+        ReplaceMode::Suffix => {
+            match (0..=chars.len()).find(|&start| pattern.matches_chars(&chars[start..])) {
+                Some(start) => format!(
+                    "{}{}",
+                    chars[..start].iter().collect::<String>(),
+                    replacement
+                ),
+                None => text.to_string(),
+            }
+        }
+        ReplaceMode::First | ReplaceMode::All => {
+            let mut replaced = String::new();
+            let mut index = 0;
+            let mut replaced_once = false;
+
+            while index < chars.len() {
+                let found = if replaced_once && mode == ReplaceMode::First {
+                    None
+                } else {
+                    // Empty matches are not replaced
+                    longest_match(index).filter(|&end| end > index)
+                };
+
+                match found {
+                    Some(end) => {
+                        replaced.push_str(replacement);
+                        index = end;
+                        replaced_once = true;
+                    }
+                    None => {
+                        replaced.push(chars[index]);
+                        index += 1;
+                    }
+                }
+            }
+
+            replaced
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{shell, TempDir};
+
+    fn expanded(text: &str, quoted: bool) -> Part {
+        Part::Expanded {
+            text: text.to_string(),
+            quoted,
+        }
+    }
+
+    fn literal(text: &str, quoted: bool) -> Part {
+        Part::Literal {
+            text: text.to_string(),
+            quoted,
+        }
+    }
+
+    fn piece(text: &str, quoted: bool) -> Piece {
+        Piece {
+            text: text.to_string(),
+            quoted,
+        }
+    }
+
+    fn unquoted_field(text: &str) -> Field {
+        vec![piece(text, false)]
+    }
+
+    #[test]
+    fn tilde() {
+        let mut shell = shell();
+        shell.vars.set("HOME", String::from("/home/fsh"));
+        shell.vars.set("OLDPWD", String::from("/tmp"));
+
+        let parts = expand_tilde(parse_word("~/src").unwrap(), &shell);
+        assert_eq!(
+            parts,
+            vec![expanded("/home/fsh", true), literal("/src", false)]
+        );
+
+        let parts = expand_tilde(parse_word("~-").unwrap(), &shell);
+        assert_eq!(parts, vec![expanded("/tmp", true)]);
+
+        // Only a tilde at the start of an unquoted word is expanded
+        let parts = expand_tilde(parse_word("'~'/a~").unwrap(), &shell);
+        assert_eq!(parts, vec![literal("~", true), literal("/a~", false)]);
+
+        // A user that does not exist is kept as it is
+        let parts = expand_tilde(parse_word("~no-such-user-fsh").unwrap(), &shell);
+        assert_eq!(parts, vec![literal("~no-such-user-fsh", false)]);
+    }
+
+    #[test]
+    fn parameters() {
+        let mut shell = shell();
+        shell.vars.set("file", String::from("main.rs"));
+
+        let parts = expand_parameters(parse_word("${file%.rs}").unwrap(), &mut shell).unwrap();
+        assert_eq!(parts, vec![expanded("main", false)]);
+
+        let parts = expand_parameters(parse_word("\"$file\"").unwrap(), &mut shell).unwrap();
+        assert_eq!(parts, vec![expanded("main.rs", true)]);
+
+        let parts =
+            expand_parameters(parse_word("${unset_fsh_var:-a b}").unwrap(), &mut shell).unwrap();
+        assert_eq!(parts, vec![literal("a b", false)]);
+
+        let parts = expand_parameters(parse_word("${#file}").unwrap(), &mut shell).unwrap();
+        assert_eq!(parts, vec![expanded("7", false)]);
+
+        assert!(
+            expand_parameters(parse_word("${unset_fsh_var?not set}").unwrap(), &mut shell).is_err()
+        );
+    }
+
+    // Expand the words of `line` after `setup` ran in the same shell
+    fn expand_line(setup: &str, line: &str) -> Vec<String> {
+        let mut shell = shell();
+        assert_eq!(crate::process::run_line(setup, &mut shell), 0);
+
+        expand_words(&crate::lexer::split_words(line).unwrap(), &mut shell).unwrap()
+    }
+
+    #[test]
+    fn indexed_arrays() {
+        let setup = "a=(x 'y z' w)";
+        assert_eq!(
+            expand_line(setup, "${a[1]} ${a[-1]} ${#a[@]}"),
+            ["y", "z", "w", "3"]
+        );
+        assert_eq!(expand_line(setup, "\"${a[@]}\""), ["x", "y z", "w"]);
+        assert_eq!(expand_line(setup, "\"${a[*]}\""), ["x y z w"]);
+        assert_eq!(expand_line(setup, "${!a[@]}"), ["0", "1", "2"]);
+        assert_eq!(expand_line(setup, "\"${a[@]:1}\""), ["y z", "w"]);
+        assert_eq!(expand_line(setup, "\"${a[@]: -2:1}\""), ["y z"]);
+        assert_eq!(expand_line("a=(x y); a+=(z)", "${a[@]}"), ["x", "y", "z"]);
+    }
+
+    #[test]
+    fn sparse_arrays() {
+        // Elements are sliced by index, not by position
+        let setup = "a=([1]=x [5]=y [9]=z)";
+        assert_eq!(expand_line(setup, "${a[@]:2}"), ["y", "z"]);
+        assert_eq!(expand_line(setup, "${a[@]:5:1}"), ["y"]);
+        assert_eq!(expand_line(setup, "${a[@]:0:2}"), ["x", "y"]);
+        assert_eq!(expand_line(setup, "${a[@]: -5}"), ["y", "z"]);
+        assert!(expand_line(setup, "${a[@]:10}").is_empty());
+        assert!(expand_line(setup, "${a[@]: -20}").is_empty());
+        assert_eq!(
+            expand_line(setup, "${!a[@]} ${#a[@]}"),
+            ["1", "5", "9", "3"]
+        );
+        assert_eq!(
+            expand_line("a=([1]=x [5]=y); unset 'a[1]'", "${a[@]}"),
+            ["y"]
+        );
+    }
+
+    #[test]
+    fn associative_arrays() {
+        let setup = "declare -A m=([one]=1 [\"t w o\"]=2); m[three]=3";
+        assert_eq!(expand_line(setup, "${m[one]} \"${m[t w o]}\""), ["1", "2"]);
+        assert_eq!(
+            expand_line(setup, "\"${!m[@]}\""),
+            ["one", "t w o", "three"]
+        );
+        assert_eq!(expand_line(setup, "${m[@]} ${#m[@]}"), ["1", "2", "3", "3"]);
+        assert_eq!(expand_line(setup, "${m[missing]:-none}"), ["none"]);
+
+        // Associative arrays are sliced by position
+        assert_eq!(expand_line(setup, "${m[@]:1:1}"), ["2"]);
+    }
+
+    #[test]
+    fn command_substitution() {
+        let mut shell = shell();
+
+        // Trailing new lines of the output are removed
+        let parts = substitute_commands(parse_word("$(printf 'a\\n\\n\\n')").unwrap(), &mut shell);
+        assert_eq!(parts, vec![expanded("a", false)]);
+
+        let parts = substitute_commands(parse_word("\"`printf b`\"").unwrap(), &mut shell);
+        assert_eq!(parts, vec![expanded("b", true)]);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut shell = shell();
+        shell.vars.set("x", String::from("4"));
+
+        let parts = expand_arithmetic(parse_word("$((1 + 2 * 3))").unwrap(), &mut shell).unwrap();
+        assert_eq!(parts, vec![expanded("7", false)]);
+
+        let parts = expand_arithmetic(parse_word("\"$((x * 2))\"").unwrap(), &mut shell).unwrap();
+        assert_eq!(parts, vec![expanded("8", true)]);
+
+        assert!(expand_arithmetic(parse_word("$((1 / 0))").unwrap(), &mut shell).is_err());
+    }
+
+    #[test]
+    fn split_on_white_space() {
+        let fields = split_fields(vec![expanded("  a \t b\n", false)], DEFAULT_IFS);
+        assert_eq!(fields, vec![unquoted_field("a"), unquoted_field("b")]);
+
+        // Quoted results are not split
+        let fields = split_fields(vec![expanded(" a b ", true)], DEFAULT_IFS);
+        assert_eq!(fields, vec![vec![piece(" a b ", true)]]);
+
+        // An empty unquoted result is no field, an empty quoted one is an empty field
+        assert_eq!(
+            split_fields(vec![expanded("", false)], DEFAULT_IFS),
+            Vec::<Field>::new()
+        );
+        assert_eq!(
+            split_fields(vec![expanded("", true)], DEFAULT_IFS),
+            vec![vec![piece("", true)]]
+        );
+    }
+
+    #[test]
+    fn split_on_other_characters() {
+        // Each non white space separator ends a field, even an empty one
+        let fields = split_fields(vec![expanded("a::b:", false)], ":");
+        assert_eq!(
+            fields,
+            vec![unquoted_field("a"), Field::new(), unquoted_field("b")]
+        );
+
+        // White space around a non white space separator belongs to it
+        let fields = split_fields(vec![expanded("a : b  c", false)], " :");
+        assert_eq!(
+            fields,
+            vec![
+                unquoted_field("a"),
+                unquoted_field("b"),
+                unquoted_field("c")
+            ]
+        );
+
+        // A result is joined with the literal text around it
+        let fields = split_fields(
+            vec![
+                literal("x", false),
+                expanded("a:b", false),
+                literal("y", true),
+            ],
+            ":",
+        );
+        assert_eq!(
+            fields,
+            vec![
+                vec![piece("xa", false)],
+                vec![piece("b", false), piece("y", true)]
+            ]
+        );
+    }
+
+    #[test]
+    fn split_with_empty_ifs() {
+        let fields = split_fields(vec![expanded(" a b ", false)], "");
+        assert_eq!(fields, vec![unquoted_field(" a b ")]);
+    }
+
+    #[test]
+    fn split_fields_of_all_parameters() {
+        // "$@" makes one field of each parameter, even an empty one
+        let parts = vec![
+            expanded("a", true),
+            Part::FieldBreak,
+            expanded("", true),
+            Part::FieldBreak,
+            expanded("b c", true),
+        ];
+        let fields = split_fields(parts, DEFAULT_IFS);
+        assert_eq!(
+            fields,
+            vec![
+                vec![piece("a", true)],
+                vec![piece("", true)],
+                vec![piece("b c", true)]
+            ]
+        );
+    }
+
+    #[test]
+    fn join_fields_of_all_parameters() {
+        // Without field splitting the fields of "$@" are joined with the first char of IFS
+        let mut shell = shell();
+        shell.vars.positional = vec![String::from("a"), String::from("b c")];
+        assert_eq!(expand_string("\"$@\"", &mut shell).unwrap(), "a b c");
+
+        shell.vars.set("IFS", String::from(":-"));
+        assert_eq!(expand_string("\"$@\"", &mut shell).unwrap(), "a:b c");
+
+        shell.vars.set("IFS", String::new());
+        assert_eq!(expand_string("\"$@\"", &mut shell).unwrap(), "ab c");
+    }
+
+    #[test]
+    fn filenames() {
+        let dir = TempDir::new("expand-test", &["a.rs", "b.rs", "c.txt"]);
+        let dir = dir.path().display().to_string();
+
+        let fields = expand_filenames(vec![unquoted_field(&format!("{}/*.rs", dir))], false);
+        let paths = remove_quotes(fields);
+        assert_eq!(
+            paths,
+            vec![format!("{}/a.rs", dir), format!("{}/b.rs", dir)]
+        );
+
+        // Quoted pattern characters match literally
+        let field = vec![piece(&format!("{}/", dir), false), piece("*", true)];
+        let fields = expand_filenames(vec![field.clone()], false);
+        assert_eq!(fields, vec![field]);
+
+        // A pattern without match is kept
+        let unmatched = unquoted_field(&format!("{}/*.md", dir));
+        let fields = expand_filenames(vec![unmatched.clone()], false);
+        assert_eq!(fields, vec![unmatched]);
+
+        // Extended patterns only with extglob
+        let field = unquoted_field(&format!("{}/!(*.rs)", dir));
+        let paths = remove_quotes(expand_filenames(vec![field.clone()], true));
+        assert_eq!(paths, vec![format!("{}/c.txt", dir)]);
+        assert_eq!(expand_filenames(vec![field.clone()], false), vec![field]);
+    }
+
+    #[test]
+    fn quote_removal() {
+        let fields = vec![
+            vec![piece("a ", true), piece("b", false), piece("\"", true)],
+            Field::new(),
+        ];
+        assert_eq!(
+            remove_quotes(fields),
+            vec![String::from("a b\""), String::new()]
+        );
+
+        let mut shell = shell();
+        assert_eq!(
+            expand_word("'a b'\"c\"\\ d", &mut shell).unwrap(),
+            vec![String::from("a bc d")]
+        );
+        assert_eq!(expand_word("''", &mut shell).unwrap(), vec![String::new()]);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::process::error_message;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Visits are aged once their total goes over this, so old directories are eventually forgotten
+const MAX_TOTAL_VISITS: u64 = 10000;
+
+// The directories changed into and how often and how recently they were visited, used by z
+// Each line of the file is path|visits|last access time in seconds since the epoch
+// Visits are added to the file when the shell exits, like the history, and the file is read again
+// then so that the visits of shells running at the same time are all kept
+#[derive(Default)]
+pub struct DirectoryDatabase {
+    file: Option<PathBuf>,
+
+    // Visits since the shell started, not in the file yet
+    visits: Vec<Entry>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    path: String,
+    visits: u64,
+    last_access: u64,
+}
+
+impl DirectoryDatabase {
+    // A database kept in `file`, or nowhere if it is None
+    pub fn new(file: Option<PathBuf>) -> Self {
+        DirectoryDatabase {
+            file,
+            visits: Vec::new(),
+        }
+    }
+
+    // Count a visit of `dir` now
+    pub fn visit(&mut self, dir: &str) {
+        let visit = Entry {
+            path: dir.to_string(),
+            visits: 1,
+            last_access: now(),
+        };
+        add(&mut self.visits, &visit);
+    }
+
+    // Add the visits since the last save to the file
+    pub fn save(&mut self) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+        if self.visits.is_empty() {
+            return;
+        }
+
+        let mut entries = self.entries();
+        if entries.iter().map(|entry| entry.visits).sum::<u64>() > MAX_TOTAL_VISITS {
+            for entry in entries.iter_mut() {
+                entry.visits = entry.visits * 9 / 10;
+            }
+            entries.retain(|entry| entry.visits > 0);
+        }
+
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|entry| format!("{}|{}|{}\n", entry.path, entry.visits, entry.last_access))
+            .collect();
+
+        if let Some(dir) = file.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Err(err) = fs::write(file, lines.concat()) {
+            eprintln!("fsh: {}: {}", file.display(), error_message(&err));
+        }
+        self.visits.clear();
+    }
+
+    // Existing directories that match all `terms` with their score, the best one first
+    // The terms must be found in the path in order, ignoring case, and the last one in its last
+    // component, so z foo finds /src/foo but not /src/foo/bar
+    pub fn search(&self, terms: &[String]) -> Vec<(String, u64)> {
+        let now = now();
+
+        let mut matches: Vec<(String, u64)> = self
+            .entries()
+            .into_iter()
+            .filter(|entry| matches_terms(&entry.path, terms) && Path::new(&entry.path).is_dir())
+            .map(|entry| {
+                let score = entry.visits * recency_weight(now.saturating_sub(entry.last_access));
+                (entry.path, score)
+            })
+            .collect();
+        matches.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        matches
+    }
+
+    // The entries of the file with the visits since the last save
+    fn entries(&self) -> Vec<Entry> {
+        let mut entries = self.load();
+        for visit in &self.visits {
+            add(&mut entries, visit);
+        }
+
+        entries
+    }
+
+    fn load(&self) -> Vec<Entry> {
+        let source = match self
+            .file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+        {
+            Some(source) => source,
+            None => return Vec::new(),
+        };
+
+        // A path can contain |, the numbers are after the last two
+        source
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.rsplitn(3, '|');
+                let last_access = fields.next()?.parse().ok()?;
+                let visits = fields.next()?.parse().ok()?;
+                let path = fields.next()?.to_string();
+
+                Some(Entry {
+                    path,
+                    visits,
+                    last_access,
+                })
+            })
+            .collect()
+    }
+}
+
+// Add the visits of `visit` to those of the same directory in `entries`
+fn add(entries: &mut Vec<Entry>, visit: &Entry) {
+    match entries.iter_mut().find(|entry| entry.path == visit.path) {
+        Some(entry) => {
+            entry.visits += visit.visits;
+            entry.last_access = entry.last_access.max(visit.last_access);
+        }
+        None => entries.push(visit.clone()),
+    }
+}
+
+// Visits of the last hour count the most, those older than a week the least
+fn recency_weight(age: u64) -> u64 {
+    match age {
+        age if age < 3600 => 16,
+        age if age < 86400 => 8,
+        age if age < 604800 => 2,
+        _ => 1,
+    }
+}
+
+fn matches_terms(path: &str, terms: &[String]) -> bool {
+    let path = path.to_lowercase();
+    let mut rest = path.as_str();
+
+    for term in terms {
+        let term = term.to_lowercase();
+        match rest.find(&term) {
+            Some(start) => rest = &rest[start + term.len()..],
+            None => return false,
+        }
+    }
+
+    match terms.last() {
+        Some(last) => {
+            let last_component = path.rsplit('/').next().unwrap_or("");
+            last_component.contains(&last.to_lowercase())
+        }
+        None => true,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn save_keeps_visits_of_other_shells() {
+        let dir = TempDir::new("frecency-test", &[]);
+        let file = dir.path().join("frecency");
+
+        // Two shells that started at the same time exit one after the other
+        let mut first = DirectoryDatabase::new(Some(file.clone()));
+        let mut second = DirectoryDatabase::new(Some(file.clone()));
+        first.visit("/tmp");
+        first.visit("/tmp");
+        second.visit("/tmp");
+        second.visit("/");
+        first.save();
+        second.save();
+
+        let entries = DirectoryDatabase::new(Some(file.clone())).load();
+        let visits: Vec<(&str, u64)> = entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.visits))
+            .collect();
+        assert_eq!(visits, [("/tmp", 3), ("/", 1)]);
+    }
+}
@@ -0,0 +1,50 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// The line editor keeps the command as chars, but moves and deletes by grapheme clusters, which
+// are what the user sees as one character, like e followed by a combining accent or an emoji
+// made of several code points
+
+// Index in `buffer` of the start of the grapheme cluster after the one at `index`
+pub fn next_boundary(buffer: &[char], index: usize) -> usize {
+    boundaries(buffer)
+        .into_iter()
+        .find(|&boundary| boundary > index)
+        .unwrap_or(buffer.len())
+}
+
+// Index in `buffer` of the start of the grapheme cluster before `index`
+pub fn previous_boundary(buffer: &[char], index: usize) -> usize {
+    boundaries(buffer)
+        .into_iter()
+        .rev()
+        .find(|&boundary| boundary < index)
+        .unwrap_or(0)
+}
+
+// The grapheme clusters of `buffer` and the index in it where each of them starts
+pub fn split(buffer: &[char]) -> Vec<(usize, String)> {
+    let text: String = buffer.iter().collect();
+    let mut index = 0;
+
+    text.graphemes(true)
+        .map(|grapheme| {
+            let start = index;
+            index += grapheme.chars().count();
+            (start, grapheme.to_string())
+        })
+        .collect()
+}
+
+// Number of columns `text` takes on the terminal, East Asian wide characters and emojis take two
+pub fn width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+// Indexes in `buffer` where its grapheme clusters start, and its length
+fn boundaries(buffer: &[char]) -> Vec<usize> {
+    let mut boundaries: Vec<usize> = split(buffer).into_iter().map(|(start, _)| start).collect();
+    boundaries.push(buffer.len());
+
+    boundaries
+}
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+// Remembers where the commands found in PATH are, so PATH is not searched every time they run
+#[derive(Default)]
+pub struct CommandHash {
+    // Command name to its path and the number of times it was found in the table
+    commands: BTreeMap<String, (PathBuf, usize)>,
+
+    // Value of PATH the paths were found with, the table is emptied when it changes
+    path: String,
+}
+
+impl CommandHash {
+    // Find the program run by `name` with the directories of `path`, using the table if possible
+    // A name that contains a slash is already a path
+    pub fn find(&mut self, name: &str, path: &str) -> Option<PathBuf> {
+        if name.contains('/') {
+            return Some(PathBuf::from(name));
+        }
+
+        self.check_path(path);
+
+        // A program that was removed since it was found is searched again
+        if let Some((program, hits)) = self.commands.get_mut(name) {
+            if program.is_file() && is_executable(program) {
+                *hits += 1;
+                return Some(program.clone());
+            }
+        }
+
+        let program = search_path(name, path).into_iter().next()?;
+        self.commands.insert(name.to_string(), (program.clone(), 1));
+
+        Some(program)
+    }
+
+    // Path of `name` if it is in the table
+    pub fn get(&mut self, name: &str, path: &str) -> Option<&Path> {
+        self.check_path(path);
+        self.commands
+            .get(name)
+            .map(|(program, _)| program.as_path())
+    }
+
+    // Add `name` to the table with `program` as its path
+    pub fn insert(&mut self, name: &str, program: PathBuf, path: &str) {
+        self.check_path(path);
+        self.commands.insert(name.to_string(), (program, 0));
+    }
+
+    // Remove `name` from the table, returns false if it was not in the table
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.commands.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    // Names in the table with their path and number of hits, sorted by name
+    pub fn entries(&mut self, path: &str) -> Vec<(&str, &Path, usize)> {
+        self.check_path(path);
+        self.commands
+            .iter()
+            .map(|(name, (program, hits))| (name.as_str(), program.as_path(), *hits))
+            .collect()
+    }
+
+    // Forget all paths if PATH changed since they were found
+    fn check_path(&mut self, path: &str) {
+        if self.path != path {
+            self.commands.clear();
+            self.path = path.to_string();
+        }
+    }
+}
+
+// All the executable files named `name` in the directories of `path`, in order
+// An empty directory in `path` is the current directory
+pub fn search_path(name: &str, path: &str) -> Vec<PathBuf> {
+    path.split(':')
+        .map(|dir| {
+            if dir.is_empty() {
+                Path::new(".")
+            } else {
+                Path::new(dir)
+            }
+        })
+        .map(|dir| dir.join(name))
+        .filter(|program| program.is_file() && is_executable(program))
+        .collect()
+}
+
+// Whether the current user can execute `path`
+pub fn is_executable(path: &Path) -> bool {
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), libc::X_OK) == 0 },
+        Err(_) => false,
+    }
+}
+
+// Names of all the files in the directories of `path`, they are the programs that can be run
+pub fn program_names(path: &str) -> Vec<String> {
+    path.split(':')
+        .filter_map(|dir| fs::read_dir(if dir.is_empty() { "." } else { dir }).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| !file_type.is_dir()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+
+    // A directory with an executable `prog` in `first` and `second`
+    fn programs(name: &str) -> (TempDir, String, String) {
+        let dir = TempDir::new(name, &[]);
+        let mut dirs = Vec::new();
+
+        for sub in ["first", "second"].iter() {
+            let sub = dir.path().join(sub);
+            fs::create_dir(&sub).unwrap();
+            fs::write(sub.join("prog"), "").unwrap();
+            fs::set_permissions(sub.join("prog"), fs::Permissions::from_mode(0o755)).unwrap();
+            dirs.push(sub.display().to_string());
+        }
+
+        let second = dirs.pop().unwrap();
+        (dir, dirs.pop().unwrap(), second)
+    }
+
+    #[test]
+    fn path_change_empties_the_table() {
+        let (_dir, first, second) = programs("hash-path-test");
+        let path = format!("{}:{}", first, second);
+        let mut hash = CommandHash::default();
+
+        let program = Path::new(&first).join("prog");
+        assert_eq!(hash.find("prog", &path), Some(program.clone()));
+        assert_eq!(hash.find("prog", &path), Some(program.clone()));
+        assert_eq!(hash.entries(&path), [("prog", program.as_path(), 2)]);
+
+        // The table was filled with another PATH
+        let path = format!("{}:{}", second, first);
+        assert_eq!(hash.get("prog", &path), None);
+        assert_eq!(
+            hash.find("prog", &path),
+            Some(Path::new(&second).join("prog"))
+        );
+        assert_eq!(hash.find("missing", &path), None);
+    }
+
+    #[test]
+    fn removed_program_is_searched_again() {
+        let (_dir, first, second) = programs("hash-removed-test");
+        let path = format!("{}:{}", first, second);
+        let mut hash = CommandHash::default();
+
+        hash.find("prog", &path);
+        fs::remove_file(Path::new(&first).join("prog")).unwrap();
+        assert_eq!(
+            hash.find("prog", &path),
+            Some(Path::new(&second).join("prog"))
+        );
+    }
+
+    #[test]
+    fn search_all_directories() {
+        let (_dir, first, second) = programs("hash-search-test");
+        let path = format!("{}::{}", first, second);
+
+        assert_eq!(
+            search_path("prog", &path),
+            [
+                Path::new(&first).join("prog"),
+                Path::new(&second).join("prog")
+            ]
+        );
+        assert!(search_path("prog", "").is_empty());
+    }
+}
@@ -1,19 +1,27 @@
+use crate::paths;
+use crate::shell::Shell;
 use rudac::queue::Circular;
 use std::fs::{self, File};
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use std::process;
 
 // Manages history of shell
 pub struct History {
-    // Path to history file which is /home/username/.fsh/history
+    // Path to history file, see `get_history_file`
     history_file_path_buf: Option<PathBuf>,
 
     // A circular buffer to store executed commands
     history_buffer: Circular<String>,
 }
 
+impl Default for History {
+    // A history that is not saved to a file
+    fn default() -> Self {
+        History::init(None)
+    }
+}
+
 impl History {
     // Init a new history handler
     fn init(history_file_path_buf: Option<PathBuf>) -> Self {
@@ -27,14 +35,17 @@ impl History {
         if history_handler.history_file_path_buf.is_some() {
             let history_file = File::open(history_handler.history_file_path_ref()).unwrap();
             let commands = BufReader::new(history_file).lines();
-            for command in commands {
-                if let Ok(command_text) = command {
-                    history_handler.add_command(command_text);
-                }
+            for command_text in commands.map_while(Result::ok) {
+                history_handler.add_command(command_text);
             }
         }
 
-        return history_handler;
+        history_handler
+    }
+
+    // The history file, None if the history is not saved
+    pub fn file(&self) -> Option<&Path> {
+        self.history_file_path_buf.as_deref()
     }
 
     // Get a reference to history file path
@@ -62,7 +73,7 @@ impl History {
         let mut suggestions = Vec::new();
 
         // Suggest any command that start with specified `command`
-        if command.trim().len() != 0 {
+        if !command.trim().is_empty() {
             for element in self.history_buffer.into_iter() {
                 if element.starts_with(command) {
                     suggestions.push(element.clone());
@@ -73,7 +84,7 @@ impl History {
         // Reverse the order the suggestion to put latest found match at index 0
         suggestions.reverse();
 
-        return suggestions;
+        suggestions
     }
 
     // Return history element at specified `index`
@@ -90,11 +101,8 @@ impl History {
         // If path to history file is specified, save the buffer
         if self.history_file_path_buf.is_some() {
             // Convert Vec<&String> to Vev<String>
-            let history_elements: Vec<String> = self
-                .get_history_elements()
-                .into_iter()
-                .map(|element| element.clone())
-                .collect();
+            let history_elements: Vec<String> =
+                self.get_history_elements().into_iter().cloned().collect();
 
             let history_file_path_buf = self.history_file_path_mut_ref();
 
@@ -104,9 +112,9 @@ impl History {
 }
 
 // Create a history handler and return it
-pub fn get_history_handler() -> History {
+pub fn get_history_handler(shell: &Shell) -> History {
     // If history file could be found or created
-    if let Ok(history_file_path_buf) = get_history_file() {
+    if let Ok(history_file_path_buf) = get_history_file(shell) {
         History::init(Some(history_file_path_buf))
     } else {
         History::init(None)
@@ -114,47 +122,67 @@ pub fn get_history_handler() -> History {
 }
 
 // Return path to history file
-fn get_history_file() -> std::result::Result<PathBuf, &'static str> {
-    // Launch whoami program and collect its output
-    if let Ok(output) = process::Command::new("whoami").output() {
-        // Output of whoami program is the username
-        let username = std::str::from_utf8(&output.stdout)
-            .unwrap()
-            .to_string()
-            .trim()
-            .to_string();
-
-        // History file is in /home/{username}/.fsh/history
-        let fsh_path_str = format!("/home/{username}/.fsh", username = username);
-        let fsh_path = Path::new(&fsh_path_str);
-
-        if !fsh_path.exists() {
-            // Create fsh dir
-            if let Ok(_) = fs::create_dir(fsh_path) {
-                // Create history file
-                let history_file_path = fsh_path.join("history");
-                if let Ok(_) = File::create(&history_file_path) {
-                    return Ok(history_file_path);
-                } else {
-                    println!("Could not create history file.")
-                }
-            } else {
-                println!("Could not create .fsh dir in user home directory.");
-            }
-        } else {
-            let history_file_path = fsh_path.join("history");
-
-            if !history_file_path.exists() {
-                if let Ok(_) = File::create(&history_file_path) {
-                    return Ok(history_file_path);
-                } else {
-                    println!("Could not create history file.")
-                }
+// It is $FSH_HISTFILE or $HISTFILE if one is set, otherwise history in the state directory
+fn get_history_file(shell: &Shell) -> std::result::Result<PathBuf, &'static str> {
+    let overridden = ["FSH_HISTFILE", "HISTFILE"]
+        .iter()
+        .find_map(|name| shell.vars.get(name).filter(|file| !file.is_empty()));
+
+    let history_file_path = match overridden {
+        Some(file) => PathBuf::from(file),
+        None => {
+            // Without a home directory the history is not saved
+            let state_dir = paths::state_dir(shell).ok_or("Failed to get history file")?;
+            if fs::create_dir_all(&state_dir).is_err() {
+                println!("Could not create {} directory.", state_dir.display());
+                return Err("Failed to get history file");
             }
 
-            return Ok(history_file_path);
+            let history_file_path = state_dir.join("history");
+            migrate_history(&history_file_path, shell);
+            history_file_path
         }
+    };
+
+    if !history_file_path.exists() && File::create(&history_file_path).is_err() {
+        println!("Could not create history file.");
+        return Err("Failed to get history file");
+    }
+
+    Ok(history_file_path)
+}
+
+// Move the history kept in ~/.fsh by older versions, or in the data directory by hand, to
+// `history_file_path`
+fn migrate_history(history_file_path: &Path, shell: &Shell) {
+    if history_file_path.exists() {
+        return;
     }
 
-    return Err("Failed to get history file");
+    let old_dirs = [paths::legacy_dir(shell), paths::data_dir(shell)];
+    for old_dir in old_dirs.iter().flatten() {
+        let old_file = old_dir.join("history");
+        if !old_file.is_file() {
+            continue;
+        }
+
+        // A rename fails when the directories are on different file systems, then it is copied
+        let moved = fs::rename(&old_file, history_file_path).is_ok()
+            || fs::copy(&old_file, history_file_path).is_ok();
+
+        if moved {
+            let _ = fs::remove_file(&old_file);
+            eprintln!(
+                "fsh: moved history from {} to {}",
+                old_file.display(),
+                history_file_path.display()
+            );
+
+            // The old directory is removed once nothing is left in it
+            let _ = fs::remove_dir(old_dir);
+        } else {
+            eprintln!("fsh: could not move history from {}", old_file.display());
+        }
+        return;
+    }
 }
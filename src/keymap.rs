@@ -0,0 +1,473 @@
+use termion::event::Key;
+
+// A named action of the line editor that keys are bound to
+#[derive(Clone, Copy, PartialEq)]
+pub enum Function {
+    AcceptLine,
+    Complete,
+    HistorySearchBackward,
+    PreviousHistory,
+    NextHistory,
+    BeginningOfLine,
+    EndOfLine,
+    BackwardChar,
+    ForwardChar,
+    BackwardWord,
+    ForwardWord,
+    DeleteChar,
+    DeleteCharOrEof,
+    BackwardDeleteChar,
+    KillLine,
+    UnixLineDiscard,
+    UnixWordRubout,
+    KillWord,
+    BackwardKillWord,
+    Yank,
+    YankPop,
+    TransposeChars,
+    UpcaseWord,
+    DowncaseWord,
+    CapitalizeWord,
+    Undo,
+    Redo,
+    ClearScreen,
+    Abort,
+    EditAndExecuteCommand,
+    SelfInsert,
+}
+
+// Names of the functions as they are given to bind, listed by bind -l
+const FUNCTIONS: [(&str, Function); 31] = [
+    ("abort", Function::Abort),
+    ("accept-line", Function::AcceptLine),
+    ("backward-char", Function::BackwardChar),
+    ("backward-delete-char", Function::BackwardDeleteChar),
+    ("backward-kill-word", Function::BackwardKillWord),
+    ("backward-word", Function::BackwardWord),
+    ("beginning-of-line", Function::BeginningOfLine),
+    ("capitalize-word", Function::CapitalizeWord),
+    ("clear-screen", Function::ClearScreen),
+    ("complete", Function::Complete),
+    ("delete-char", Function::DeleteChar),
+    ("delete-char-or-eof", Function::DeleteCharOrEof),
+    ("downcase-word", Function::DowncaseWord),
+    ("edit-and-execute-command", Function::EditAndExecuteCommand),
+    ("end-of-line", Function::EndOfLine),
+    ("forward-char", Function::ForwardChar),
+    ("forward-word", Function::ForwardWord),
+    ("history-search-backward", Function::HistorySearchBackward),
+    ("kill-line", Function::KillLine),
+    ("kill-word", Function::KillWord),
+    ("next-history", Function::NextHistory),
+    ("previous-history", Function::PreviousHistory),
+    ("redo", Function::Redo),
+    ("self-insert", Function::SelfInsert),
+    ("transpose-chars", Function::TransposeChars),
+    ("undo", Function::Undo),
+    ("unix-line-discard", Function::UnixLineDiscard),
+    ("unix-word-rubout", Function::UnixWordRubout),
+    ("upcase-word", Function::UpcaseWord),
+    ("yank", Function::Yank),
+    ("yank-pop", Function::YankPop),
+];
+
+// Bindings of the keys in emacs mode and in insert mode of vi mode, written like for bind
+// Characters that are not bound are inserted by self-insert
+const DEFAULT_BINDINGS: [(&str, &str); 39] = [
+    ("\\n", "accept-line"),
+    ("\\t", "complete"),
+    ("\\C-c", "abort"),
+    ("\\C-d", "delete-char-or-eof"),
+    ("\\e[3~", "delete-char"),
+    ("\\C-r", "history-search-backward"),
+    ("\\C-l", "clear-screen"),
+    ("\\e[D", "backward-char"),
+    ("\\C-b", "backward-char"),
+    ("\\e[C", "forward-char"),
+    ("\\C-f", "forward-char"),
+    ("\\e[H", "beginning-of-line"),
+    ("\\C-a", "beginning-of-line"),
+    ("\\e[F", "end-of-line"),
+    ("\\C-e", "end-of-line"),
+    ("\\eb", "backward-word"),
+    ("\\ef", "forward-word"),
+    ("\\C-k", "kill-line"),
+    ("\\C-u", "unix-line-discard"),
+    ("\\C-w", "unix-word-rubout"),
+    ("\\ed", "kill-word"),
+    ("\\e\\C-?", "backward-kill-word"),
+    ("\\C-y", "yank"),
+    ("\\ey", "yank-pop"),
+    ("\\C-t", "transpose-chars"),
+    ("\\eu", "upcase-word"),
+    ("\\el", "downcase-word"),
+    ("\\ec", "capitalize-word"),
+    ("\\C-_", "undo"),
+    ("\\C-x\\C-u", "undo"),
+    ("\\e_", "redo"),
+    ("\\C-x\\C-e", "edit-and-execute-command"),
+    ("\\e[A", "previous-history"),
+    ("\\e[B", "next-history"),
+    ("\\C-?", "backward-delete-char"),
+    ("\\C-h", "backward-delete-char"),
+    ("\\C-p", "previous-history"),
+    ("\\C-n", "next-history"),
+    ("\\C-g", "abort"),
+];
+
+// What a key sequence does
+#[derive(Clone)]
+pub enum Binding {
+    Function(Function),
+
+    // Run a shell command, the command being typed is kept
+    Command(String),
+}
+
+// What the keys typed so far do
+pub enum Lookup<'a> {
+    Bound(&'a Binding),
+
+    // They start a longer sequence like Ctrl-X for Ctrl-X Ctrl-E, the next key is needed
+    Prefix,
+
+    Unbound,
+}
+
+// The key sequences bound to functions or shell commands, changed by the bind builtin
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Binding)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|(keys, name)| {
+                let keys = parse_keys(keys).expect("invalid default key sequence");
+                let function = function(name).expect("invalid default function");
+                (keys, Binding::Function(function))
+            })
+            .collect();
+
+        Keymap { bindings }
+    }
+}
+
+impl Keymap {
+    pub fn lookup(&self, keys: &[Key]) -> Lookup<'_> {
+        if let Some((_, binding)) = self.bindings.iter().find(|(bound, _)| bound == keys) {
+            return Lookup::Bound(binding);
+        }
+
+        if self
+            .bindings
+            .iter()
+            .any(|(bound, _)| bound.starts_with(keys))
+        {
+            Lookup::Prefix
+        } else {
+            Lookup::Unbound
+        }
+    }
+
+    // Bind `keys` to `binding`, replacing what they were bound to
+    pub fn bind(&mut self, keys: Vec<Key>, binding: Binding) {
+        match self.bindings.iter_mut().find(|(bound, _)| *bound == keys) {
+            Some((_, bound)) => *bound = binding,
+            None => self.bindings.push((keys, binding)),
+        }
+    }
+
+    // Remove the binding of `keys`, returns whether they were bound
+    pub fn unbind(&mut self, keys: &[Key]) -> bool {
+        let count = self.bindings.len();
+        self.bindings.retain(|(bound, _)| bound != keys);
+        self.bindings.len() != count
+    }
+
+    pub fn bindings(&self) -> &[(Vec<Key>, Binding)] {
+        &self.bindings
+    }
+}
+
+// The function called `name`
+pub fn function(name: &str) -> Option<Function> {
+    FUNCTIONS
+        .iter()
+        .find(|(function_name, _)| *function_name == name)
+        .map(|(_, function)| *function)
+}
+
+pub fn function_name(function: Function) -> &'static str {
+    FUNCTIONS
+        .iter()
+        .find(|(_, named)| *named == function)
+        .map_or("", |(name, _)| name)
+}
+
+pub fn function_names() -> impl Iterator<Item = &'static str> {
+    FUNCTIONS.iter().map(|(name, _)| *name)
+}
+
+// Parse a key sequence written like in readline's inputrc: \C-x is Ctrl-X, \M-x and \ex are
+// Alt-X, \e is Esc, \C-? is Backspace, \t is Tab, \n and \r are Enter and \\ is a backslash
+// The escape sequences of the arrows, Home, End and Delete like \e[A are read as those keys
+pub fn parse_keys(text: &str) -> Result<Vec<Key>, String> {
+    let invalid = || format!("{}: invalid key sequence", text);
+
+    // Characters of the sequence with the escapes replaced, Ctrl is kept as a flag
+    let mut chars: Vec<(char, bool)> = Vec::new();
+    let mut rest = text.chars().peekable();
+
+    while let Some(c) = rest.next() {
+        if c != '\\' {
+            chars.push((c, false));
+            continue;
+        }
+
+        match rest.next().ok_or_else(invalid)? {
+            'C' if rest.peek() == Some(&'-') => {
+                rest.next();
+                let c = rest.next().ok_or_else(invalid)?;
+                chars.push((c, true));
+            }
+            'M' if rest.peek() == Some(&'-') => {
+                rest.next();
+                chars.push(('\x1b', false));
+            }
+            'e' => chars.push(('\x1b', false)),
+            't' => chars.push(('\t', false)),
+            'n' | 'r' => chars.push(('\n', false)),
+            c => chars.push((c, false)),
+        }
+    }
+
+    let mut keys = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let (c, ctrl) = chars[index];
+        index += 1;
+
+        if ctrl {
+            keys.push(ctrl_key(c).ok_or_else(invalid)?);
+            continue;
+        }
+        if c != '\x1b' {
+            keys.push(Key::Char(c));
+            continue;
+        }
+
+        // Esc starts the sequence of a special key or adds Alt to the next key
+        let special: String = chars[index..].iter().take(3).map(|(c, _)| *c).collect();
+        let special_key = [
+            ("[A", Key::Up),
+            ("[B", Key::Down),
+            ("[C", Key::Right),
+            ("[D", Key::Left),
+            ("[H", Key::Home),
+            ("[F", Key::End),
+            ("[3~", Key::Delete),
+        ]
+        .iter()
+        .find(|(sequence, _)| {
+            special.starts_with(sequence)
+                && chars[index..]
+                    .iter()
+                    .take(sequence.len())
+                    .all(|(_, ctrl)| !ctrl)
+        });
+
+        match (special_key, chars.get(index)) {
+            (Some((sequence, key)), _) => {
+                keys.push(*key);
+                index += sequence.len();
+            }
+            (None, Some((c, true))) if ctrl_key(*c) == Some(Key::Backspace) => {
+                keys.push(Key::Alt('\x7f'));
+                index += 1;
+            }
+            (None, Some((c, false))) if *c != '\x1b' => {
+                keys.push(Key::Alt(*c));
+                index += 1;
+            }
+            _ => keys.push(Key::Esc),
+        }
+    }
+
+    if keys.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(keys)
+}
+
+// Write `keys` like parse_keys reads them
+pub fn format_keys(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|key| match key {
+            Key::Char('\n') => String::from("\\n"),
+            Key::Char('\t') => String::from("\\t"),
+            Key::Char('\\') => String::from("\\\\"),
+            Key::Char(c) => c.to_string(),
+            Key::Ctrl(c) => format!("\\C-{}", ctrl_name(*c)),
+            Key::Backspace => String::from("\\C-?"),
+            Key::Alt('\x7f') => String::from("\\e\\C-?"),
+            Key::Alt(c) => format!("\\e{}", c),
+            Key::Esc => String::from("\\e"),
+            Key::Up => String::from("\\e[A"),
+            Key::Down => String::from("\\e[B"),
+            Key::Right => String::from("\\e[C"),
+            Key::Left => String::from("\\e[D"),
+            Key::Home => String::from("\\e[H"),
+            Key::End => String::from("\\e[F"),
+            Key::Delete => String::from("\\e[3~"),
+            _ => String::new(),
+        })
+        .collect()
+}
+
+// The key termion reads for Ctrl and `c`
+// Ctrl-I is read as Tab, Ctrl-J and Ctrl-M as Enter, Ctrl-[ as Esc, Ctrl-? as Backspace and
+// Ctrl-\ to Ctrl-_ as Ctrl-4 to Ctrl-7
+fn ctrl_key(c: char) -> Option<Key> {
+    match c.to_ascii_lowercase() {
+        '?' => Some(Key::Backspace),
+        'i' => Some(Key::Char('\t')),
+        'j' | 'm' => Some(Key::Char('\n')),
+        '[' => Some(Key::Esc),
+        '\\' => Some(Key::Ctrl('4')),
+        ']' => Some(Key::Ctrl('5')),
+        '^' => Some(Key::Ctrl('6')),
+        '_' => Some(Key::Ctrl('7')),
+        c if c.is_ascii_lowercase() => Some(Key::Ctrl(c)),
+        _ => None,
+    }
+}
+
+// The character written after \C- for the key termion reads as Ctrl and `c`
+fn ctrl_name(c: char) -> char {
+    match c {
+        '4' => '\\',
+        '5' => ']',
+        '6' => '^',
+        '7' => '_',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound(keymap: &Keymap, keys: &[Key]) -> Option<&'static str> {
+        match keymap.lookup(keys) {
+            Lookup::Bound(Binding::Function(function)) => Some(function_name(*function)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parse_key_sequences() {
+        assert_eq!(parse_keys("ab"), Ok(vec![Key::Char('a'), Key::Char('b')]));
+        assert_eq!(
+            parse_keys(r"\C-a\C-Z"),
+            Ok(vec![Key::Ctrl('a'), Key::Ctrl('z')])
+        );
+        assert_eq!(
+            parse_keys(r"\C-x\C-e"),
+            Ok(vec![Key::Ctrl('x'), Key::Ctrl('e')])
+        );
+        assert_eq!(
+            parse_keys(r"\ef\M-b"),
+            Ok(vec![Key::Alt('f'), Key::Alt('b')])
+        );
+        assert_eq!(parse_keys(r"\e\C-?"), Ok(vec![Key::Alt('\x7f')]));
+        assert_eq!(
+            parse_keys(r"\C-?\C-h"),
+            Ok(vec![Key::Backspace, Key::Ctrl('h')])
+        );
+        assert_eq!(parse_keys(r"\C-_"), Ok(vec![Key::Ctrl('7')]));
+        assert_eq!(
+            parse_keys(r"\t\n\r\\"),
+            Ok(vec![
+                Key::Char('\t'),
+                Key::Char('\n'),
+                Key::Char('\n'),
+                Key::Char('\\')
+            ])
+        );
+        assert_eq!(
+            parse_keys(r"\e[A\e[3~\e"),
+            Ok(vec![Key::Up, Key::Delete, Key::Esc])
+        );
+        assert_eq!(parse_keys(r"\e\e"), Ok(vec![Key::Esc, Key::Esc]));
+
+        assert!(parse_keys("").is_err());
+        assert!(parse_keys(r"\C-").is_err());
+        assert!(parse_keys(r"\C-1").is_err());
+        assert!(parse_keys("\\").is_err());
+    }
+
+    #[test]
+    fn format_key_sequences() {
+        assert_eq!(format_keys(&[Key::Ctrl('x'), Key::Ctrl('e')]), r"\C-x\C-e");
+        assert_eq!(format_keys(&[Key::Alt('\x7f')]), r"\e\C-?");
+        assert_eq!(format_keys(&[Key::Ctrl('7'), Key::Char('\\')]), r"\C-_\\");
+        assert_eq!(format_keys(&[Key::Up, Key::Esc]), r"\e[A\e");
+    }
+
+    #[test]
+    fn formatted_keys_are_parsed_back() {
+        for (keys, _) in DEFAULT_BINDINGS {
+            let parsed = parse_keys(keys).unwrap();
+            assert_eq!(parse_keys(&format_keys(&parsed)), Ok(parsed));
+        }
+
+        let keys = [
+            Key::Char('\t'),
+            Key::Char('\n'),
+            Key::Char('\\'),
+            Key::Alt('x'),
+            Key::Ctrl('4'),
+            Key::Ctrl('5'),
+            Key::Ctrl('6'),
+            Key::Backspace,
+            Key::Down,
+            Key::Right,
+            Key::Left,
+            Key::Home,
+            Key::End,
+            Key::Delete,
+        ];
+        assert_eq!(parse_keys(&format_keys(&keys)), Ok(keys.to_vec()));
+    }
+
+    #[test]
+    fn lookup_prefixes() {
+        let mut keymap = Keymap::default();
+        assert_eq!(bound(&keymap, &[Key::Ctrl('a')]), Some("beginning-of-line"));
+        assert_eq!(
+            bound(&keymap, &[Key::Ctrl('x'), Key::Ctrl('e')]),
+            Some("edit-and-execute-command")
+        );
+        assert!(matches!(keymap.lookup(&[Key::Ctrl('x')]), Lookup::Prefix));
+        assert!(matches!(
+            keymap.lookup(&[Key::Ctrl('x'), Key::Char('a')]),
+            Lookup::Unbound
+        ));
+        assert!(matches!(keymap.lookup(&[Key::Char('a')]), Lookup::Unbound));
+
+        // A sequence can be bound while longer ones start with it
+        keymap.bind(vec![Key::Ctrl('x')], Binding::Function(Function::Abort));
+        assert_eq!(bound(&keymap, &[Key::Ctrl('x')]), Some("abort"));
+
+        // Removing the longer sequences leaves nothing to wait for after the prefix
+        assert!(keymap.unbind(&[Key::Ctrl('x'), Key::Ctrl('e')]));
+        assert!(keymap.unbind(&[Key::Ctrl('x'), Key::Ctrl('u')]));
+        assert!(keymap.unbind(&[Key::Ctrl('x')]));
+        assert!(!keymap.unbind(&[Key::Ctrl('x')]));
+        assert!(matches!(keymap.lookup(&[Key::Ctrl('x')]), Lookup::Unbound));
+    }
+}
@@ -0,0 +1,490 @@
+// Split a command line into words and operators
+// Quotes and expansions are kept inside the words as typed, they are processed during expansion
+
+// Control and redirection operators sorted so that longer ones are matched first
+const OPERATORS: [&str; 18] = [
+    "&>>", "&&", "||", ">>", ">|", "<>", "<&", ">&", "&>", ";;", ";", "&", "|", "(", ")", "<", ">",
+    "\n",
+];
+
+// Operators that are plain words inside [[ ... ]]
+const CONDITIONAL_OPERATORS: [&str; 6] = ["&&", "||", "(", ")", "<", ">"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Word(String),
+    Operator(&'static str),
+
+    // File descriptor number written right before a redirection operator like 2>
+    IoNumber(i32),
+}
+
+// Split `line` into words and operators
+pub fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut index = 0;
+
+    // Whether the next word is in the position of a command name, where [[ starts a conditional
+    let mut command_start = true;
+
+    // Whether the words are inside [[ ... ]]
+    let mut conditional = false;
+
+    while index < chars.len() {
+        let c = chars[index];
+
+        if c == ' ' || c == '\t' || c == '\r' {
+            if in_word {
+                push_word(
+                    &mut tokens,
+                    std::mem::take(&mut word),
+                    &mut command_start,
+                    &mut conditional,
+                );
+                in_word = false;
+            }
+            index += 1;
+            continue;
+        }
+
+        // A backslash followed by a newline continues the line
+        if c == '\\' && chars.get(index + 1) == Some(&'\n') {
+            index += 2;
+            continue;
+        }
+
+        // A # at the start of a word starts a comment that ends with the line
+        if c == '#' && !in_word {
+            while index < chars.len() && chars[index] != '\n' {
+                index += 1;
+            }
+            continue;
+        }
+
+        // The right operand of =~ is a regular expression where ( ) and | are part of the word
+        if conditional && !in_word && tokens.last() == Some(&Token::Word(String::from("=~"))) {
+            let end = skip_regex(&chars, index)?;
+            word.extend(&chars[index..end]);
+            in_word = true;
+            index = end;
+            continue;
+        }
+
+        // The values of an array assignment like files=(a b c) and the groups of extended
+        // patterns like !(*.o) are part of the word
+        if c == '('
+            && in_word
+            && (is_array_assignment_prefix(&word) || is_extended_pattern_prefix(&word))
+        {
+            let end = skip_until_closing(&chars, index + 1, '(', ')')?;
+            word.extend(&chars[index..end]);
+            index = end;
+            continue;
+        }
+
+        if let Some(operator) = operator_at(&chars, index) {
+            if conditional && CONDITIONAL_OPERATORS.contains(&operator) {
+                if in_word {
+                    push_word(
+                        &mut tokens,
+                        std::mem::take(&mut word),
+                        &mut command_start,
+                        &mut conditional,
+                    );
+                    in_word = false;
+                }
+                tokens.push(Token::Word(operator.to_string()));
+                index += operator.len();
+                continue;
+            }
+
+            let is_redirection = operator.contains(['<', '>']);
+
+            if in_word {
+                match word.parse::<i32>() {
+                    // A number directly followed by a redirection operator is the redirected file descriptor
+                    Ok(fd) if is_redirection && word.chars().all(|c| c.is_ascii_digit()) => {
+                        tokens.push(Token::IoNumber(fd));
+                        word.clear();
+                    }
+                    _ => push_word(
+                        &mut tokens,
+                        std::mem::take(&mut word),
+                        &mut command_start,
+                        &mut conditional,
+                    ),
+                }
+                in_word = false;
+            }
+
+            tokens.push(Token::Operator(operator));
+            command_start = !is_redirection;
+            index += operator.len();
+            continue;
+        }
+
+        let end = skip_quoted(&chars, index)?;
+        word.extend(&chars[index..end]);
+        in_word = true;
+        index = end;
+    }
+
+    if in_word {
+        push_word(&mut tokens, word, &mut command_start, &mut conditional);
+    }
+
+    Ok(tokens)
+}
+
+// Add a word to `tokens` and keep track of where commands and conditionals start
+fn push_word(
+    tokens: &mut Vec<Token>,
+    word: String,
+    command_start: &mut bool,
+    conditional: &mut bool,
+) {
+    if *conditional {
+        *conditional = word != "]]";
+    } else {
+        *conditional = *command_start && word == "[[";
+
+        // Reserved words that are followed by a command, { also starts the body of a function
+        *command_start = word == "{" || (*command_start && word == "!");
+    }
+
+    tokens.push(Token::Word(word));
+}
+
+// The operator that starts at `index`, if any
+fn operator_at(chars: &[char], index: usize) -> Option<&'static str> {
+    OPERATORS.iter().copied().find(|operator| {
+        operator
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| chars.get(index + offset) == Some(&c))
+    })
+}
+
+// Whether `word` ends with the char that starts a group of an extended pattern like @(a|b)
+fn is_extended_pattern_prefix(word: &str) -> bool {
+    matches!(
+        word.chars().last(),
+        Some('?') | Some('*') | Some('+') | Some('@') | Some('!')
+    )
+}
+
+// Skip a regular expression up to the next blank outside of parentheses
+fn skip_regex(chars: &[char], start: usize) -> Result<usize, String> {
+    let mut depth = 0;
+    let mut index = start;
+
+    while index < chars.len() {
+        match chars[index] {
+            ' ' | '\t' | '\r' | '\n' if depth == 0 => break,
+            '(' => {
+                depth += 1;
+                index += 1;
+            }
+            ')' => {
+                depth -= 1;
+                index += 1;
+            }
+            _ => index = skip_quoted(chars, index)?,
+        }
+    }
+
+    Ok(index)
+}
+
+// Split `line` into words separated by unquoted blanks
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+
+        if c == ' ' || c == '\t' || c == '\r' || c == '\n' {
+            if in_word {
+                words.push(std::mem::take(&mut word));
+                in_word = false;
+            }
+            index += 1;
+            continue;
+        }
+
+        // The values of an array assignment like files=(a b c) are part of the word
+        let end = if c == '(' && is_array_assignment_prefix(&word) {
+            skip_until_closing(&chars, index + 1, '(', ')')?
+        } else {
+            skip_quoted(&chars, index)?
+        };
+        word.extend(&chars[index..end]);
+        in_word = true;
+        index = end;
+    }
+
+    if in_word {
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+// Whether `word` is the start of an assignment like name= or name+=
+fn is_array_assignment_prefix(word: &str) -> bool {
+    match word.strip_suffix('=') {
+        Some(name) => crate::vars::is_valid_name(name.strip_suffix('+').unwrap_or(name)),
+        None => false,
+    }
+}
+
+// Skip the char at `start` and, if it opens a quote or an expansion, everything up to its end
+// Returns the index after the skipped text
+pub(crate) fn skip_quoted(chars: &[char], start: usize) -> Result<usize, String> {
+    match chars[start] {
+        '\\' => Ok(std::cmp::min(start + 2, chars.len())),
+        '\'' => skip_single_quoted(chars, start),
+        '"' => skip_double_quoted(chars, start),
+        '`' => skip_backquoted(chars, start),
+        '$' => skip_dollar(chars, start),
+        _ => Ok(start + 1),
+    }
+}
+
+// `start` is the index of the opening quote
+pub(crate) fn skip_single_quoted(chars: &[char], start: usize) -> Result<usize, String> {
+    match chars[start + 1..].iter().position(|&c| c == '\'') {
+        Some(offset) => Ok(start + offset + 2),
+        None => Err(unexpected_eof('\'')),
+    }
+}
+
+// `start` is the index of the opening quote
+pub(crate) fn skip_double_quoted(chars: &[char], start: usize) -> Result<usize, String> {
+    let mut index = start + 1;
+
+    while index < chars.len() {
+        match chars[index] {
+            '"' => return Ok(index + 1),
+            '\\' => index += 2,
+            '`' => index = skip_backquoted(chars, index)?,
+            '$' => index = skip_dollar(chars, index)?,
+            _ => index += 1,
+        }
+    }
+
+    Err(unexpected_eof('"'))
+}
+
+// `start` is the index of the opening backquote
+pub(crate) fn skip_backquoted(chars: &[char], start: usize) -> Result<usize, String> {
+    let mut index = start + 1;
+
+    while index < chars.len() {
+        match chars[index] {
+            '`' => return Ok(index + 1),
+            '\\' => index += 2,
+            _ => index += 1,
+        }
+    }
+
+    Err(unexpected_eof('`'))
+}
+
+// `start` is the index of a `$`
+// Skips $name, ${...}, $(...) and $((...))
+pub(crate) fn skip_dollar(chars: &[char], start: usize) -> Result<usize, String> {
+    match chars.get(start + 1) {
+        Some('{') => skip_until_closing(chars, start + 2, '{', '}'),
+        Some('(') => skip_until_closing(chars, start + 2, '(', ')'),
+        Some(_) => Ok(start + 1),
+        None => Ok(start + 1),
+    }
+}
+
+// Skip nested text until the `close` char that balances an already opened `open` char
+fn skip_until_closing(
+    chars: &[char],
+    start: usize,
+    open: char,
+    close: char,
+) -> Result<usize, String> {
+    let mut depth = 1;
+    let mut index = start;
+
+    while index < chars.len() {
+        let c = chars[index];
+
+        if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Ok(index + 1);
+            }
+            index += 1;
+        } else if c == open {
+            depth += 1;
+            index += 1;
+        } else {
+            index = skip_quoted(chars, index)?;
+        }
+    }
+
+    Err(unexpected_eof(close))
+}
+
+fn unexpected_eof(expected: char) -> String {
+    format!("unexpected EOF while looking for matching `{}'", expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str) -> Token {
+        Token::Word(text.to_string())
+    }
+
+    #[test]
+    fn words_and_operators() {
+        assert_eq!(
+            tokenize("a  'b c'\"d\"|e&&f;;g").unwrap(),
+            [
+                word("a"),
+                word("'b c'\"d\""),
+                Token::Operator("|"),
+                word("e"),
+                Token::Operator("&&"),
+                word("f"),
+                Token::Operator(";;"),
+                word("g"),
+            ]
+        );
+        assert_eq!(
+            tokenize("echo $(a | b) ${c:-d e} `f g`").unwrap(),
+            [
+                word("echo"),
+                word("$(a | b)"),
+                word("${c:-d e}"),
+                word("`f g`")
+            ]
+        );
+    }
+
+    #[test]
+    fn redirections() {
+        assert_eq!(
+            tokenize("cmd 2>err >>out 3<&0 a2>x").unwrap(),
+            [
+                word("cmd"),
+                Token::IoNumber(2),
+                Token::Operator(">"),
+                word("err"),
+                Token::Operator(">>"),
+                word("out"),
+                Token::IoNumber(3),
+                Token::Operator("<&"),
+                word("0"),
+                word("a2"),
+                Token::Operator(">"),
+                word("x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_and_continuations() {
+        assert_eq!(
+            tokenize("a # b 'c\nd").unwrap(),
+            [word("a"), Token::Operator("\n"), word("d")]
+        );
+        assert_eq!(tokenize("a#b").unwrap(), [word("a#b")]);
+        assert_eq!(tokenize("a \\\n b").unwrap(), [word("a"), word("b")]);
+        assert_eq!(tokenize("a\\\nb").unwrap(), [word("ab")]);
+
+        // Inside single quotes a backslash and a newline are kept
+        assert_eq!(tokenize("'a\\\nb'").unwrap(), [word("'a\\\nb'")]);
+    }
+
+    #[test]
+    fn unclosed_quotes() {
+        assert_eq!(
+            tokenize("echo 'a").unwrap_err(),
+            "unexpected EOF while looking for matching `''"
+        );
+        assert!(tokenize("echo \"a").is_err());
+        assert!(tokenize("echo \"$(a\"").is_err());
+        assert!(tokenize("echo `a").is_err());
+        assert!(tokenize("echo ${a").is_err());
+        assert!(tokenize("echo $((1 + 2)").is_err());
+        assert!(tokenize("a=(b c").is_err());
+    }
+
+    #[test]
+    fn parentheses_inside_words() {
+        assert_eq!(
+            tokenize("a=(b 'c d') ls !(*.o|x)").unwrap(),
+            [word("a=(b 'c d')"), word("ls"), word("!(*.o|x)")]
+        );
+        assert_eq!(
+            tokenize("f() (a)").unwrap(),
+            [
+                word("f"),
+                Token::Operator("("),
+                Token::Operator(")"),
+                Token::Operator("("),
+                word("a"),
+                Token::Operator(")"),
+            ]
+        );
+    }
+
+    #[test]
+    fn conditionals() {
+        // Inside [[ ]] && || ( ) < > are words and the right side of =~ is one word
+        assert_eq!(
+            tokenize("[[ ( a < b ) && c =~ ^(x|y) ]] && d").unwrap(),
+            [
+                word("[["),
+                word("("),
+                word("a"),
+                word("<"),
+                word("b"),
+                word(")"),
+                word("&&"),
+                word("c"),
+                word("=~"),
+                word("^(x|y)"),
+                word("]]"),
+                Token::Operator("&&"),
+                word("d"),
+            ]
+        );
+
+        // [[ is only special as a command name
+        assert_eq!(
+            tokenize("echo [[ a && b").unwrap(),
+            [
+                word("echo"),
+                word("[["),
+                word("a"),
+                Token::Operator("&&"),
+                word("b")
+            ]
+        );
+    }
+
+    #[test]
+    fn split_into_words() {
+        assert_eq!(
+            split_words(" a 'b c'\n d=(e f) ").unwrap(),
+            ["a", "'b c'", "d=(e f)"]
+        );
+    }
+}
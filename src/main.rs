@@ -1,17 +1,62 @@
+mod arith;
+mod builtins;
+mod conditional;
+mod dirs;
+mod editor;
+mod expand;
+mod frecency;
+mod graphemes;
+mod hash;
 mod history;
+mod keymap;
+mod lexer;
+mod options;
+mod parser;
+mod paths;
+mod pattern;
 mod preprocess;
 mod process;
 mod read;
+mod render;
+mod shell;
+mod suggest;
+mod sys;
+mod terminal;
+#[cfg(test)]
+mod testing;
+mod user;
+mod vars;
+mod vi;
 
+use frecency::DirectoryDatabase;
 use history::get_history_handler;
+use shell::Shell;
+use user::User;
 
 fn main() {
     // Ignore Ctrl+C signal
     ctrlc::set_handler(move || {}).expect("Error setting Ctrl-C handler");
 
+    // Find out who runs the shell and where their home directory is
+    let user = User::current();
+
+    let mut shell = Shell::new(user);
+
+    // Run the rc file, it can choose where the history is kept with HISTFILE
+    process::run_rc_file(&mut shell);
+
     // Get a history handler
-    let history_handler = get_history_handler();
+    shell.history = get_history_handler(&shell);
+
+    // Visited directories are kept next to the history, wherever HISTFILE puts it, in a file
+    // named after it like history.directories
+    let directories_file = shell.history.file().map(|file| {
+        let mut name = file.file_name().unwrap_or_default().to_os_string();
+        name.push(".directories");
+        file.with_file_name(name)
+    });
+    shell.directories = DirectoryDatabase::new(directories_file);
 
     // Start shell
-    read::read_loop(history_handler);
+    read::read_loop(shell);
 }
@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+// Names of the options that can be toggled with the shopt builtin
+const SHOPT_NAMES: [&str; 4] = ["autocd", "cdspell", "dirspell", "extglob"];
+
+// Names of the options that can be set with set -o
+// They are the editing modes of the line editor, exactly one of them is on
+const SET_NAMES: [&str; 2] = ["emacs", "vi"];
+
+// Shell options that change how commands are interpreted
+pub struct Options {
+    shopt: BTreeMap<&'static str, bool>,
+    set: BTreeMap<&'static str, bool>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        // Every option is disabled by default, except the emacs editing mode
+        let shopt = SHOPT_NAMES.iter().map(|name| (*name, false)).collect();
+        let set = SET_NAMES
+            .iter()
+            .map(|name| (*name, *name == "emacs"))
+            .collect();
+
+        Options { shopt, set }
+    }
+}
+
+impl Options {
+    // Whether shopt option `name` is enabled
+    pub fn shopt(&self, name: &str) -> bool {
+        self.shopt.get(name).copied().unwrap_or(false)
+    }
+
+    // Enable or disable shopt option `name`
+    pub fn set_shopt(&mut self, name: &str, value: bool) -> Result<(), String> {
+        match self.shopt.get_mut(name) {
+            Some(option) => {
+                *option = value;
+                Ok(())
+            }
+            None => Err(format!("{}: invalid shell option name", name)),
+        }
+    }
+
+    // All shopt options and their values sorted by name
+    pub fn shopt_options(&self) -> Vec<(&'static str, bool)> {
+        self.shopt
+            .iter()
+            .map(|(name, value)| (*name, *value))
+            .collect()
+    }
+
+    // Whether set -o option `name` is enabled
+    pub fn is_set(&self, name: &str) -> bool {
+        self.set.get(name).copied().unwrap_or(false)
+    }
+
+    // Enable or disable set -o option `name`
+    // Enabling an editing mode disables the other one, disabling it goes back to the other one
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        if !self.set.contains_key(name) {
+            return Err(format!("{}: invalid option name", name));
+        }
+
+        for mode in SET_NAMES.iter() {
+            self.set.insert(mode, (*mode == name) == value);
+        }
+
+        Ok(())
+    }
+
+    // All set -o options and their values sorted by name
+    pub fn set_options(&self) -> Vec<(&'static str, bool)> {
+        self.set
+            .iter()
+            .map(|(name, value)| (*name, *value))
+            .collect()
+    }
+}
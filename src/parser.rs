@@ -0,0 +1,630 @@
+use crate::lexer::{self, Token};
+use std::fmt;
+use std::rc::Rc;
+
+// Parse the tokens of a command line into a syntax tree
+//
+// list      : and_or ((';' | '&' | newline) and_or)*
+// and_or    : pipeline (('&&' | '||') pipeline)*
+// pipeline  : ['!'] command ('|' command)*
+// command   : simple_command | compound redirection* | function
+// compound  : '(' list ')' | '{' list '}' | case
+// case      : 'case' word 'in' (['('] word ('|' word)* ')' list ';;')* 'esac'
+// function  : name '(' ')' compound | 'function' name ['(' ')'] compound
+
+// Words that are part of the syntax when they are in the position of a command name
+pub const KEYWORDS: [&str; 9] = ["!", "{", "}", "[[", "]]", "function", "case", "in", "esac"];
+
+// Commands separated by ; & or newlines
+pub struct List {
+    pub items: Vec<ListItem>,
+}
+
+pub struct ListItem {
+    pub and_or: AndOr,
+
+    // Whether the commands are followed by & and run in the background
+    pub background: bool,
+}
+
+// Pipelines connected with && and ||
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+pub struct Pipeline {
+    // Whether the pipeline starts with ! to negate its exit status
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+pub enum Command {
+    Simple(SimpleCommand),
+
+    // ( list ) runs in a forked copy of the shell
+    Subshell(List, Vec<Redirection>),
+
+    // { list; } runs in the current shell
+    Group(List, Vec<Redirection>),
+
+    // Definition of a function with its name and body
+    Function(String, Rc<Command>),
+
+    // case word in pattern) list;; esac runs the list of the first pattern matching the word
+    Case(String, Vec<CaseItem>, Vec<Redirection>),
+}
+
+pub struct CaseItem {
+    // Patterns separated by |, as typed
+    pub patterns: Vec<String>,
+    pub list: List,
+}
+
+pub struct SimpleCommand {
+    // Assignments, command name and arguments as typed
+    pub words: Vec<String>,
+    pub redirections: Vec<Redirection>,
+}
+
+// Why a command line could not be parsed
+#[derive(Debug, PartialEq)]
+pub enum SyntaxError {
+    // The input ended where a command or a closing token was expected, more lines can complete it
+    UnexpectedEnd,
+
+    // A token that cannot appear where it is, as typed
+    UnexpectedToken(String),
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyntaxError::UnexpectedEnd => write!(f, "syntax error: unexpected end of file"),
+            SyntaxError::UnexpectedToken(token) => {
+                write!(f, "syntax error near unexpected token `{}'", token)
+            }
+        }
+    }
+}
+
+pub struct Redirection {
+    // File descriptor written before the operator, like 2 in 2>err
+    pub fd: Option<i32>,
+    pub operator: &'static str,
+    pub target: String,
+}
+
+// Parse a complete command line
+pub fn parse(tokens: &[Token]) -> Result<List, SyntaxError> {
+    let mut parser = Parser { tokens, pos: 0 };
+    let list = parser.list()?;
+
+    match parser.peek() {
+        None => Ok(list),
+        Some(token) => Err(unexpected(Some(token))),
+    }
+}
+
+// Whether `source` is a complete command line, more lines are needed after an unclosed quote,
+// a trailing backslash, a trailing | && or || and inside an unclosed (, { or case
+// fsh has no if, while or for, so a line ending with then or do is complete
+pub fn is_complete(source: &str) -> bool {
+    let trailing_backslashes = source.chars().rev().take_while(|&c| c == '\\').count();
+    if trailing_backslashes % 2 == 1 {
+        return false;
+    }
+
+    // The lexer only fails on unclosed quotes and expansions
+    let tokens = match lexer::tokenize(source) {
+        Ok(tokens) => tokens,
+        Err(_) => return false,
+    };
+
+    !matches!(parse(&tokens), Err(SyntaxError::UnexpectedEnd))
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    // Whether the next token is the reserved word `word`
+    fn peek_reserved(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(next)) if next == word)
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek_operator() == Some("\n") {
+            self.pos += 1;
+        }
+    }
+
+    // A list ends at the end of the line, at the ) or } closing a compound command or at the ;;
+    // or esac ending an item of a case command
+    fn list(&mut self) -> Result<List, SyntaxError> {
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            if self.peek().is_none()
+                || matches!(self.peek_operator(), Some(")") | Some(";;"))
+                || self.peek_reserved("}")
+                || self.peek_reserved("esac")
+            {
+                break;
+            }
+
+            let and_or = self.and_or()?;
+
+            let background = match self.peek_operator() {
+                Some("&") => true,
+                Some(";") | Some("\n") => false,
+                _ => {
+                    items.push(ListItem {
+                        and_or,
+                        background: false,
+                    });
+                    break;
+                }
+            };
+            self.pos += 1;
+
+            items.push(ListItem { and_or, background });
+        }
+
+        Ok(List { items })
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, SyntaxError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+
+        loop {
+            let connector = match self.peek_operator() {
+                Some("&&") => Connector::And,
+                Some("||") => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+
+            rest.push((connector, self.pipeline()?));
+        }
+
+        Ok(AndOr { first, rest })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, SyntaxError> {
+        let negated = self.peek_reserved("!");
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut commands = vec![self.command()?];
+
+        while self.peek_operator() == Some("|") {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn command(&mut self) -> Result<Command, SyntaxError> {
+        if let Some(command) = self.compound_command()? {
+            return Ok(command);
+        }
+
+        if self.peek_reserved("function") {
+            self.pos += 1;
+            let name = match self.peek() {
+                Some(Token::Word(name)) => name.clone(),
+                token => return Err(unexpected(token)),
+            };
+            self.pos += 1;
+
+            // The parentheses are optional after the name
+            if self.peek_operator() == Some("(") {
+                self.pos += 1;
+                self.expect_operator(")")?;
+            }
+
+            return self.function_body(name);
+        }
+
+        if let (Some(Token::Word(name)), Some(Token::Operator("("))) =
+            (self.peek(), self.tokens.get(self.pos + 1))
+        {
+            self.pos += 2;
+            self.expect_operator(")")?;
+
+            return self.function_body(name.clone());
+        }
+
+        self.simple_command()
+    }
+
+    // Parse ( list ), { list; } or a case command with their redirections if one comes next
+    fn compound_command(&mut self) -> Result<Option<Command>, SyntaxError> {
+        if self.peek_operator() == Some("(") {
+            self.pos += 1;
+            let list = self.compound_list(")")?;
+            return Ok(Some(Command::Subshell(list, self.redirections()?)));
+        }
+
+        if self.peek_reserved("{") {
+            self.pos += 1;
+            let list = self.compound_list("}")?;
+            return Ok(Some(Command::Group(list, self.redirections()?)));
+        }
+
+        if self.peek_reserved("case") {
+            self.pos += 1;
+            let (word, items) = self.case_clause()?;
+            return Ok(Some(Command::Case(word, items, self.redirections()?)));
+        }
+
+        Ok(None)
+    }
+
+    // The word and the items of a case command up to and including esac
+    fn case_clause(&mut self) -> Result<(String, Vec<CaseItem>), SyntaxError> {
+        let word = self.word()?;
+
+        self.skip_newlines();
+        if !self.peek_reserved("in") {
+            return Err(unexpected(self.peek()));
+        }
+        self.pos += 1;
+
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            if self.peek_reserved("esac") {
+                self.pos += 1;
+                return Ok((word, items));
+            }
+
+            // The patterns can also start with an opening parenthesis
+            if self.peek_operator() == Some("(") {
+                self.pos += 1;
+            }
+
+            let mut patterns = vec![self.word()?];
+            while self.peek_operator() == Some("|") {
+                self.pos += 1;
+                patterns.push(self.word()?);
+            }
+            self.expect_operator(")")?;
+
+            let list = self.list()?;
+            items.push(CaseItem { patterns, list });
+
+            // The ;; can be left out after the last item
+            if self.peek_operator() == Some(";;") {
+                self.pos += 1;
+            } else if !self.peek_reserved("esac") {
+                return Err(unexpected(self.peek()));
+            }
+        }
+    }
+
+    fn word(&mut self) -> Result<String, SyntaxError> {
+        match self.peek() {
+            Some(Token::Word(word)) => {
+                self.pos += 1;
+                Ok(word.clone())
+            }
+            token => Err(unexpected(token)),
+        }
+    }
+
+    // The body of a function is a compound command that can start on the next line
+    fn function_body(&mut self, name: String) -> Result<Command, SyntaxError> {
+        self.skip_newlines();
+
+        match self.compound_command()? {
+            Some(body) => Ok(Command::Function(name, Rc::new(body))),
+            None => Err(unexpected(self.peek())),
+        }
+    }
+
+    fn expect_operator(&mut self, operator: &str) -> Result<(), SyntaxError> {
+        if self.peek_operator() != Some(operator) {
+            return Err(unexpected(self.peek()));
+        }
+        self.pos += 1;
+
+        Ok(())
+    }
+
+    // The body of a compound command up to and including its `close` token
+    fn compound_list(&mut self, close: &str) -> Result<List, SyntaxError> {
+        let list = self.list()?;
+
+        let closed = match self.peek() {
+            Some(Token::Operator(operator)) => *operator == close,
+            Some(Token::Word(word)) => word == close,
+            _ => false,
+        };
+
+        if list.items.is_empty() || !closed {
+            return Err(unexpected(self.peek()));
+        }
+        self.pos += 1;
+
+        Ok(list)
+    }
+
+    fn simple_command(&mut self) -> Result<Command, SyntaxError> {
+        let mut words = Vec::new();
+        let mut redirections = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    words.push(word.clone());
+                    self.pos += 1;
+                }
+                _ => match self.redirection()? {
+                    Some(redirection) => redirections.push(redirection),
+                    None => break,
+                },
+            }
+        }
+
+        if words.is_empty() && redirections.is_empty() {
+            return Err(unexpected(self.peek()));
+        }
+
+        Ok(Command::Simple(SimpleCommand {
+            words,
+            redirections,
+        }))
+    }
+
+    fn redirections(&mut self) -> Result<Vec<Redirection>, SyntaxError> {
+        let mut redirections = Vec::new();
+
+        while let Some(redirection) = self.redirection()? {
+            redirections.push(redirection);
+        }
+
+        Ok(redirections)
+    }
+
+    // Parse a redirection like 2>>file if one comes next
+    fn redirection(&mut self) -> Result<Option<Redirection>, SyntaxError> {
+        let start = self.pos;
+
+        let fd = match self.peek() {
+            Some(Token::IoNumber(fd)) => {
+                self.pos += 1;
+                Some(*fd)
+            }
+            _ => None,
+        };
+
+        let operator = match self.peek_operator() {
+            Some(operator) if operator.contains(['<', '>']) => operator,
+            _ => {
+                self.pos = start;
+                return Ok(None);
+            }
+        };
+        self.pos += 1;
+
+        match self.peek() {
+            Some(Token::Word(target)) => {
+                self.pos += 1;
+                Ok(Some(Redirection {
+                    fd,
+                    operator,
+                    target: target.clone(),
+                }))
+            }
+            Some(token) => Err(unexpected(Some(token))),
+            None => Err(unexpected(Some(&Token::Operator("\n")))),
+        }
+    }
+}
+
+// The error for an unexpected token, None is the end of the input where a command was expected
+fn unexpected(token: Option<&Token>) -> SyntaxError {
+    let token = match token {
+        Some(Token::Word(word)) => word.clone(),
+        Some(Token::Operator("\n")) => String::from("newline"),
+        Some(Token::Operator(operator)) => operator.to_string(),
+        Some(Token::IoNumber(fd)) => fd.to_string(),
+        None => return SyntaxError::UnexpectedEnd,
+    };
+
+    SyntaxError::UnexpectedToken(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<List, SyntaxError> {
+        parse(&lexer::tokenize(line).unwrap())
+    }
+
+    // The words of each simple command of `line`, in order
+    fn commands(line: &str) -> Vec<Vec<String>> {
+        let list = parse_line(line).unwrap();
+        let mut commands = Vec::new();
+
+        for item in &list.items {
+            let and_or = &item.and_or;
+            let pipelines =
+                std::iter::once(&and_or.first).chain(and_or.rest.iter().map(|(_, p)| p));
+            for pipeline in pipelines {
+                for command in &pipeline.commands {
+                    if let Command::Simple(command) = command {
+                        commands.push(command.words.clone());
+                    }
+                }
+            }
+        }
+
+        commands
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            commands("a 1; b && c | d\ne & f || g"),
+            [
+                vec!["a", "1"],
+                vec!["b"],
+                vec!["c"],
+                vec!["d"],
+                vec!["e"],
+                vec!["f"],
+                vec!["g"]
+            ]
+        );
+
+        let list = parse_line("a & b; ! c | d").unwrap();
+        assert!(list.items[0].background);
+        assert!(!list.items[1].background);
+        assert!(list.items[2].and_or.first.negated);
+        assert_eq!(list.items[2].and_or.first.commands.len(), 2);
+    }
+
+    #[test]
+    fn comments_and_continuations() {
+        assert_eq!(commands("echo a # b; c"), [vec!["echo", "a"]]);
+        assert_eq!(commands("# only a comment"), Vec::<Vec<String>>::new());
+        assert_eq!(commands("echo a#b"), [vec!["echo", "a#b"]]);
+        assert_eq!(commands("echo a \\\n  b"), [vec!["echo", "a", "b"]]);
+        assert_eq!(commands("echo a\\\nb"), [vec!["echo", "ab"]]);
+        assert_eq!(
+            commands("echo a |\n  # comment\n  cat"),
+            [vec!["echo", "a"], vec!["cat"]]
+        );
+        assert_eq!(commands("echo a &&\n\n b"), [vec!["echo", "a"], vec!["b"]]);
+        assert_eq!(commands("echo 'a\nb'"), [vec!["echo", "'a\nb'"]]);
+    }
+
+    #[test]
+    fn incomplete_input() {
+        // More lines can complete the command
+        for line in [
+            "echo 'a",
+            "echo \"a",
+            "echo `a",
+            "echo $(a",
+            "echo ${a",
+            "echo a \\",
+            "echo a |",
+            "echo a &&",
+            "echo a ||",
+            "echo a |\n",
+            "( echo a",
+            "{ echo a",
+            "{ echo a\n",
+            "f() {",
+            "f()",
+            "function f",
+        ]
+        .iter()
+        {
+            assert!(!is_complete(line), "{:?} is complete", line);
+        }
+
+        for line in [
+            "",
+            "echo a",
+            "echo 'a\nb'",
+            "echo a \\\\",
+            "echo a # '",
+            "echo a &",
+            "( echo a )",
+            "{ echo a; }",
+            "f() { echo a; }",
+        ]
+        .iter()
+        {
+            assert!(is_complete(line), "{:?} is incomplete", line);
+        }
+
+        // Syntax errors can not be completed and the line is run to report them
+        assert!(is_complete("echo a | | b"));
+        assert!(is_complete("echo a )"));
+        assert_eq!(
+            parse_line("echo a |").err(),
+            Some(SyntaxError::UnexpectedEnd)
+        );
+        assert_eq!(
+            parse_line("echo a ) b").err(),
+            Some(SyntaxError::UnexpectedToken(String::from(")")))
+        );
+        assert_eq!(
+            parse_line("echo > ;").err().unwrap().to_string(),
+            "syntax error near unexpected token `;'"
+        );
+    }
+
+    // The patterns of each item of the case command that makes up `line`
+    fn case_patterns(line: &str) -> Vec<Vec<String>> {
+        let list = parse_line(line).unwrap();
+        match &list.items[0].and_or.first.commands[0] {
+            Command::Case(_, items, _) => items.iter().map(|item| item.patterns.clone()).collect(),
+            _ => panic!("not a case command: {}", line),
+        }
+    }
+
+    #[test]
+    fn case_command() {
+        assert_eq!(
+            case_patterns("case $x in a|b) echo ab;; (c) echo c;; *) ;; esac"),
+            [vec!["a", "b"], vec!["c"], vec!["*"]]
+        );
+        assert_eq!(
+            case_patterns(
+                "case $x\nin\n  @(a|b)) echo ab\n    echo done\n  ;;\n  !(*.c)) echo other\nesac"
+            ),
+            [vec!["@(a|b)"], vec!["!(*.c)"]]
+        );
+        assert!(case_patterns("case x in esac").is_empty());
+        assert!(parse_line("case x in a) echo a;; esac > out").is_ok());
+        assert!(parse_line("case x in a) echo a;; esac; echo next").is_ok());
+    }
+
+    #[test]
+    fn invalid_case_command() {
+        assert_eq!(
+            parse_line("case x a) echo a;; esac").err(),
+            Some(SyntaxError::UnexpectedToken(String::from("a")))
+        );
+        assert!(parse_line("case x in a echo a;; esac").is_err());
+        assert!(parse_line("case x in a) echo a; b) echo b;; esac").is_err());
+        assert!(parse_line("echo a;; echo b").is_err());
+        assert!(!is_complete("case x in"));
+        assert!(!is_complete("case x in a) echo a;;"));
+        assert!(is_complete("case x in a) echo a;; esac"));
+    }
+}
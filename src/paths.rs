@@ -0,0 +1,51 @@
+use crate::shell::Shell;
+use std::path::{Path, PathBuf};
+
+// Files of fsh are kept in the directories of the XDG base directory specification
+
+// Directory of the rc file, $XDG_CONFIG_HOME/fsh or ~/.config/fsh
+pub fn config_dir(shell: &Shell) -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config", shell)
+}
+
+// Directory of data files, $XDG_DATA_HOME/fsh or ~/.local/share/fsh
+// fsh keeps nothing there, but versions of the specification before XDG_STATE_HOME put history
+// in it, so a history file moved there by hand is still picked up
+pub fn data_dir(shell: &Shell) -> Option<PathBuf> {
+    base_dir("XDG_DATA_HOME", ".local/share", shell)
+}
+
+// Directory of the history and other files that change as the shell is used,
+// $XDG_STATE_HOME/fsh or ~/.local/state/fsh
+pub fn state_dir(shell: &Shell) -> Option<PathBuf> {
+    base_dir("XDG_STATE_HOME", ".local/state", shell)
+}
+
+// Directory where fsh kept all its files before, ~/.fsh
+pub fn legacy_dir(shell: &Shell) -> Option<PathBuf> {
+    home(shell).map(|home| Path::new(home).join(".fsh"))
+}
+
+// Commands run when an interactive shell starts
+pub fn rc_file(shell: &Shell) -> Option<PathBuf> {
+    config_dir(shell).map(|dir| dir.join("fshrc"))
+}
+
+// The directory in `var`, or `default` in the home directory when it is unset
+// The specification says a relative path in `var` is ignored
+fn base_dir(var: &str, default: &str, shell: &Shell) -> Option<PathBuf> {
+    let base = match shell.vars.get(var).filter(|dir| dir.starts_with('/')) {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(home(shell)?).join(default),
+    };
+
+    Some(base.join("fsh"))
+}
+
+fn home(shell: &Shell) -> Option<&str> {
+    shell
+        .vars
+        .get("HOME")
+        .filter(|home| !home.is_empty())
+        .or(shell.user.home.as_deref())
+}
@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// A compiled shell pattern
+// Supports `*`, `?`, bracket expressions and, if extglob is enabled, the ksh extended patterns:
+// ?(pat|pat) zero or one, *(pat) zero or more, +(pat) one or more, @(pat) exactly one and !(pat) anything except
+pub struct Pattern {
+    nodes: Vec<Node>,
+}
+
+enum Node {
+    Char(char),
+    AnyChar,
+    AnyString,
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+    Extended {
+        kind: ExtKind,
+        alternatives: Vec<Vec<Node>>,
+    },
+}
+
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Named(String),
+}
+
+#[derive(Clone, Copy)]
+enum ExtKind {
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+    ExactlyOne,
+    Not,
+}
+
+impl Pattern {
+    // Compile `pattern`. Malformed constructs like an unclosed `[` are taken literally
+    pub fn new(pattern: &str, extglob: bool) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut pos = 0;
+        let nodes = parse_sequence(&chars, &mut pos, extglob, false);
+
+        Pattern { nodes }
+    }
+
+    // Whether the whole `text` is matched by this pattern
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        self.matches_chars(&chars)
+    }
+
+    pub fn matches_chars(&self, text: &[char]) -> bool {
+        let mut matcher = Matcher {
+            text,
+            sequences: HashMap::new(),
+            repetitions: HashMap::new(),
+        };
+
+        matcher.match_nodes(&self.nodes, 0, text.len())
+    }
+
+    // Whether this pattern starts with a literal dot
+    // Files whose name begins with a dot are only matched by such patterns
+    fn matches_hidden(&self) -> bool {
+        matches!(self.nodes.first(), Some(Node::Char('.')))
+    }
+}
+
+// Whether `pattern` contains any unescaped pattern character
+pub fn has_magic(pattern: &str, extglob: bool) -> bool {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut index = 0;
+
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '*' | '?' => return true,
+            '[' if bracket_end(&chars, index).is_some() => return true,
+            '+' | '@' | '!' if extglob && group_end(&chars, index).is_some() => return true,
+            _ => {}
+        }
+        index += 1;
+    }
+
+    false
+}
+
+// Remove the backslashes used to escape pattern characters
+pub fn unescape(pattern: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                unescaped.push(escaped);
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+
+    unescaped
+}
+
+// Expand `pattern` to the sorted list of paths it matches
+// Returns an empty list if nothing matches
+pub fn expand(pattern: &str, extglob: bool) -> Vec<String> {
+    let (mut candidates, rest) = if let Some(rest) = pattern.strip_prefix('/') {
+        (vec![String::from("/")], rest)
+    } else {
+        (vec![String::new()], pattern)
+    };
+
+    let segments: Vec<&str> = rest.split('/').collect();
+
+    for (index, segment) in segments.iter().enumerate() {
+        let is_last = index == segments.len() - 1;
+
+        // A trailing slash only keeps directories
+        if segment.is_empty() {
+            if is_last {
+                candidates.retain(|candidate| Path::new(candidate).is_dir());
+                candidates = candidates
+                    .into_iter()
+                    .map(|candidate| candidate + "/")
+                    .collect();
+            }
+            continue;
+        }
+
+        let mut next_candidates = Vec::new();
+
+        for candidate in &candidates {
+            if has_magic(segment, extglob) {
+                let segment_pattern = Pattern::new(segment, extglob);
+                let dir = if candidate.is_empty() {
+                    "."
+                } else {
+                    candidate.as_str()
+                };
+
+                let mut names: Vec<String> = match fs::read_dir(dir) {
+                    Ok(entries) => entries
+                        .filter_map(|entry| entry.ok())
+                        .filter_map(|entry| entry.file_name().into_string().ok())
+                        .filter(|name| !name.starts_with('.') || segment_pattern.matches_hidden())
+                        .filter(|name| segment_pattern.matches(name))
+                        .collect(),
+                    Err(_) => continue,
+                };
+                names.sort();
+
+                for name in names {
+                    next_candidates.push(join(candidate, &name));
+                }
+            } else {
+                let path = join(candidate, &unescape(segment));
+
+                // Intermediate literal segments are checked when the next magic segment is read
+                if !is_last || fs::symlink_metadata(&path).is_ok() {
+                    next_candidates.push(path);
+                }
+            }
+        }
+
+        candidates = next_candidates;
+    }
+
+    candidates
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+// Parse nodes until the end of the pattern or, inside an extended group, until `|` or `)`
+fn parse_sequence(chars: &[char], pos: &mut usize, extglob: bool, in_group: bool) -> Vec<Node> {
+    let mut nodes = Vec::new();
+
+    while *pos < chars.len() {
+        let c = chars[*pos];
+
+        if in_group && (c == '|' || c == ')') {
+            break;
+        }
+
+        match c {
+            '\\' => {
+                *pos += 1;
+                if let Some(&escaped) = chars.get(*pos) {
+                    nodes.push(Node::Char(escaped));
+                    *pos += 1;
+                } else {
+                    nodes.push(Node::Char('\\'));
+                }
+            }
+            '?' | '*' | '+' | '@' | '!' if extglob && group_end(chars, *pos).is_some() => {
+                let kind = match c {
+                    '?' => ExtKind::ZeroOrOne,
+                    '*' => ExtKind::ZeroOrMore,
+                    '+' => ExtKind::OneOrMore,
+                    '@' => ExtKind::ExactlyOne,
+                    _ => ExtKind::Not,
+                };
+
+                // Skip the operator and the opening parenthesis
+                *pos += 2;
+
+                let mut alternatives = vec![parse_sequence(chars, pos, extglob, true)];
+                while chars.get(*pos) == Some(&'|') {
+                    *pos += 1;
+                    alternatives.push(parse_sequence(chars, pos, extglob, true));
+                }
+
+                // Skip the closing parenthesis
+                *pos += 1;
+
+                nodes.push(Node::Extended { kind, alternatives });
+            }
+            '?' => {
+                nodes.push(Node::AnyChar);
+                *pos += 1;
+            }
+            '*' => {
+                // Consecutive stars match the same as a single one
+                if !matches!(nodes.last(), Some(Node::AnyString)) {
+                    nodes.push(Node::AnyString);
+                }
+                *pos += 1;
+            }
+            '[' => match bracket_end(chars, *pos) {
+                Some(end) => {
+                    nodes.push(parse_class(&chars[*pos + 1..end]));
+                    *pos = end + 1;
+                }
+                None => {
+                    nodes.push(Node::Char('['));
+                    *pos += 1;
+                }
+            },
+            _ => {
+                nodes.push(Node::Char(c));
+                *pos += 1;
+            }
+        }
+    }
+
+    nodes
+}
+
+// Index of the `]` closing the bracket expression that starts at `start`
+fn bracket_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut index = start + 1;
+
+    if matches!(chars.get(index), Some('!') | Some('^')) {
+        index += 1;
+    }
+
+    // A `]` right after the opening bracket is a member of the set
+    if chars.get(index) == Some(&']') {
+        index += 1;
+    }
+
+    while index < chars.len() {
+        match chars[index] {
+            ']' => return Some(index),
+            '\\' => index += 1,
+            '[' if chars.get(index + 1) == Some(&':') => {
+                // Skip over a character class name like [:alpha:]
+                if let Some(offset) = chars[index + 2..].windows(2).position(|w| w == [':', ']']) {
+                    index += offset + 3;
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+
+    None
+}
+
+// Index of the `)` closing the extended group whose operator is at `start`
+fn group_end(chars: &[char], start: usize) -> Option<usize> {
+    if chars.get(start + 1) != Some(&'(') {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut index = start + 1;
+
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            '[' => {
+                if let Some(end) = bracket_end(chars, index) {
+                    index = end;
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+
+    None
+}
+
+// Parse the content of a bracket expression without the surrounding brackets
+fn parse_class(chars: &[char]) -> Node {
+    let mut items = Vec::new();
+    let mut index = 0;
+
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+    if negated {
+        index += 1;
+    }
+
+    while index < chars.len() {
+        let mut c = chars[index];
+
+        if c == '[' && chars.get(index + 1) == Some(&':') {
+            if let Some(offset) = chars[index + 2..].windows(2).position(|w| w == [':', ']']) {
+                let name: String = chars[index + 2..index + 2 + offset].iter().collect();
+                items.push(ClassItem::Named(name));
+                index += offset + 4;
+                continue;
+            }
+        }
+
+        if c == '\\' && index + 1 < chars.len() {
+            index += 1;
+            c = chars[index];
+        }
+
+        if chars.get(index + 1) == Some(&'-') && index + 2 < chars.len() {
+            items.push(ClassItem::Range(c, chars[index + 2]));
+            index += 3;
+        } else {
+            items.push(ClassItem::Char(c));
+            index += 1;
+        }
+    }
+
+    Node::Class { negated, items }
+}
+
+fn class_matches(items: &[ClassItem], c: char) -> bool {
+    items.iter().any(|item| match item {
+        ClassItem::Char(member) => *member == c,
+        ClassItem::Range(low, high) => *low <= c && c <= *high,
+        ClassItem::Named(name) => match name.as_str() {
+            "alnum" => c.is_alphanumeric(),
+            "alpha" => c.is_alphabetic(),
+            "blank" => c == ' ' || c == '\t',
+            "cntrl" => c.is_control(),
+            "digit" => c.is_ascii_digit(),
+            "graph" => !c.is_whitespace() && !c.is_control(),
+            "lower" => c.is_lowercase(),
+            "print" => !c.is_control(),
+            "punct" => c.is_ascii_punctuation(),
+            "space" => c.is_whitespace(),
+            "upper" => c.is_uppercase(),
+            "xdigit" => c.is_ascii_hexdigit(),
+            _ => false,
+        },
+    })
+}
+
+// Matches the nodes of a pattern against a text
+// A node can be tried from the same position many times, like the nodes after nested *(...)
+// groups, so the results are remembered by node and position and each is found only once
+struct Matcher<'a> {
+    text: &'a [char],
+
+    // Whether the nodes starting with a given node match text[start..end], by the address of
+    // the node, start and end
+    sequences: HashMap<(usize, usize, usize), bool>,
+
+    // Whether text[start..end] is a concatenation of matches of a group's alternatives, by the
+    // address of the alternatives, start and end
+    repetitions: HashMap<(usize, usize, usize), bool>,
+}
+
+impl Matcher<'_> {
+    // Whether `nodes` match the text from `index` up to `end`
+    fn match_nodes(&mut self, nodes: &[Node], index: usize, end: usize) -> bool {
+        let (node, rest) = match nodes.split_first() {
+            Some(split) => split,
+            None => return index == end,
+        };
+
+        let key = (node as *const Node as usize, index, end);
+        if let Some(&matched) = self.sequences.get(&key) {
+            return matched;
+        }
+
+        let matched = match node {
+            Node::Char(c) => {
+                index < end && self.text[index] == *c && self.match_nodes(rest, index + 1, end)
+            }
+            Node::AnyChar => index < end && self.match_nodes(rest, index + 1, end),
+            Node::AnyString => (index..=end).any(|next| self.match_nodes(rest, next, end)),
+            Node::Class { negated, items } => {
+                index < end
+                    && class_matches(items, self.text[index]) != *negated
+                    && self.match_nodes(rest, index + 1, end)
+            }
+            Node::Extended { kind, alternatives } => (index..=end).any(|next| {
+                self.extended_matches(*kind, alternatives, index, next)
+                    && self.match_nodes(rest, next, end)
+            }),
+        };
+
+        self.sequences.insert(key, matched);
+        matched
+    }
+
+    // Whether the group matches the text from `start` up to `end`
+    fn extended_matches(
+        &mut self,
+        kind: ExtKind,
+        alternatives: &[Vec<Node>],
+        start: usize,
+        end: usize,
+    ) -> bool {
+        match kind {
+            ExtKind::ZeroOrOne => start == end || self.any_alternative(alternatives, start, end),
+            ExtKind::ZeroOrMore => start == end || self.repeated(alternatives, start, end),
+            ExtKind::OneOrMore => self.repeated(alternatives, start, end),
+            ExtKind::ExactlyOne => self.any_alternative(alternatives, start, end),
+            ExtKind::Not => !self.any_alternative(alternatives, start, end),
+        }
+    }
+
+    fn any_alternative(&mut self, alternatives: &[Vec<Node>], start: usize, end: usize) -> bool {
+        alternatives
+            .iter()
+            .any(|alternative| self.match_nodes(alternative, start, end))
+    }
+
+    // Whether the text from `start` up to `end` is a concatenation of one or more matches of the
+    // alternatives, the last one can be empty
+    fn repeated(&mut self, alternatives: &[Vec<Node>], start: usize, end: usize) -> bool {
+        let key = (alternatives.as_ptr() as usize, start, end);
+        if let Some(&matched) = self.repetitions.get(&key) {
+            return matched;
+        }
+
+        // A match is found if the whole text is one, otherwise a non-empty first match must be
+        // followed by the repetition of the rest
+        let matched = self.any_alternative(alternatives, start, end)
+            || (start + 1..end).any(|next| {
+                self.any_alternative(alternatives, start, next)
+                    && self.repeated(alternatives, next, end)
+            });
+
+        self.repetitions.insert(key, matched);
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern, true).matches(text)
+    }
+
+    #[test]
+    fn basic_patterns() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[[:digit:]]*", "42"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+
+        // An unclosed bracket is taken literally
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn extended_patterns() {
+        assert!(matches("?(a|b)c", "c"));
+        assert!(matches("?(a|b)c", "ac"));
+        assert!(!matches("?(a|b)c", "abc"));
+        assert!(matches("*(ab)", ""));
+        assert!(matches("*(ab)", "ababab"));
+        assert!(!matches("*(ab)", "aba"));
+        assert!(!matches("+(ab)", ""));
+        assert!(matches("+(ab|c)", "abcab"));
+        assert!(matches("@(foo|bar).txt", "bar.txt"));
+        assert!(!matches("@(foo|bar).txt", "foobar.txt"));
+
+        // Without extglob the parentheses are literal
+        assert!(!Pattern::new("@(foo)", false).matches("foo"));
+        assert!(Pattern::new("@(foo)", false).matches("@(foo)"));
+    }
+
+    #[test]
+    fn negated_patterns() {
+        assert!(matches("!(foo)", "bar"));
+        assert!(matches("!(foo)", ""));
+        assert!(!matches("!(foo)", "foo"));
+        assert!(matches("!(*.c)", "main.rs"));
+        assert!(!matches("!(*.c)", "main.c"));
+        assert!(matches("!(*.c|*.h)", "Makefile"));
+        assert!(!matches("!(*.c|*.h)", "main.h"));
+        assert!(matches("lib!(foo).so", "libbar.so"));
+        assert!(!matches("lib!(foo).so", "libfoo.so"));
+    }
+
+    #[test]
+    fn nested_patterns() {
+        assert!(matches("+(a|*(b))", "abbab"));
+        assert!(!matches("+(a|*(b))", "abc"));
+        assert!(matches("@(x|+(ab))y", "ababy"));
+        assert!(matches("!(+(a))", "ab"));
+        assert!(!matches("!(+(a))", "aaa"));
+        assert!(matches("*(@(a|b)c)", "acbc"));
+    }
+
+    #[test]
+    fn nested_repetitions_are_not_exponential() {
+        let text = format!("{}b", "a".repeat(60));
+        assert!(!matches("*(*(a|aa))", &text));
+        assert!(!matches("+(a|aa)+(a|aa)+(a|aa)", &text));
+        assert!(matches("+(a|aa)+(a|aa)b", &text));
+    }
+
+    #[test]
+    fn magic_characters() {
+        assert!(has_magic("*.rs", false));
+        assert!(!has_magic("\\*.rs", false));
+        assert!(!has_magic("@(a)", false));
+        assert!(has_magic("@(a)", true));
+        assert_eq!(unescape("a\\*b"), "a*b");
+    }
+}
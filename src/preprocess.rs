@@ -1,30 +1,24 @@
+use crate::dirs;
+use crate::graphemes;
 use std::env;
 use termion::{color, style};
-use regex::Regex;
-use std::process;
 
 // Returns a prompt and its length without any style/color
-pub fn prompt() -> (String, usize) {
+// `home` is the home directory of the user, it is shown as a special and short character
+pub fn prompt(home: Option<&str>) -> (String, usize) {
     // Get current working directory
     let mut current_path = String::new();
     if let Ok(path) = env::current_dir() {
         current_path = path.to_str().unwrap().to_string();
     }
 
-    let mut username = String::new();
-
-    // Launch whoami program and collect its output
-    if let Ok(output) = process::Command::new("whoami").output() {
-        // Output of whoami program is the username
-        username = std::str::from_utf8(&output.stdout).unwrap().to_string().trim().to_string();
-    }
-
     // Replace home directory with special and short character
-    let home_dir_regex = format!("/home/{username}/|/home/{username}", username=username);
-    let re = Regex::new(&home_dir_regex).unwrap();
-    let current_path = re.replace(&current_path, "⌂|");
+    let current_path = match home.and_then(|home| dirs::strip_home(&current_path, home)) {
+        Some(rest) => format!("⌂|{}", rest.trim_start_matches('/')),
+        None => current_path,
+    };
 
-    let current_path_len = current_path.chars().count();
+    let current_path_len = graphemes::width(&current_path);
 
     let formatted_prompt = format!(
         "{current_path_style}{current_path_color}{current_path}{style_reset}{color_reset}$ ",
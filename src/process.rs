@@ -1,102 +1,1111 @@
-use crate::history::History;
-use glob::glob;
-use std::env;
-use std::process::Command;
-use std::str;
+use crate::builtins;
+use crate::conditional;
+use crate::dirs;
+use crate::expand;
+use crate::hash;
+use crate::lexer;
+use crate::parser::{self, AndOr, CaseItem, Command, Connector, List, Pipeline, Redirection};
+use crate::paths;
+use crate::pattern::Pattern;
+use crate::shell::Shell;
+use crate::suggest;
+use crate::sys::{self, Fork};
+use crate::vars::is_valid_name;
+use std::fs::{self, File, OpenOptions};
+use std::io::{stdout, BufRead, BufReader, Read, Write};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
 
-pub(crate) fn execute(command_line: String, history_handler: &mut History) {
-    // Split command line
-    // [0] is command and [1..] are arguments
-    let mut tokens: Vec<&str> = command_line
-        .rsplit(|c| c == ' ' || c == '\t' || c == '\r')
-        .rev()
-        .collect();
+// Execute a command line and remember its exit status in $?
+pub(crate) fn execute(command_line: String, shell: &mut Shell) {
+    sys::reap_children();
+    shell.last_status = run_line(&command_line, shell);
+}
 
-    // Expand arguments if they contain glob like: *.mp3
-    let args = expand_arguments(tokens.split_off(1));
+// Execute a command line and return its exit status
+pub(crate) fn run_line(command_line: &str, shell: &mut Shell) -> i32 {
+    let tokens = match lexer::tokenize(command_line) {
+        Ok(tokens) => tokens,
+        Err(msg) => {
+            eprintln!("fsh: syntax error: {}", msg);
+            return 2;
+        }
+    };
 
-    let command = tokens.pop().unwrap();
+    match parser::parse(&tokens) {
+        Ok(list) => run_list(&list, shell),
+        Err(msg) => {
+            eprintln!("fsh: {}", msg);
+            2
+        }
+    }
+}
 
-    match command {
-        "pwd" => pwd(),
-        "cd" => cd(args),
-        "echo" => echo(args),
-        "exit" => {
-            history_handler.save();
-            exit();
+// Run the commands of the rc file, if the user has one, before the first prompt
+pub(crate) fn run_rc_file(shell: &mut Shell) {
+    let rc_file = match paths::rc_file(shell) {
+        Some(rc_file) if rc_file.is_file() => rc_file,
+        _ => return,
+    };
+
+    match fs::read(&rc_file) {
+        Ok(source) => {
+            shell.last_status = run_line(&String::from_utf8_lossy(&source), shell);
         }
-        "history" => history(history_handler.get_history_elements()),
-        _ => launch_command(command, args), // External command
+        Err(err) => eprintln!("fsh: {}: {}", rc_file.display(), error_message(&err)),
     }
 }
 
-// Execute an external command/program and display its output
-fn launch_command(command: &str, args: Vec<String>) {
-    if let Ok(child) = Command::new(command).args(args).spawn() {
-        let output = child.wait_with_output().unwrap();
-        print!("{}", str::from_utf8(&output.stdout).unwrap());
-    } else {
-        println!("Something went wrong")
+// Run the commands of `list` one after the other and return the status of the last one
+fn run_list(list: &List, shell: &mut Shell) -> i32 {
+    let mut status = 0;
+
+    for item in &list.items {
+        status = if item.background {
+            run_in_background(&item.and_or, shell)
+        } else {
+            run_and_or(&item.and_or, shell)
+        };
+        shell.last_status = status;
+
+        if shell.returning {
+            break;
+        }
     }
+
+    status
 }
 
-// Built in commands
-fn pwd() {
-    if let Ok(path) = env::current_dir() {
-        println!("{}", path.display());
+// Run commands connected with && and || from left to right
+// A pipeline after && runs only if the previous one succeeded, after || only if it failed
+fn run_and_or(and_or: &AndOr, shell: &mut Shell) -> i32 {
+    let mut status = run_pipeline(&and_or.first, shell);
+
+    for (connector, pipeline) in &and_or.rest {
+        if shell.returning {
+            break;
+        }
+        if (*connector == Connector::And) == (status == 0) {
+            shell.last_status = status;
+            status = run_pipeline(pipeline, shell);
+        }
     }
+
+    status
 }
 
-fn echo(args: Vec<String>) {
-    println!("{}", args.join(" "));
+fn run_in_background(and_or: &AndOr, shell: &mut Shell) -> i32 {
+    // Flush pending output so the child does not write it a second time
+    flush_stdout();
+
+    match sys::fork() {
+        Ok(Fork::Child) => {
+            shell.subshell = true;
+            let status = run_and_or(and_or, shell);
+            flush_stdout();
+
+            sys::exit_now(status)
+        }
+        Ok(Fork::Parent(pid)) => {
+            shell.last_background = Some(pid);
+            0
+        }
+        Err(msg) => {
+            eprintln!("fsh: cannot fork: {}", msg);
+            1
+        }
+    }
 }
 
-fn exit() {
-    std::process::exit(0)
+// Run the commands of a pipeline, each one in a forked copy of the shell with its standard
+// output connected to the standard input of the next one
+// A single command runs in the current shell so builtins like cd can change its state
+fn run_pipeline(pipeline: &Pipeline, shell: &mut Shell) -> i32 {
+    let status = if pipeline.commands.len() == 1 {
+        run_command(&pipeline.commands[0], shell)
+    } else {
+        run_piped_commands(&pipeline.commands, shell)
+    };
+
+    if pipeline.negated {
+        (status == 0) as i32
+    } else {
+        status
+    }
 }
 
-fn cd(args: Vec<String>) {
-    // If a path is provided, change directory else change directory to home directory
-    if args.len() > 0 {
-        // If directory does not exist, print error message
-        if let Err(msg) = env::set_current_dir(&args[0]) {
-            println!("{}", msg);
+fn run_piped_commands(commands: &[Command], shell: &mut Shell) -> i32 {
+    let mut children = Vec::new();
+
+    // Read end of the pipe coming from the previous command
+    let mut input: Option<RawFd> = None;
+
+    flush_stdout();
+
+    for (index, command) in commands.iter().enumerate() {
+        let output = if index + 1 < commands.len() {
+            match sys::pipe() {
+                Ok(fds) => Some(fds),
+                Err(msg) => {
+                    eprintln!("fsh: cannot make pipe: {}", msg);
+                    break;
+                }
+            }
+        } else {
+            None
+        };
+
+        match sys::fork() {
+            Ok(Fork::Child) => {
+                if let Some(input) = input {
+                    sys::dup2(input, 0).unwrap();
+                    sys::close(input);
+                }
+                if let Some((read_fd, write_fd)) = output {
+                    sys::close(read_fd);
+                    sys::dup2(write_fd, 1).unwrap();
+                    sys::close(write_fd);
+                }
+
+                shell.subshell = true;
+                let status = run_command(command, shell);
+                flush_stdout();
+
+                sys::exit_now(status)
+            }
+            Ok(Fork::Parent(pid)) => children.push(pid),
+            Err(msg) => eprintln!("fsh: cannot fork: {}", msg),
         }
-    } else {
-        let mut username = String::new();
-        if let Ok(output) = Command::new("whoami").output() {
-            username = std::str::from_utf8(&output.stdout).unwrap().to_string();
+
+        if let Some(input) = input {
+            sys::close(input);
         }
-        env::set_current_dir(&format!("/home/{}", username.trim())).unwrap();
+        input = output.map(|(read_fd, write_fd)| {
+            sys::close(write_fd);
+            read_fd
+        });
     }
+
+    if let Some(input) = input {
+        sys::close(input);
+    }
+
+    // The status of a pipeline is the status of its last command
+    children.into_iter().fold(1, |_, pid| sys::wait_pid(pid))
 }
 
-fn history(history_elements: Vec<&String>) {
-    for element in history_elements {
-        println!("{}", element)
+fn run_command(command: &Command, shell: &mut Shell) -> i32 {
+    match command {
+        Command::Simple(command) => {
+            run_simple_command(&command.words, &command.redirections, shell)
+        }
+        Command::Subshell(list, redirections) => run_subshell(list, redirections, shell),
+        Command::Group(list, redirections) => {
+            with_redirections(redirections, shell, |shell| run_list(list, shell))
+        }
+        Command::Function(name, body) => {
+            shell.functions.insert(name.clone(), Rc::clone(body));
+            0
+        }
+        Command::Case(word, items, redirections) => {
+            with_redirections(redirections, shell, |shell| run_case(word, items, shell))
+        }
     }
 }
 
-// Expand each argument that contains glob like: *.mp3
-fn expand_arguments<'a>(args: Vec<&str>) -> Vec<String> {
-    let mut expanded_args = Vec::new();
+// Run the list of the first item with a pattern that matches `word`
+// The patterns are expanded one after the other until one matches
+fn run_case(word: &str, items: &[CaseItem], shell: &mut Shell) -> i32 {
+    let word = match expand::expand_string(word, shell) {
+        Ok(word) => word,
+        Err(msg) => {
+            eprintln!("fsh: {}", msg);
+            return 1;
+        }
+    };
+    let extglob = shell.options.shopt("extglob");
 
-    for arg in args {
-        if arg.contains('*') {
-            let path = std::path::Path::new(arg);
-            let paths = if path.is_relative() {
-                glob(&format!("./{}", arg)).expect("Failed to read glob pattern")
-            } else {
-                glob(arg).expect("Failed to read glob pattern")
+    for item in items {
+        for pattern in &item.patterns {
+            let pattern = match expand::expand_pattern(pattern, shell) {
+                Ok(pattern) => pattern,
+                Err(msg) => {
+                    eprintln!("fsh: {}", msg);
+                    return 1;
+                }
             };
 
-            for path in paths {
-                expanded_args.push(path.unwrap().as_path().to_str().unwrap().to_string());
+            if Pattern::new(&pattern, extglob).matches(&word) {
+                return run_list(&item.list, shell);
             }
+        }
+    }
+
+    0
+}
+
+// Run the body of a function with `args` as its positional parameters
+fn run_function(body: &Command, args: Vec<String>, shell: &mut Shell) -> i32 {
+    let positional = std::mem::replace(&mut shell.vars.positional, args);
+    shell.function_depth += 1;
+    let status = run_command(body, shell);
+    shell.function_depth -= 1;
+    shell.returning = false;
+    shell.vars.positional = positional;
+
+    status
+}
+
+// Run `list` in a forked copy of the shell so changes like cd or assignments do not leak
+fn run_subshell(list: &List, redirections: &[Redirection], shell: &mut Shell) -> i32 {
+    flush_stdout();
+
+    match sys::fork() {
+        Ok(Fork::Child) => {
+            shell.subshell = true;
+            let status = with_redirections(redirections, shell, |shell| run_list(list, shell));
+            flush_stdout();
+
+            sys::exit_now(status)
+        }
+        Ok(Fork::Parent(pid)) => sys::wait_pid(pid),
+        Err(msg) => {
+            eprintln!("fsh: cannot fork: {}", msg);
+            1
+        }
+    }
+}
+
+// Builtins whose arguments can be assignments like: declare -A map=([key]=value)
+const DECLARATION_BUILTINS: [&str; 3] = ["declare", "typeset", "export"];
+
+fn run_simple_command(words: &[String], redirections: &[Redirection], shell: &mut Shell) -> i32 {
+    // Leading words like NAME=value are assignments
+    let assignment_count = words
+        .iter()
+        .take_while(|word| parse_assignment(word).is_some())
+        .count();
+    let (assignments, words) = words.split_at(assignment_count);
+
+    // Status of the last command substitution, if any is performed
+    shell.substitution_status = None;
+
+    // Arguments of declaration builtins are expanded by the builtin itself
+    if let Some((command, args)) = words.split_first() {
+        if DECLARATION_BUILTINS.contains(&command.as_str()) {
+            return with_redirections(redirections, shell, |shell| {
+                builtins::declare(command, args, shell)
+            });
+        }
+
+        // Operands of [[ ... ]] are expanded while the expression is evaluated
+        if command == "[[" {
+            return with_redirections(redirections, shell, |shell| {
+                conditional::extended_test(args, shell)
+            });
+        }
+    }
+
+    let args = match expand::expand_words(words, shell) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("fsh: {}", msg);
+            return 1;
+        }
+    };
+
+    // `command name` runs `name` as a builtin or a program, with options it is the builtin itself
+    let mut args = &args[..];
+    let mut skip_functions = false;
+    while args.len() > 1 && args[0] == "command" && !args[1].starts_with('-') {
+        args = &args[1..];
+        skip_functions = true;
+    }
+
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.clone(), args.to_vec()),
+        None => {
+            // Without a command the assignments set shell variables
+            for word in assignments {
+                if let Err(msg) = assign(&parse_assignment(word).unwrap(), shell) {
+                    eprintln!("fsh: {}", msg);
+                    return 1;
+                }
+            }
+
+            let status = shell.substitution_status.unwrap_or(0);
+            return with_redirections(redirections, shell, |_| status);
+        }
+    };
+
+    let mut values = Vec::new();
+    for word in assignments {
+        let assignment = parse_assignment(word).unwrap();
+        match expand::expand_string(assignment.value, shell) {
+            Ok(value) => values.push((assignment.name.to_string(), value)),
+            Err(msg) => {
+                eprintln!("fsh: {}", msg);
+                return 1;
+            }
+        }
+    }
+
+    with_redirections(redirections, shell, |shell| {
+        if let Some(body) = shell.functions.get(&command).filter(|_| !skip_functions) {
+            return run_function(&Rc::clone(body), args, shell);
+        }
+
+        if let Some(status) = builtins::run(&command, args.clone(), shell) {
+            return status;
+        }
+
+        // With autocd a directory name typed alone at the prompt changes into it, as cd does
+        if shell.interactive
+            && shell.options.shopt("autocd")
+            && args.is_empty()
+            && Path::new(&command).is_dir()
+        {
+            eprintln!("cd -- {}", command);
+            return dirs::cd(vec![String::from("--"), command.clone()], shell);
+        }
+
+        // A PATH assigned before the command is also used to find it
+        let path = match values.iter().find(|(name, _)| name == "PATH") {
+            Some((_, path)) => path.clone(),
+            None => shell.vars.get("PATH").unwrap_or("").to_string(),
+        };
+
+        let program = match shell.hash.find(&command, &path) {
+            Some(program) => program,
+            None => return command_not_found(&command, args, &path, shell),
+        };
+
+        // Assignments before an external command are only added to its environment
+        let environment = shell.vars.environment(values);
+        launch_command(&command, &program, args, environment, shell)
+    })
+}
+
+// Run the command_not_found_handler function with the command and its arguments if it is
+// defined, otherwise report the command with the names that look like it
+fn command_not_found(command: &str, args: Vec<String>, path: &str, shell: &mut Shell) -> i32 {
+    // The handler is not defined while it runs so a missing command in it does not call it again
+    if let Some(handler) = shell.functions.remove(HANDLER) {
+        let status = run_function(&handler, [vec![command.to_string()], args].concat(), shell);
+        shell
+            .functions
+            .entry(HANDLER.to_string())
+            .or_insert(handler);
+        return status;
+    }
+
+    eprintln!("fsh: {}: command not found", command);
+
+    let programs = hash::program_names(path);
+    let candidates = builtins::BUILTINS
+        .iter()
+        .copied()
+        .chain(shell.functions.keys().map(|name| name.as_str()))
+        .chain(programs.iter().map(|name| name.as_str()));
+
+    match suggest::similar(command, candidates).as_slice() {
+        [] => {}
+        [suggestion] => eprintln!("fsh: did you mean `{}'?", suggestion),
+        suggestions => eprintln!("fsh: did you mean one of: {}?", suggestions.join(", ")),
+    }
+
+    127
+}
+
+// Function run when a command is not found
+const HANDLER: &str = "command_not_found_handler";
+
+// Apply `redirections` to the shell process while `run` runs, then restore the redirected
+// file descriptors, so they apply to builtins as well as to launched programs
+fn with_redirections<F>(redirections: &[Redirection], shell: &mut Shell, run: F) -> i32
+where
+    F: FnOnce(&mut Shell) -> i32,
+{
+    if redirections.is_empty() {
+        return run(shell);
+    }
+
+    flush_stdout();
+
+    // Redirected file descriptors with a copy of what they referred to before
+    let mut saved = Vec::new();
+
+    let status = match redirect(redirections, &mut saved, shell) {
+        Ok(()) => run(shell),
+        Err(msg) => {
+            eprintln!("fsh: {}", msg);
+            1
+        }
+    };
+
+    flush_stdout();
+
+    for (fd, saved_fd) in saved.into_iter().rev() {
+        match saved_fd {
+            Some(saved_fd) => {
+                sys::dup2(saved_fd, fd).unwrap_or(());
+                sys::close(saved_fd);
+            }
+            None => sys::close(fd),
+        }
+    }
+
+    status
+}
+
+fn redirect(
+    redirections: &[Redirection],
+    saved: &mut Vec<(RawFd, Option<RawFd>)>,
+    shell: &mut Shell,
+) -> Result<(), String> {
+    for redirection in redirections {
+        let operator = redirection.operator;
+
+        let target = match expand::expand_word(&redirection.target, shell)?.as_slice() {
+            [target] => target.clone(),
+            _ => return Err(format!("{}: ambiguous redirect", redirection.target)),
+        };
+
+        let default_fd = if operator.starts_with('<') { 0 } else { 1 };
+        let fd = redirection.fd.unwrap_or(default_fd);
+
+        // <&n and >&n make fd a copy of n, <&- and >&- close it
+        if operator == "<&" || operator == ">&" {
+            if target == "-" {
+                saved.push((fd, sys::save_fd(fd)));
+                sys::close(fd);
+                continue;
+            }
+
+            if let Ok(source) = target.parse::<RawFd>() {
+                if sys::save_fd(source).map(sys::close).is_none() {
+                    return Err(format!("{}: Bad file descriptor", target));
+                }
+                saved.push((fd, sys::save_fd(fd)));
+                sys::dup2(source, fd).map_err(|err| format!("{}: {}", target, err))?;
+                continue;
+            }
+
+            if operator == "<&" {
+                return Err(format!("{}: ambiguous redirect", redirection.target));
+            }
+        }
+
+        let mut options = OpenOptions::new();
+        match operator {
+            "<" => options.read(true),
+            "<>" => options.read(true).write(true).create(true),
+            ">>" | "&>>" => options.append(true).create(true),
+            _ => options.write(true).create(true).truncate(true),
+        };
+
+        let file = options
+            .open(&target)
+            .map_err(|err| format!("{}: {}", target, error_message(&err)))?;
+        let file_fd = file.into_raw_fd();
+
+        // &>file and >&file redirect both the standard output and the standard error
+        let fds = if operator.starts_with('&') || (operator == ">&" && redirection.fd.is_none()) {
+            vec![1, 2]
         } else {
-            expanded_args.push(arg.to_string());
+            vec![fd]
+        };
+
+        for fd in fds {
+            saved.push((fd, sys::save_fd(fd)));
+            sys::dup2(file_fd, fd).map_err(|err| format!("{}: {}", target, err))?;
         }
+        sys::close(file_fd);
     }
 
-    return expanded_args;
+    Ok(())
+}
+
+// Write the output still buffered, like before forking so that the child does not write it too
+// The output can not always be written, like when it is redirected to a full disk
+pub(crate) fn flush_stdout() {
+    if let Err(err) = stdout().flush() {
+        eprintln!("fsh: write error: {}", error_message(&err));
+    }
+}
+
+// Describe an I/O error without the "(os error N)" suffix
+pub(crate) fn error_message(err: &std::io::Error) -> String {
+    let message = err.to_string();
+
+    match message.find(" (os error") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+// An assignment word like NAME=value, NAME+=value, NAME[subscript]=value or NAME=(value...)
+pub(crate) struct Assignment<'a> {
+    pub name: &'a str,
+    pub subscript: Option<&'a str>,
+
+    // += appends to the current value
+    pub append: bool,
+
+    pub value: &'a str,
+}
+
+// Parse `word` if it is an assignment
+pub(crate) fn parse_assignment(word: &str) -> Option<Assignment<'_>> {
+    let name_len = word
+        .find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+        .unwrap_or(word.len());
+    let name = &word[..name_len];
+
+    if !is_valid_name(name) {
+        return None;
+    }
+
+    let mut rest = &word[name_len..];
+
+    let subscript = if rest.starts_with('[') {
+        let end = subscript_end(rest)?;
+        let subscript = &rest[1..end];
+        rest = &rest[end + 1..];
+        Some(subscript)
+    } else {
+        None
+    };
+
+    let (append, value) = if let Some(value) = rest.strip_prefix("+=") {
+        (true, value)
+    } else {
+        (false, rest.strip_prefix('=')?)
+    };
+
+    Some(Assignment {
+        name,
+        subscript,
+        append,
+        value,
+    })
+}
+
+// Index of the `]` closing the subscript that starts at the beginning of `text`
+// Brackets inside quotes like ["]"] are part of the subscript
+fn subscript_end(text: &str) -> Option<usize> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut depth = 0;
+    let mut index = 0;
+
+    while index < chars.len() {
+        match chars[index] {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(chars[..index].iter().map(|c| c.len_utf8()).sum());
+                }
+            }
+            _ => {
+                index = lexer::skip_quoted(&chars, index).ok()?;
+                continue;
+            }
+        }
+        index += 1;
+    }
+
+    None
+}
+
+// Perform an assignment in the shell
+pub(crate) fn assign(assignment: &Assignment, shell: &mut Shell) -> Result<(), String> {
+    let name = assignment.name;
+    let value = assignment.value;
+
+    if let Some(subscript) = assignment.subscript {
+        let value = expand::expand_string(value, shell)?;
+        return assign_element(name, subscript, value, assignment.append, shell);
+    }
+
+    // name=(a b c) assigns all the elements of an array, name+=(d) appends to them
+    if value.starts_with('(') && value.ends_with(')') {
+        let words = lexer::split_words(&value[1..value.len() - 1])?;
+
+        if !assignment.append {
+            shell.vars.clear_array(name);
+        }
+
+        for word in words {
+            match parse_element(&word) {
+                Some((subscript, append, value)) => {
+                    let value = expand::expand_string(value, shell)?;
+                    assign_element(name, subscript, value, append, shell)?;
+                }
+                None if shell.vars.is_associative(name) => {
+                    return Err(format!(
+                        "{}: {}: must use subscript when assigning associative array",
+                        name, word
+                    ));
+                }
+                None => {
+                    let values = expand::expand_word(&word, shell)?;
+                    shell.vars.append_indexed(name, values);
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    let mut value = expand::expand_string(value, shell)?;
+    if assignment.append {
+        value = format!("{}{}", shell.vars.get(name).unwrap_or(""), value);
+    }
+    shell.vars.set(name, value);
+
+    Ok(())
+}
+
+// Parse an element of an array assignment like [key]=value or [key]+=value
+fn parse_element(word: &str) -> Option<(&str, bool, &str)> {
+    if !word.starts_with('[') {
+        return None;
+    }
+
+    let end = subscript_end(word)?;
+    let rest = &word[end + 1..];
+
+    if let Some(value) = rest.strip_prefix("+=") {
+        Some((&word[1..end], true, value))
+    } else {
+        Some((&word[1..end], false, rest.strip_prefix('=')?))
+    }
+}
+
+// Set the element `subscript` of array `name`
+fn assign_element(
+    name: &str,
+    subscript: &str,
+    mut value: String,
+    append: bool,
+    shell: &mut Shell,
+) -> Result<(), String> {
+    if shell.vars.is_associative(name) {
+        let key = expand::expand_string(subscript, shell)?;
+        if append {
+            value = format!(
+                "{}{}",
+                shell.vars.get_associative(name, &key).unwrap_or(""),
+                value
+            );
+        }
+        shell.vars.set_associative(name, key, value);
+        return Ok(());
+    }
+
+    let index = match expand::element_index(name, subscript, shell)? {
+        Some(index) => index,
+        None => return Err(format!("{}[{}]: bad array subscript", name, subscript)),
+    };
+
+    if append {
+        value = format!(
+            "{}{}",
+            shell.vars.get_indexed(name, index).unwrap_or(""),
+            value
+        );
+    }
+    shell.vars.set_indexed(name, index, value);
+
+    Ok(())
+}
+
+// Execute an external command/program and return its exit status
+// `program` is the path of the program found for `command`
+fn launch_command(
+    command: &str,
+    program: &Path,
+    args: Vec<String>,
+    environment: Vec<(String, String)>,
+    shell: &mut Shell,
+) -> i32 {
+    let spawned = process::Command::new(program)
+        .arg0(command)
+        .args(&args)
+        .env_clear()
+        .envs(environment.iter().map(|(name, value)| (name, value)))
+        .spawn();
+
+    match spawned {
+        Ok(mut child) => match child.wait() {
+            Ok(status) => status
+                .code()
+                .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)),
+            Err(_) => 1,
+        },
+        // A text file without a #! line is a script for the shell itself
+        Err(err)
+            if err.raw_os_error() == Some(libc::ENOEXEC)
+                && program.is_file()
+                && !is_binary(program) =>
+        {
+            run_script(command, program, args, environment, shell)
+        }
+        Err(err) => {
+            let (status, msg) = launch_error(command, program, &err);
+            eprintln!("fsh: {}: {}", command, msg);
+            status
+        }
+    }
+}
+
+// Exit status and description of the failure to run `program`
+// A missing program exits with 127, one that exists but can not be run with 126
+fn launch_error(command: &str, program: &Path, err: &std::io::Error) -> (i32, String) {
+    if program.is_dir() {
+        return (126, String::from("Is a directory"));
+    }
+
+    match err.raw_os_error() {
+        // The program exists but the interpreter named by its #! line does not
+        Some(libc::ENOENT) if program.exists() => match interpreter(program) {
+            Some(interpreter) => (
+                126,
+                format!(
+                    "{}: bad interpreter: No such file or directory",
+                    interpreter
+                ),
+            ),
+            None => (126, error_message(err)),
+        },
+        Some(libc::ENOENT) if command.contains('/') => {
+            (127, String::from("No such file or directory"))
+        }
+        Some(libc::ENOENT) => (127, String::from("command not found")),
+        Some(libc::ENOEXEC) => (
+            126,
+            String::from("cannot execute binary file: Exec format error"),
+        ),
+        _ => (126, error_message(err)),
+    }
+}
+
+// Run the script `program` in a forked copy of the shell, like a new shell would run it
+fn run_script(
+    command: &str,
+    program: &Path,
+    args: Vec<String>,
+    environment: Vec<(String, String)>,
+    shell: &mut Shell,
+) -> i32 {
+    let source = match fs::read(program) {
+        Ok(source) => String::from_utf8_lossy(&source).into_owned(),
+        Err(err) => {
+            eprintln!("fsh: {}: {}", command, error_message(&err));
+            return 126;
+        }
+    };
+
+    flush_stdout();
+
+    match sys::fork() {
+        Ok(Fork::Child) => {
+            shell.subshell = true;
+            shell.interactive = false;
+            shell.name = command.to_string();
+            shell.vars.positional = args;
+            shell.functions.clear();
+
+            // The script gets the same environment as a launched program
+            for (name, value) in environment {
+                shell.vars.export(&name, Some(value));
+            }
+
+            let status = run_line(&source, shell);
+            flush_stdout();
+
+            sys::exit_now(status)
+        }
+        Ok(Fork::Parent(pid)) => sys::wait_pid(pid),
+        Err(msg) => {
+            eprintln!("fsh: cannot fork: {}", msg);
+            126
+        }
+    }
+}
+
+// Whether `program` is a binary file rather than a script
+// Like bash, it is binary when a NUL byte comes before the first new line in its first 80 bytes
+fn is_binary(program: &Path) -> bool {
+    let mut start = Vec::new();
+
+    match File::open(program) {
+        Ok(file) => {
+            if file.take(80).read_to_end(&mut start).is_err() {
+                return true;
+            }
+        }
+        Err(_) => return true,
+    }
+
+    start
+        .iter()
+        .take_while(|&&byte| byte != b'\n')
+        .any(|&byte| byte == 0)
+}
+
+// The interpreter named by the #! line of `program`
+fn interpreter(program: &Path) -> Option<String> {
+    let mut first_line = String::new();
+    BufReader::new(File::open(program).ok()?)
+        .read_line(&mut first_line)
+        .ok()?;
+
+    let interpreter = first_line.strip_prefix("#!")?.split_whitespace().next()?;
+
+    Some(interpreter.to_string())
+}
+
+// Run `source` in a forked copy of the shell and return what it writes to the standard output
+// This is how command substitutions like $(date) are performed
+pub(crate) fn capture_output(source: &str, shell: &mut Shell) -> String {
+    let (read_fd, write_fd) = match sys::pipe() {
+        Ok(fds) => fds,
+        Err(msg) => {
+            eprintln!("fsh: cannot make pipe for command substitution: {}", msg);
+            return String::new();
+        }
+    };
+
+    // Flush pending output so the child does not write it a second time
+    flush_stdout();
+
+    match sys::fork() {
+        Ok(Fork::Child) => {
+            sys::close(read_fd);
+            sys::dup2(write_fd, 1).unwrap();
+            sys::close(write_fd);
+
+            shell.subshell = true;
+            let status = run_line(source, shell);
+            flush_stdout();
+
+            sys::exit_now(status)
+        }
+        Ok(Fork::Parent(pid)) => {
+            sys::close(write_fd);
+
+            // Safe because the read end of the pipe is owned by nothing else
+            let mut reader = unsafe { File::from_raw_fd(read_fd) };
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).unwrap_or(0);
+
+            shell.substitution_status = Some(sys::wait_pid(pid));
+
+            String::from_utf8_lossy(&output).into_owned()
+        }
+        Err(msg) => {
+            sys::close(read_fd);
+            sys::close(write_fd);
+            eprintln!("fsh: cannot fork: {}", msg);
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{shell, TempDir};
+    use std::io;
+    use std::os::unix::fs::PermissionsExt;
+
+    // The item of a case command run for `word`, recorded in the variable `item`
+    fn case_item(word: &str, shell: &mut Shell) -> String {
+        shell.vars.set("word", word.to_string());
+        let status = run_line(
+            "item=none; case $word in \
+             a|b) item=ab;; \
+             \"*\") item=star;; \
+             +([0-9])) item=number;; \
+             !(*.c|*.h)) item=other;; \
+             esac",
+            shell,
+        );
+        assert_eq!(status, 0);
+
+        shell.vars.get("item").unwrap().to_string()
+    }
+
+    #[test]
+    fn case_command() {
+        let mut shell = shell();
+        shell.options.set_shopt("extglob", true).unwrap();
+
+        assert_eq!(case_item("b", &mut shell), "ab");
+        assert_eq!(case_item("*", &mut shell), "star");
+        assert_eq!(case_item("42", &mut shell), "number");
+        assert_eq!(case_item("main.rs", &mut shell), "other");
+        assert_eq!(case_item("main.c", &mut shell), "none");
+
+        // The first matching item runs and the status is the one of its list
+        assert_eq!(
+            run_line("case x in x) false;; *) true;; esac", &mut shell),
+            1
+        );
+        assert_eq!(run_line("case x in y) false;; esac", &mut shell), 0);
+    }
+
+    #[test]
+    fn launch_errors() {
+        let dir = TempDir::new("launch-test", &[]);
+        let script = dir.path().join("script");
+        fs::write(&script, "#!/nonexistent/interpreter -x\necho a\n").unwrap();
+        let missing = dir.path().join("missing");
+        let enoent = io::Error::from_raw_os_error(libc::ENOENT);
+
+        assert_eq!(
+            launch_error("dir", dir.path(), &enoent),
+            (126, String::from("Is a directory"))
+        );
+        assert_eq!(
+            launch_error("./script", &script, &enoent),
+            (
+                126,
+                String::from(
+                    "/nonexistent/interpreter: bad interpreter: No such file or directory"
+                )
+            )
+        );
+        assert_eq!(
+            launch_error("./missing", &missing, &enoent),
+            (127, String::from("No such file or directory"))
+        );
+        assert_eq!(
+            launch_error("missing", &missing, &enoent),
+            (127, String::from("command not found"))
+        );
+        assert_eq!(
+            launch_error(
+                "./script",
+                &script,
+                &io::Error::from_raw_os_error(libc::ENOEXEC)
+            ),
+            (
+                126,
+                String::from("cannot execute binary file: Exec format error")
+            )
+        );
+        assert_eq!(
+            launch_error(
+                "./script",
+                &script,
+                &io::Error::from_raw_os_error(libc::EACCES)
+            ),
+            (126, String::from("Permission denied"))
+        );
+    }
+
+    #[test]
+    fn command_not_found_handler() {
+        let mut shell = shell();
+        shell.vars.set("PATH", String::from("/nonexistent"));
+
+        assert_eq!(run_line("fsh-missing-command a", &mut shell), 127);
+
+        // The handler gets the command and its arguments and its status is the status of the command
+        let handler = "command_not_found_handler() { args=\"$*\"; return 42; }";
+        assert_eq!(run_line(handler, &mut shell), 0);
+        assert_eq!(run_line("fsh-missing-command a 'b c'", &mut shell), 42);
+        assert_eq!(shell.vars.get("args"), Some("fsh-missing-command a b c"));
+
+        // A missing command in the handler does not run the handler again
+        let handler = "command_not_found_handler() { fsh-other-command; }";
+        assert_eq!(run_line(handler, &mut shell), 0);
+        assert_eq!(run_line("fsh-missing-command", &mut shell), 127);
+        assert!(shell.functions.contains_key(HANDLER));
+    }
+
+    #[test]
+    fn binary_files() {
+        let dir = TempDir::new("binary-test", &[]);
+        let binary = |content: &[u8]| {
+            let file = dir.path().join("file");
+            fs::write(&file, content).unwrap();
+            is_binary(&file)
+        };
+
+        assert!(binary(b"\x7fELF\x02\x01\x01\x00"));
+        assert!(binary(b"echo a\x00\n"));
+        assert!(!binary(b""));
+        assert!(!binary(b"echo a\n"));
+
+        // Only a NUL before the first new line counts
+        assert!(!binary(b"echo a\n\x00"));
+
+        // And only in the first 80 bytes
+        let mut content = vec![b'a'; 80];
+        content.push(0);
+        assert!(!binary(&content));
+        content[79] = 0;
+        assert!(binary(&content));
+
+        assert!(is_binary(&dir.path().join("missing")));
+    }
+
+    #[test]
+    fn scripts_without_interpreter() {
+        let dir = TempDir::new("script-test", &[]);
+        let script = dir.path().join("script");
+        let output = dir.path().join("output");
+        fs::write(&script, "echo \"$0 $1 $X\" > \"$2\"\nexit 3\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // A text file the kernel can not run is run by the shell, the assignments are exported
+        let mut shell = shell();
+        let line = format!("X=x {} a {}", script.display(), output.display());
+        assert_eq!(run_line(&line, &mut shell), 3);
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            format!("{} a x\n", script.display())
+        );
+        assert_eq!(shell.vars.get("X"), None);
+
+        // A binary file fails
+        fs::write(&script, b"\x00\x01\x02\n").unwrap();
+        assert_eq!(run_line(&script.display().to_string(), &mut shell), 126);
+    }
+
+    #[test]
+    fn autocd() {
+        let dir = TempDir::new("autocd-test", &[]);
+        let dir = dir.path().canonicalize().unwrap().display().to_string();
+        let mut shell = shell();
+        shell.options.set_shopt("autocd", true).unwrap();
+
+        // The commands run in a forked shell so the directory of the tests does not change
+        let directory_after = |line: &str, shell: &mut Shell| {
+            capture_output(&format!("{}; pwd", line), shell)
+                .trim_end()
+                .to_string()
+        };
+
+        // Not in scripts or the rc file
+        assert_ne!(directory_after(&dir, &mut shell), dir);
+        assert_eq!(run_line(&dir, &mut shell), 126);
+
+        shell.interactive = true;
+        assert_eq!(directory_after(&dir, &mut shell), dir);
+
+        // Not with arguments
+        assert_ne!(directory_after(&format!("{} a", dir), &mut shell), dir);
+        assert_eq!(run_line(&format!("{} a", dir), &mut shell), 126);
+
+        shell.options.set_shopt("autocd", false).unwrap();
+        assert_ne!(directory_after(&dir, &mut shell), dir);
+    }
 }
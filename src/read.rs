@@ -1,486 +1,237 @@
-use crate::history::History;
+use crate::dirs;
+use crate::editor::{Action, LineEditor};
+use crate::expand;
+use crate::graphemes;
 use crate::preprocess::prompt;
 use crate::process::execute;
-use glob::glob;
-use std::io::{stdin, stdout, Write};
-use termion::cursor::DetectCursorPos;
-use termion::event::Key;
+use crate::render::{Prompt, Renderer};
+use crate::shell::Shell;
+use crate::sys;
+use crate::terminal::{StdoutTerminal, Terminal, PASTE_END, PASTE_START};
+use crate::vars;
+use std::fs;
+use std::io::{self, stdin};
+use termion::event::{Event, Key};
 use termion::input::TermRead;
-use termion::raw::IntoRawMode;
-use termion::style;
 
-// This functions process keyboard events like inputting a char or pressing Ctrl-d and etc
-// It needs flags and variables to share information across events
-pub fn read_loop(mut history_handler: History) {
+// This functions reads keyboard events like inputting a char or pressing Ctrl-d and etc
+// The line editor updates the command for each of them and tells what to do next, like
+// drawing the command again or running it
+pub fn read_loop(mut shell: Shell) {
+    shell.interactive = true;
+
     // Get the standard input stream
     let stdin = stdin();
 
     // Get the standard output stream and go to raw mode
-    let mut _stdout = stdout().into_raw_mode().unwrap();
-
-    // This buffer contains user input
-    // After every Enter this buffer will be collected and processed in order to run the command
-    // This buffer will be updated after every event
-    let mut char_buf = Vec::<char>::new();
+    let mut terminal = StdoutTerminal::new().unwrap();
 
-    // Contains suggestions based on user input
-    let mut suggestions = Vec::<String>::new();
-    // Indicates what suggestion should be displayed
-    let mut suggestion_index = 0;
+    // Draw again when the terminal changes size, and tell the commands its size
+    sys::catch_window_resize();
+    export_window_size(&terminal, &mut shell);
 
-    // Indicated if we are in search mode or not
-    let mut search_mode = false;
+    let mut editor = LineEditor::new(shell.options.is_set("vi"));
+    let mut renderer = Renderer::default();
 
-    // Indicates what command in history buffer should be displayed
-    let mut history_index: i64 = -1;
+    // The prompts are found again before each command, since the current directory may change
+    let mut prompt = main_prompt(&shell);
+    let mut continuation = continuation_prompt(&mut shell);
 
-    // The print_prompt method prints the prompt and returns the minimum x coordinate that cursor can hold
-    // Cursor can not go behind the prompt therefore this minimum value is the size of the printed prompt
-    let mut min_cursor_x_bound = print_prompt(&mut _stdout);
+    renderer.draw(&mut terminal, &editor, &prompt, &continuation);
 
     // Process each keyboard event
-    for c in stdin.keys() {
-        // Get cursor position in terminal
-        let (cursor_x, cursor_y) = _stdout.cursor_pos().unwrap();
-
-        match c.unwrap() {
-            Key::Char('\n') => {
-                if search_mode {
-                    // Reset search variables and flags
-                    search_mode = false;
-                    suggestions.clear();
-                    suggestion_index = 0;
-
-                    // Go to a new line
-                    println!("\r");
-
-                    // Print the prompt
-                    print_prompt(&mut _stdout);
-
-                    // Print the command
-                    let command: String = char_buf.iter().collect();
-                    print!("{}", command);
-                    _stdout.flush().unwrap();
+    let mut events = stdin.events();
+    while let Some(event) = events.next() {
+        let action = match event {
+            Ok(Event::Key(key)) => editor.handle_key(key, &shell),
+            // Pasted text is inserted as it is instead of being handled key by key
+            Ok(Event::Unsupported(sequence)) if sequence == PASTE_START => {
+                let text = read_paste(&mut events);
+                editor.paste(&text, &shell)
+            }
+            Ok(_) => continue,
+            // Waiting for a key was interrupted by a signal, like the one of a new terminal size
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                if sys::window_resized() {
+                    export_window_size(&terminal, &mut shell);
+                    renderer.draw(&mut terminal, &editor, &prompt, &continuation);
                 }
-                // Go to a new line
-                println!("\r");
+                continue;
+            }
+            Err(_) => break,
+        };
+
+        match action {
+            Action::None => continue,
+            Action::Redraw => {}
+            Action::List(entries) => renderer.list(&mut terminal, &entries),
+            Action::ClearScreen => renderer.clear_screen(&mut terminal),
+            Action::Execute(command) => {
+                // Leave the command on the screen as it runs
+                renderer.draw(&mut terminal, &editor, &prompt, &continuation);
+                renderer.finish(&mut terminal, "");
 
                 // Execute the command in buffer
-                if !char_buf.is_empty() {
+                if !command.trim().is_empty() {
                     // Exit of raw mode to give a normal terminal to child process
-                    std::mem::drop(_stdout);
-
-                    // Extract command
-                    let command: String = char_buf.iter().collect();
+                    terminal.suspend();
 
                     // Add command to history
-                    history_handler.add_command(command.clone());
+                    shell.history.add_command(command.clone());
 
                     // Execute the command with normal tty
-                    execute(command, &mut history_handler);
+                    execute(command, &mut shell);
 
                     // Go into raw mode again
-                    _stdout = stdout().into_raw_mode().unwrap();
-
-                    char_buf.clear();
+                    terminal.resume();
                 }
 
-                // Clear the current line for prompt
-                write!(_stdout, "{}\r", termion::clear::CurrentLine).unwrap();
-                _stdout.flush().unwrap();
-
-                // Print the prompt
-                min_cursor_x_bound = print_prompt(&mut _stdout);
+                editor.reset(shell.options.is_set("vi"));
+                prompt = main_prompt(&shell);
+                continuation = continuation_prompt(&mut shell);
             }
-            Key::Char('\t') => {
-                if search_mode {
-                    if let Some(suggestion) = suggestions.get(suggestion_index) {
-                        char_buf.clear();
-
-                        // Set suggestion as current command
-                        for _char in suggestion.chars() {
-                            char_buf.push(_char);
-                        }
-
-                        // Clear the current line for prompt
-                        write!(_stdout, "{}\r", termion::clear::CurrentLine).unwrap();
-                        _stdout.flush().unwrap();
-
-                        // Print the prompt
-                        min_cursor_x_bound = print_prompt(&mut _stdout);
-
-                        // print the suggestion as current command
-                        print!("{}", suggestion);
-                        _stdout.flush().unwrap();
-
-                        search_mode = false;
-                        suggestions.clear();
-                        suggestion_index = 0;
-                    }
-                } else {
-                    let command: String = char_buf.iter().collect();
-
-                    let mut _index = 0;
-                    let cursor_index = (cursor_x - min_cursor_x_bound) as usize;
-                    // first space before cursor
-                    for (index, _char) in command
-                        .chars()
-                        .rev()
-                        .skip(command.len() - cursor_index)
-                        .enumerate()
-                    {
-                        if _char == ' ' {
-                            _index = command.len() - ((command.len() - cursor_index) + index);
-                            break;
-                        }
-                    }
-                    // path glob
-                    if _index != 0 {
-                        let glob_text = &command[_index..cursor_index].trim();
-                        let glob_entries = get_entries_of_glob(glob_text);
-                        let mut suggestions = vec![];
-                        for entry in glob_entries {
-                            match entry {
-                                Ok(suggestion) => suggestions.push(suggestion),
-                                Err(_) => println!("Error occurred during glob search"),
-                            }
-                        }
-                        // Fill the rest of user typed text if there is only one suggestion
-                        if suggestions.len() == 1 {
-                            let suggestion_str =
-                                suggestions.pop().unwrap().to_str().unwrap().to_string();
-                            for _char in suggestion_str.chars().skip(glob_text.chars().count()) {
-                                char_buf.push(_char);
-                            }
-                            print!("{}", &suggestion_str[glob_text.chars().count()..]);
-                            _stdout.flush().unwrap();
-                        } else if suggestions.len() > 1 {
-                            // Go to a new line
-                            println!("\r");
-                            // Print all suggestions
-                            for path in suggestions {
-                                println!("{}\r", path.as_path().display());
-                            }
-                            // Print the prompt
-                            min_cursor_x_bound = print_prompt(&mut _stdout);
-                            // Print the already typed command
-                            print!("{}", command);
-                            _stdout.flush().unwrap();
-                        }
+            Action::Run(command) => {
+                // The output of the command goes below the command being typed, which is kept
+                renderer.finish(&mut terminal, "");
+                terminal.suspend();
+                execute(command, &mut shell);
+                terminal.resume();
+
+                prompt = main_prompt(&shell);
+                continuation = continuation_prompt(&mut shell);
+            }
+            Action::EditAndExecute(command) => {
+                renderer.finish(&mut terminal, "");
+                terminal.suspend();
+
+                if let Some(command) = edit_command(&command, &mut shell) {
+                    if !command.trim().is_empty() {
+                        // Show what is run like it was typed
+                        println!("{}", command);
+                        shell.history.add_command(command.clone());
+                        execute(command, &mut shell);
                     }
                 }
-            }
-            Key::Char(c) => {
-                char_buf.push(c);
-
-                print!("{}", c);
-                _stdout.flush().unwrap();
-
-                // Update suggestion based on this new char
-                if search_mode {
-                    // Already typed command
-                    let command: String = char_buf.iter().collect();
-
-                    suggestion_index = 0;
-
-                    // Get suggestions for this command
-                    suggestions = history_handler.search(&command);
-
-                    print_suggestion(
-                        &command,
-                        suggestions.get(suggestion_index),
-                        cursor_y,
-                        &mut _stdout,
-                    );
 
-                    min_cursor_x_bound = 5;
-                }
+                terminal.resume();
+                editor.reset(shell.options.is_set("vi"));
+                prompt = main_prompt(&shell);
+                continuation = continuation_prompt(&mut shell);
+            }
+            Action::Cancel => {
+                renderer.finish(&mut terminal, "^C");
+                editor.reset(shell.options.is_set("vi"));
             }
-            Key::Ctrl(c) if c == 'c' => {
-                println!("^C\r");
+            Action::Jump(directory) => {
+                renderer.finish(&mut terminal, "");
 
-                if search_mode {
-                    search_mode = false;
-                    suggestions.clear();
-                    suggestion_index = 0;
+                if let Some(directory) = directory {
+                    if let Err(msg) = dirs::change_directory(&directory, &mut shell) {
+                        terminal.write(&format!("fsh: z: {}\r\n", msg));
+                    }
                 }
 
-                char_buf.clear();
-
-                min_cursor_x_bound = print_prompt(&mut _stdout);
+                editor.reset(shell.options.is_set("vi"));
+                prompt = main_prompt(&shell);
             }
-            Key::Ctrl(c) if c == 'd' => {
-                print!("exit");
-                _stdout.flush().unwrap();
+            Action::Exit => {
+                terminal.write("exit");
 
-                // Save history to file
-                history_handler.save();
+                // Save history and visited directories to files
+                shell.history.save();
+                shell.directories.save();
 
                 break;
             }
-            Key::Ctrl(c) if c == 'r' => {
-                // Enable search mode
-                search_mode = true;
-                suggestion_index = 0;
-
-                // Already typed command
-                let command: String = char_buf.iter().collect();
-
-                // Get suggestions for this command
-                suggestions = history_handler.search(&command);
-
-                print_suggestion(
-                    &command,
-                    suggestions.get(suggestion_index),
-                    cursor_y,
-                    &mut _stdout,
-                );
-
-                min_cursor_x_bound = 5;
-            }
-            Key::Left => move_cursor_left(&mut _stdout, cursor_x, cursor_y, min_cursor_x_bound),
-            Key::Right => move_cursor_right(
-                &mut _stdout,
-                cursor_x,
-                cursor_y,
-                char_buf.len() as u16 + min_cursor_x_bound,
-            ),
-            Key::Up => {
-                if search_mode {
-                    // Already typed command
-                    let command: String = char_buf.iter().collect();
-
-                    // If there exists a suggestion after current one, move index to the next one
-                    if let Some(_) = suggestions.get(suggestion_index + 1) {
-                        suggestion_index += 1;
-                    }
-
-                    print_suggestion(
-                        &command,
-                        suggestions.get(suggestion_index),
-                        cursor_y,
-                        &mut _stdout,
-                    );
-                } else {
-                    history_index += 1;
-                    if let Some(history_element) = history_handler.get(history_index as usize) {
-                        // Populate character buffer with suggestion
-                        char_buf = history_element.chars().into_iter().collect();
-
-                        // Clear current line
-                        write!(_stdout, "\r{}", termion::clear::CurrentLine).unwrap();
-
-                        // Print the prompt
-                        min_cursor_x_bound = print_prompt(&mut _stdout);
-
-                        // Print the history element
-                        print!("{}", history_element);
-                        _stdout.flush().unwrap();
-                    } else {
-                        // If there is no more command in history, revert history index
-                        history_index -= 1;
-                    }
-                }
-            }
-            Key::Down => {
-                if search_mode {
-                    if suggestion_index == 0 {
-                        continue;
-                    }
-
-                    // Already typed command
-                    let command: String = char_buf.iter().collect();
-
-                    if let Some(_) = suggestions.get(suggestion_index - 1) {
-                        suggestion_index -= 1;
-                    }
-
-                    print_suggestion(
-                        &command,
-                        suggestions.get(suggestion_index),
-                        cursor_y,
-                        &mut _stdout,
-                    );
-                } else {
-                    if history_index <= 0 {
-                        char_buf.clear();
-
-                        // Clear current line
-                        write!(_stdout, "\r{}", termion::clear::CurrentLine).unwrap();
-
-                        // Print the prompt
-                        min_cursor_x_bound = print_prompt(&mut _stdout);
-
-                        history_index = -1;
+        }
 
-                        continue;
-                    }
+        // The terminal may have changed size while a command was running
+        if sys::window_resized() {
+            export_window_size(&terminal, &mut shell);
+        }
 
-                    history_index -= 1;
-                    if let Some(history_element) = history_handler.get(history_index as usize) {
-                        // Populate character buffer with suggestion
-                        char_buf = history_element.chars().into_iter().collect();
+        renderer.draw(&mut terminal, &editor, &prompt, &continuation);
+    }
 
-                        // Clear current line
-                        write!(_stdout, "\r{}", termion::clear::CurrentLine).unwrap();
+    // Go to a clear line and exit
+    terminal.write("\r\n");
+    terminal.flush();
+}
 
-                        // Print the prompt
-                        min_cursor_x_bound = print_prompt(&mut _stdout);
+// The text pasted in the terminal, read until the end of the paste
+// Enter and Tab are kept as a new line and a tab, and the other control keys as their characters
+fn read_paste(events: &mut impl Iterator<Item = io::Result<Event>>) -> String {
+    let mut text = String::new();
 
-                        // Print the history element
-                        print!("{}", history_element);
-                        _stdout.flush().unwrap();
-                    }
-                }
+    for event in events {
+        match event {
+            Ok(Event::Key(Key::Char(c))) => text.push(c),
+            Ok(Event::Key(Key::Ctrl(c))) if c.is_ascii_lowercase() => {
+                text.push((c as u8 - b'a' + 1) as char)
             }
-            Key::Backspace => {
-                // Get cursor position relative to char buf boundaries
-                let cursor_index = cursor_x - min_cursor_x_bound;
-                if cursor_index != 0 {
-                    // Remove character before cursor
-                    char_buf.remove((cursor_index - 1) as usize);
-
-                    // Move cursor to the deleted char position
-                    write!(_stdout, "{}", termion::cursor::Goto(cursor_x - 1, cursor_y)).unwrap();
-
-                    // Clear the line from cursor position until new line
-                    write!(_stdout, "{}", termion::clear::UntilNewline).unwrap();
-
-                    // Print rest of the command(after deleted character)
-                    let rest_of_command: String = char_buf.iter().skip((cursor_index - 1) as usize).collect();
-                    print!("{}", rest_of_command);
-
-                    // Move back cursor to position of deleted char
-                    write!(_stdout, "{}", termion::cursor::Goto(cursor_x - 1, cursor_y)).unwrap();
-                    _stdout.flush().unwrap();
-                }
-
-                // Update suggestion based on new command
-                if search_mode {
-                    // Already typed command
-                    let command: String = char_buf.iter().collect();
-
-                    // Get suggestions for this command
-                    suggestions = history_handler.search(&command);
-
-                    suggestion_index = 0;
+            Ok(Event::Key(Key::Backspace)) => text.push('\x7f'),
+            Ok(Event::Unsupported(sequence)) if sequence == PASTE_END => break,
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
 
-                    print_suggestion(
-                        &command,
-                        suggestions.get(suggestion_index),
-                        cursor_y,
-                        &mut _stdout,
-                    );
+    text
+}
 
-                    min_cursor_x_bound = 5;
-                }
-            }
-            _ => continue,
-        }
+// Let the user change `command` in $VISUAL or $EDITOR, vi by default, and return it as it is
+// when the editor exits, or None if the editor failed
+fn edit_command(command: &str, shell: &mut Shell) -> Option<String> {
+    let file = std::env::temp_dir().join(format!("fsh-command-{}.sh", shell.pid));
+    if let Err(err) = fs::write(&file, format!("{}\n", command)) {
+        eprintln!("fsh: {}: {}", file.display(), err);
+        return None;
+    }
 
-        // Flush again.
-        _stdout.flush().unwrap();
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| shell.vars.get(name))
+        .find(|editor| !editor.is_empty())
+        .unwrap_or("vi")
+        .to_string();
+    // The editor can have arguments like `code -w`, the file is quoted as TMPDIR may have spaces
+    let path = vars::quote(&file.to_string_lossy());
+    execute(format!("{} {}", editor, path), shell);
+
+    let edited = fs::read_to_string(&file);
+    let _ = fs::remove_file(&file);
+
+    if shell.last_status != 0 {
+        return None;
     }
-    // Go to a clear line and exit
-    println!("\r");
+    edited
+        .ok()
+        .map(|command| command.trim_end_matches('\n').to_string())
 }
 
-// Print the suggestion in search mode
-fn print_suggestion(
-    command: &String,
-    suggestion: Option<&String>,
-    cursor_y: u16,
-    _stdout: &mut termion::raw::RawTerminal<std::io::Stdout>,
-) {
-    let cropped_suggestion_text = if let Some(suggestion_str) = suggestion {
-        &suggestion_str[command.len()..]
-    } else {
-        ""
-    };
-
-    let formatted_suggestion_text = format!(
-        "{}{}{}",
-        style::Underline,
-        cropped_suggestion_text,
-        style::NoUnderline
-    );
-
-    // Print search icon + command + (cropped suggestion)
-    write!(
-        _stdout,
-        "\r{clear_line}🔍: {command}{suggestion}{adjust_cursor}",
-        clear_line = termion::clear::CurrentLine,
-        suggestion = formatted_suggestion_text,
-        command = command,
-        adjust_cursor = termion::cursor::Goto(5 + command.len() as u16, cursor_y) // Move cursor right after typed command
-    )
-    .unwrap();
-    _stdout.flush().unwrap();
-}
+// Put the size of the terminal in COLUMNS and LINES for the commands that are run
+fn export_window_size(terminal: &dyn Terminal, shell: &mut Shell) {
+    let (columns, rows) = terminal.size();
 
-// Get entries based on `path_str`
-fn get_entries_of_glob(path_str: &str) -> glob::Paths {
-    // If user already used '*', don't add it
-    let postfix = if path_str.contains('*') {
-        String::new()
-    } else {
-        String::from("*")
-    };
-
-    let path = std::path::Path::new(path_str);
-
-    if path.is_relative() {
-        glob(&format!("./{}{postfix}", path_str, postfix = postfix))
-            .expect("Failed to read glob pattern")
-    } else {
-        glob(&format!("{}{postfix}", path_str, postfix = postfix))
-            .expect("Failed to read glob pattern")
-    }
+    shell.vars.export("COLUMNS", Some(columns.to_string()));
+    shell.vars.export("LINES", Some(rows.to_string()));
 }
 
-// Prints the prompt
-// Returns minimum x coordinate that cursor can get without interfering with the prompt text
-fn print_prompt(stdout: &mut termion::raw::RawTerminal<std::io::Stdout>) -> u16 {
+// The prompt before the first line of a command
+fn main_prompt(shell: &Shell) -> Prompt {
     // Get the prompt and its size without any style and color
-    let (prompt_text, prompt_len) = prompt();
+    let home = shell.vars.get("HOME").or(shell.user.home.as_deref());
+    let (text, width) = prompt(home);
 
-    // Print the prompt
-    print!("{}", prompt_text);
-    stdout.flush().unwrap();
-
-    // Cursor must be on position after the last char of prompt
-    // That plus one is because coordinate starts at 1. so the 1 is offset
-    (prompt_len + 1) as u16
+    Prompt { text, width }
 }
 
-// Moves the cursor one position to left until it reaches the minimum allowed value
-fn move_cursor_left(
-    stdout: &mut termion::raw::RawTerminal<std::io::Stdout>,
-    cursor_x: u16,
-    cursor_y: u16,
-    min_cursor_x_bound: u16,
-) {
-    write!(
-        stdout,
-        "{}",
-        termion::cursor::Goto(std::cmp::max(cursor_x - 1, min_cursor_x_bound), cursor_y),
-    )
-    .unwrap();
-}
+// The prompt of the continuation lines of an incomplete command, which is $PS2
+fn continuation_prompt(shell: &mut Shell) -> Prompt {
+    let ps2 = shell.vars.get("PS2").unwrap_or("").to_string();
+    let text = expand::expand_string(&ps2, shell).unwrap_or(ps2);
+    let width = graphemes::width(&text);
 
-// Moves the cursor one position to right until it reaches the maximum allowed value
-// Maximum value is the total number of typed chars
-// Cursor can not go beyond last typed char
-fn move_cursor_right(
-    stdout: &mut termion::raw::RawTerminal<std::io::Stdout>,
-    cursor_x: u16,
-    cursor_y: u16,
-    max_cursor_x_bound: u16,
-) {
-    write!(
-        stdout,
-        "{}",
-        termion::cursor::Goto(std::cmp::min(cursor_x + 1, max_cursor_x_bound), cursor_y),
-    )
-    .unwrap();
-}
\ No newline at end of file
+    Prompt { text, width }
+}
@@ -0,0 +1,646 @@
+use crate::editor::{LineEditor, Mode};
+use crate::graphemes;
+use crate::terminal::Terminal;
+use crate::vi::ViMode;
+use termion::{clear, cursor, style};
+
+// A prompt and the number of columns it takes on the terminal, without its styles and colors
+pub struct Prompt {
+    pub text: String,
+    pub width: usize,
+}
+
+// Something drawn on the terminal, a grapheme cluster of the command or a whole prompt
+#[derive(PartialEq)]
+struct Cell {
+    text: String,
+    width: usize,
+    style: Style,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    Plain,
+    // The suggestion of search and jump mode
+    Underline,
+    // The selection of visual mode
+    Invert,
+}
+
+// Draws the line editor on the terminal
+// Each line of the command wraps to as many rows as the width of the terminal needs. The renderer
+// remembers what it drew and where it left the cursor, so that it draws again only from the
+// first cell that changed, without asking the terminal where the cursor is
+//
+// Rows and columns are counted from the first row of the command. A line always takes the row
+// after its last character, so a line filling whole rows has an empty row where the cursor can be.
+// A wide character that does not fit at the end of a row goes to the next one like terminals do
+#[derive(Default)]
+pub struct Renderer {
+    // Lines of the command as they are drawn
+    lines: Vec<Vec<Cell>>,
+
+    // Width of the terminal the lines were drawn for
+    width: usize,
+
+    // Number of rows the lines take
+    rows: usize,
+
+    // Row and column of the cursor
+    cursor: (usize, usize),
+
+    // Index of the line the cursor is on and of the cell it is on in that line
+    cursor_cell: (usize, usize),
+}
+
+impl Renderer {
+    // Draw the command of `editor` over its previous drawing
+    // `prompt` is printed before its first line and `continuation` before the other ones
+    pub fn draw(
+        &mut self,
+        terminal: &mut dyn Terminal,
+        editor: &LineEditor,
+        prompt: &Prompt,
+        continuation: &Prompt,
+    ) {
+        let width = terminal.size().0.max(1);
+        let (lines, cursor_cell) = layout(editor, prompt, continuation);
+        let mut output = String::new();
+
+        // Everything wraps differently on a terminal of another width and is drawn again
+        // The terminal is expected to wrap again what it shows, which puts the cursor where it
+        // would be if the command had been drawn for the new width
+        let change = if width != self.width {
+            if !self.lines.is_empty() {
+                self.rows = end(&self.lines, width).0 + 1;
+                self.cursor = position(&self.lines, self.cursor_cell.0, self.cursor_cell.1, width);
+            }
+            Some((0, 0))
+        } else {
+            first_change(&self.lines, &lines)
+        };
+
+        if let Some((line, cell)) = change {
+            // Go to the first cell that changed and clear everything after it
+            let (row, column) = place(&lines[line][..cell], width);
+            let first_row = rows_before(&lines, line, width);
+            self.move_cursor(&mut output, (first_row + row, column));
+            output.push_str(&format!("{}", clear::AfterCursor));
+
+            let mut current_style = Style::Plain;
+            for (index, cells) in lines.iter().enumerate().skip(line) {
+                if index > line {
+                    output.push_str("\r\n");
+                }
+
+                let start = if index == line { cell } else { 0 };
+                for cell in &cells[start..] {
+                    if cell.style != current_style {
+                        output.push_str(&style_code(cell.style));
+                        current_style = cell.style;
+                    }
+                    output.push_str(&cell.text);
+                }
+                if current_style != Style::Plain {
+                    output.push_str(&style_code(Style::Plain));
+                    current_style = Style::Plain;
+                }
+
+                // At the end of a full row the terminal waits for another character to wrap,
+                // the cursor is put on the next row like after any other line
+                if place(cells, width).1 == 0 && line_width(cells) > 0 {
+                    output.push_str("\r\n");
+                }
+            }
+
+            let end = end(&lines, width);
+            self.cursor = end;
+            self.rows = end.0 + 1;
+        }
+
+        self.move_cursor(
+            &mut output,
+            position(&lines, cursor_cell.0, cursor_cell.1, width),
+        );
+
+        self.lines = lines;
+        self.width = width;
+        self.cursor_cell = cursor_cell;
+
+        terminal.write(&output);
+        terminal.flush();
+    }
+
+    // Leave the command as it is drawn and go to a new line, after writing `mark` like ^C
+    pub fn finish(&mut self, terminal: &mut dyn Terminal, mark: &str) {
+        let mut output = String::new();
+
+        if !self.lines.is_empty() {
+            let end = end(&self.lines, self.width);
+            self.move_cursor(&mut output, end);
+        }
+        output.push_str(&format!("{}\r\n", mark));
+
+        terminal.write(&output);
+        terminal.flush();
+
+        *self = Renderer::default();
+    }
+
+    // Clear the whole screen, the command is drawn again at its top
+    pub fn clear_screen(&mut self, terminal: &mut dyn Terminal) {
+        terminal.write(&format!("{}{}", cursor::Goto(1, 1), clear::All));
+        *self = Renderer::default();
+    }
+
+    // Print `entries` below the command, the command is drawn again under them
+    pub fn list(&mut self, terminal: &mut dyn Terminal, entries: &[String]) {
+        self.finish(terminal, "");
+
+        for entry in entries {
+            terminal.write(&format!("{}\r\n", entry));
+        }
+        terminal.flush();
+    }
+
+    // Move the cursor from where it is to `target`
+    fn move_cursor(&mut self, output: &mut String, target: (usize, usize)) {
+        let (row, column) = target;
+        let (current_row, _) = self.cursor;
+
+        // The row the cursor is on exists even before anything is drawn
+        let last_row = self.rows.max(1) - 1;
+
+        if row > last_row {
+            // Rows after the drawn ones are made by new lines, which scroll the screen at its bottom
+            if current_row < last_row {
+                output.push_str(&format!(
+                    "{}",
+                    cursor::Down((last_row - current_row) as u16)
+                ));
+            }
+            for _ in last_row..row {
+                output.push_str("\r\n");
+            }
+            self.rows = row + 1;
+        } else if row < current_row {
+            output.push_str(&format!("{}", cursor::Up((current_row - row) as u16)));
+        } else if row > current_row {
+            output.push_str(&format!("{}", cursor::Down((row - current_row) as u16)));
+        }
+
+        output.push('\r');
+        if column > 0 {
+            output.push_str(&format!("{}", cursor::Right(column as u16)));
+        }
+
+        self.cursor = target;
+    }
+}
+
+// The cells of each line of the command of `editor` and the indexes of the line and of the cell
+// the cursor is on
+fn layout(
+    editor: &LineEditor,
+    prompt: &Prompt,
+    continuation: &Prompt,
+) -> (Vec<Vec<Cell>>, (usize, usize)) {
+    let line_cells = |index: usize, line: &String| {
+        let line_prompt = if index == 0 { prompt } else { continuation };
+        let chars: Vec<char> = line.chars().collect();
+
+        let mut cells = vec![text_cell(&line_prompt.text, line_prompt.width)];
+        cells.extend(grapheme_cells(&chars, Style::Plain));
+        cells
+    };
+    let mut lines: Vec<Vec<Cell>> = editor
+        .lines()
+        .iter()
+        .enumerate()
+        .map(|(index, line)| line_cells(index, line))
+        .collect();
+
+    let buffer = editor.buffer();
+    let mut cells = Vec::new();
+
+    if editor.mode() == Mode::Edit {
+        let line_prompt = if editor.lines().is_empty() {
+            prompt
+        } else {
+            continuation
+        };
+
+        // In vi mode the line being edited starts with the mode
+        let indicator = match editor.vi_mode() {
+            Some(ViMode::Insert) => "(ins) ",
+            Some(ViMode::Normal) => "(cmd) ",
+            Some(ViMode::Visual) => "(vis) ",
+            None => "",
+        };
+        if !indicator.is_empty() {
+            cells.push(text_cell(indicator, indicator.len()));
+        }
+        cells.push(text_cell(&line_prompt.text, line_prompt.width));
+    } else {
+        cells.push(text_cell(
+            &format!("{}: ", search_icon(editor)),
+            graphemes::width(search_icon(editor)) + 2,
+        ));
+    }
+    let command_start = cells.len();
+
+    // The selection of visual mode is drawn inverted
+    let selection = editor.selection();
+    let graphemes = graphemes::split(buffer);
+    let cursor_cell = command_start
+        + graphemes
+            .iter()
+            .filter(|(start, _)| *start < editor.cursor())
+            .count();
+    cells.extend(graphemes.into_iter().map(|(start, grapheme)| {
+        let selected =
+            selection.is_some_and(|(selection_start, end)| selection_start <= start && start < end);
+        grapheme_cell(
+            grapheme,
+            if selected {
+                Style::Invert
+            } else {
+                Style::Plain
+            },
+        )
+    }));
+
+    // In search mode the rest of the suggested command is drawn after the text searched for, in
+    // jump mode the suggestion is a whole directory drawn after the terms of z
+    match editor.suggestion() {
+        Some(directory) if editor.mode() == Mode::Jump => {
+            let chars: Vec<char> = directory.chars().collect();
+            cells.extend(grapheme_cells(&[' ', ' '], Style::Plain));
+            cells.extend(grapheme_cells(&chars, Style::Underline));
+        }
+        Some(suggestion) => {
+            let command: String = buffer.iter().collect();
+            let rest: Vec<char> = suggestion
+                .strip_prefix(command.as_str())
+                .unwrap_or("")
+                .chars()
+                .collect();
+            cells.extend(grapheme_cells(&rest, Style::Underline));
+        }
+        None => {}
+    }
+    let cursor_line = lines.len();
+    lines.push(cells);
+
+    // The lines after the one being edited when the cursor was moved up
+    for line in editor.next_lines() {
+        lines.push(line_cells(lines.len(), line));
+    }
+
+    (lines, (cursor_line, cursor_cell))
+}
+
+fn search_icon(editor: &LineEditor) -> &'static str {
+    if editor.mode() == Mode::Jump {
+        "📁"
+    } else {
+        "🔍"
+    }
+}
+
+fn text_cell(text: &str, width: usize) -> Cell {
+    Cell {
+        text: text.to_string(),
+        width,
+        style: Style::Plain,
+    }
+}
+
+// Control characters like a pasted tab are drawn as ^ and a letter, like ^I
+fn grapheme_cell(grapheme: String, style: Style) -> Cell {
+    let text = match grapheme.chars().next() {
+        Some(c) if c.is_ascii_control() => format!("^{}", (c as u8 ^ 0x40) as char),
+        _ => grapheme,
+    };
+
+    Cell {
+        width: graphemes::width(&text),
+        text,
+        style,
+    }
+}
+
+fn grapheme_cells(chars: &[char], style: Style) -> impl Iterator<Item = Cell> {
+    graphemes::split(chars)
+        .into_iter()
+        .map(move |(_, grapheme)| grapheme_cell(grapheme, style))
+}
+
+fn style_code(cell_style: Style) -> String {
+    match cell_style {
+        Style::Plain => format!("{}{}", style::NoUnderline, style::NoInvert),
+        Style::Underline => format!("{}", style::Underline),
+        Style::Invert => format!("{}", style::Invert),
+    }
+}
+
+fn line_width(cells: &[Cell]) -> usize {
+    cells.iter().map(|cell| cell.width).sum()
+}
+
+// The first line that differs between what is drawn and what is to be drawn, and the first cell
+// that differs in it, or None if nothing changed
+fn first_change(drawn: &[Vec<Cell>], lines: &[Vec<Cell>]) -> Option<(usize, usize)> {
+    for (index, cells) in lines.iter().enumerate() {
+        match drawn.get(index) {
+            Some(drawn_cells) if drawn_cells == cells => continue,
+            Some(drawn_cells) => {
+                let cell = drawn_cells
+                    .iter()
+                    .zip(cells)
+                    .take_while(|(drawn, cell)| drawn == cell)
+                    .count();
+                return Some((index, cell));
+            }
+            None => return Some((index, 0)),
+        }
+    }
+
+    // Lines were removed after the last one, like when Backspace joins two lines
+    if drawn.len() > lines.len() {
+        let last = lines.len() - 1;
+        return Some((last, lines[last].len()));
+    }
+
+    None
+}
+
+// Row and column after `cells` drawn from the start of a row on a terminal of `width` columns
+fn place(cells: &[Cell], width: usize) -> (usize, usize) {
+    let (mut row, mut column) = (0, 0);
+
+    for cell in cells {
+        if column > 0 && column + cell.width > width && cell.width <= width {
+            row += 1;
+            column = 0;
+        }
+
+        column += cell.width;
+        row += column / width;
+        column %= width;
+    }
+
+    (row, column)
+}
+
+// Number of rows the lines before line `line` take
+fn rows_before(lines: &[Vec<Cell>], line: usize, width: usize) -> usize {
+    lines[..line]
+        .iter()
+        .map(|cells| place(cells, width).0 + 1)
+        .sum()
+}
+
+// Row and column where cell `cell` of line `line` is drawn
+fn position(lines: &[Vec<Cell>], line: usize, cell: usize, width: usize) -> (usize, usize) {
+    let cells = &lines[line];
+    let (mut row, mut column) = place(&cells[..cell], width);
+
+    // A wide character that does not fit at the end of the row is on the next one
+    if let Some(next) = cells.get(cell) {
+        if column > 0 && column + next.width > width && next.width <= width {
+            row += 1;
+            column = 0;
+        }
+    }
+
+    (rows_before(lines, line, width) + row, column)
+}
+
+// Row and column after the last line
+fn end(lines: &[Vec<Cell>], width: usize) -> (usize, usize) {
+    let last = lines.len() - 1;
+    let (row, column) = place(&lines[last], width);
+
+    (rows_before(lines, last, width) + row, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::Shell;
+    use crate::terminal::RecordingTerminal;
+    use crate::testing::shell;
+    use termion::event::Key;
+
+    fn prompt() -> Prompt {
+        Prompt {
+            text: String::from("$ "),
+            width: 2,
+        }
+    }
+
+    fn continuation() -> Prompt {
+        Prompt {
+            text: String::from("> "),
+            width: 2,
+        }
+    }
+
+    fn type_text(editor: &mut LineEditor, text: &str, shell: &Shell) {
+        for c in text.chars() {
+            editor.handle_key(Key::Char(c), shell);
+        }
+    }
+
+    fn draw(
+        renderer: &mut Renderer,
+        terminal: &mut RecordingTerminal,
+        editor: &LineEditor,
+    ) -> String {
+        renderer.draw(terminal, editor, &prompt(), &continuation());
+        terminal.take_output()
+    }
+
+    #[test]
+    fn draw_prompt_and_command() {
+        let shell = shell();
+        let mut terminal = RecordingTerminal::new(20, 10);
+        let mut renderer = Renderer::default();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "ls", &shell);
+
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\r\x1b[J$ ls\r\x1b[4C"
+        );
+    }
+
+    #[test]
+    fn draw_only_what_changed() {
+        let shell = shell();
+        let mut terminal = RecordingTerminal::new(20, 10);
+        let mut renderer = Renderer::default();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "ls", &shell);
+        draw(&mut renderer, &mut terminal, &editor);
+
+        type_text(&mut editor, "x", &shell);
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\r\x1b[4C\x1b[Jx\r\x1b[5C"
+        );
+
+        // Nothing is written again when only the cursor moves
+        editor.handle_key(Key::Left, &shell);
+        assert_eq!(draw(&mut renderer, &mut terminal, &editor), "\r\x1b[4C");
+    }
+
+    #[test]
+    fn draw_wrapped_command() {
+        let shell = shell();
+        let mut terminal = RecordingTerminal::new(10, 10);
+        let mut renderer = Renderer::default();
+        let mut editor = LineEditor::new(false);
+
+        // A command filling the row exactly puts the cursor on the next row
+        type_text(&mut editor, "echo abc", &shell);
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\r\x1b[J$ echo abc\r\n\r"
+        );
+
+        type_text(&mut editor, "d", &shell);
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\r\x1b[Jd\r\x1b[1C"
+        );
+
+        // Going back to the first row moves up
+        editor.handle_key(Key::Home, &shell);
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\x1b[1A\r\x1b[2C"
+        );
+    }
+
+    #[test]
+    fn draw_wide_character_on_next_row() {
+        let shell = shell();
+        let mut terminal = RecordingTerminal::new(10, 10);
+        let mut renderer = Renderer::default();
+        let mut editor = LineEditor::new(false);
+
+        // Only one column is left on the first row, the terminal draws the wide character on the
+        // second one
+        type_text(&mut editor, "echo ab日", &shell);
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\r\x1b[J$ echo ab日\r\x1b[2C"
+        );
+
+        editor.handle_key(Key::Left, &shell);
+        assert_eq!(draw(&mut renderer, &mut terminal, &editor), "\r");
+    }
+
+    #[test]
+    fn draw_cursor_on_earlier_line() {
+        let shell = shell();
+        let mut terminal = RecordingTerminal::new(20, 10);
+        let mut renderer = Renderer::default();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "echo 'a", &shell);
+        editor.handle_key(Key::Char('\n'), &shell);
+        type_text(&mut editor, "b", &shell);
+        draw(&mut renderer, &mut terminal, &editor);
+
+        // The lines stay as they are drawn, only the cursor goes up
+        editor.handle_key(Key::Up, &shell);
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\x1b[1A\r\x1b[3C"
+        );
+
+        // The line below is drawn again after an edit of the line above
+        type_text(&mut editor, "x", &shell);
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\r\x1b[3C\x1b[Jxcho 'a\r\n> b\x1b[1A\r\x1b[4C"
+        );
+
+        // The cursor is left after the last line when the command is finished
+        renderer.finish(&mut terminal, "");
+        assert_eq!(terminal.take_output(), "\x1b[1B\r\x1b[3C\r\n");
+    }
+
+    #[test]
+    fn finish_after_command() {
+        let shell = shell();
+        let mut terminal = RecordingTerminal::new(20, 10);
+        let mut renderer = Renderer::default();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "ls", &shell);
+        editor.handle_key(Key::Home, &shell);
+        draw(&mut renderer, &mut terminal, &editor);
+
+        renderer.finish(&mut terminal, "^C");
+        assert_eq!(terminal.take_output(), "\r\x1b[4C^C\r\n");
+
+        // The next command is drawn from the start of the new row
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\r\x1b[J$ ls\r\x1b[2C"
+        );
+    }
+
+    #[test]
+    fn list_entries_below_command() {
+        let shell = shell();
+        let mut terminal = RecordingTerminal::new(20, 10);
+        let mut renderer = Renderer::default();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "ls a", &shell);
+        draw(&mut renderer, &mut terminal, &editor);
+
+        renderer.list(&mut terminal, &[String::from("ab"), String::from("ac")]);
+        assert_eq!(terminal.take_output(), "\r\x1b[6C\r\nab\r\nac\r\n");
+
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\r\x1b[J$ ls a\r\x1b[6C"
+        );
+    }
+
+    #[test]
+    fn draw_again_after_width_change() {
+        let shell = shell();
+        let mut terminal = RecordingTerminal::new(10, 10);
+        let mut renderer = Renderer::default();
+        let mut editor = LineEditor::new(false);
+
+        type_text(&mut editor, "echo abcdef", &shell);
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\r\x1b[J$ echo abcdef\r\x1b[3C"
+        );
+
+        // On a wider terminal the command fits on one row, where the terminal put the cursor
+        terminal.size = (20, 10);
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\r\x1b[J$ echo abcdef\r\x1b[13C"
+        );
+
+        // On a narrower one it takes three rows
+        terminal.size = (5, 10);
+        assert_eq!(
+            draw(&mut renderer, &mut terminal, &editor),
+            "\x1b[2A\r\x1b[J$ echo abcdef\r\x1b[3C"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use crate::dirs;
+use crate::frecency::DirectoryDatabase;
+use crate::hash::CommandHash;
+use crate::history::History;
+use crate::keymap::Keymap;
+use crate::options::Options;
+use crate::parser::Command;
+use crate::user::User;
+use crate::vars::Variables;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// State of the running shell shared by the line editor and the executed commands
+pub struct Shell {
+    pub history: History,
+    pub options: Options,
+    pub vars: Variables,
+
+    // Key bindings of the line editor, changed by bind
+    pub keymap: Keymap,
+
+    // Bodies of the defined functions by name
+    pub functions: HashMap<String, Rc<Command>>,
+
+    // Directories saved by pushd, the current directory is not included
+    pub dir_stack: Vec<String>,
+
+    // Visited directories ranked for z
+    pub directories: DirectoryDatabase,
+
+    // Paths of the programs found in PATH
+    pub hash: CommandHash,
+
+    // Exit status of the last executed command, $?
+    pub last_status: i32,
+
+    // Exit status of the last command substitution of the command being expanded
+    pub substitution_status: Option<i32>,
+
+    // The user running the shell
+    pub user: User,
+
+    // Name of the shell or of the script it runs, $0
+    pub name: String,
+
+    // Process id of the interactive shell, $$
+    pub pid: u32,
+
+    // Process id of the last command run in the background, $!
+    pub last_background: Option<i32>,
+
+    // Whether this is a forked copy of the shell, like the one running a command substitution
+    pub subshell: bool,
+
+    // Whether the commands are typed at the prompt, rather than read from the rc file or a script
+    pub interactive: bool,
+
+    // Number of function bodies being run, return can only be used in one
+    pub function_depth: usize,
+
+    // Set by return so the rest of the function body is skipped
+    pub returning: bool,
+}
+
+impl Shell {
+    pub fn new(user: User) -> Self {
+        let mut vars = Variables::from_environment();
+
+        // UID is the user id and USER the user name when the environment does not provide it
+        vars.set("UID", user.uid.to_string());
+        if let (None, Some(name)) = (vars.get("USER"), &user.name) {
+            vars.export("USER", Some(name.clone()));
+        }
+
+        // PWD from the environment is kept if it is a logical path of the current directory
+        if !vars.get("PWD").is_some_and(dirs::is_current_directory) {
+            if let Ok(path) = std::env::current_dir() {
+                vars.export("PWD", Some(path.display().to_string()));
+            }
+        }
+
+        Shell {
+            // The history file is found after the rc file had a chance to set HISTFILE
+            history: History::default(),
+            options: Options::default(),
+            vars,
+            keymap: Keymap::default(),
+            functions: HashMap::new(),
+            dir_stack: Vec::new(),
+            directories: DirectoryDatabase::default(),
+            hash: CommandHash::default(),
+            last_status: 0,
+            substitution_status: None,
+            user,
+            name: String::from("fsh"),
+            pid: std::process::id(),
+            last_background: None,
+            subshell: false,
+            interactive: false,
+            function_depth: 0,
+            returning: false,
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// Find the names that look like a mistyped name
+
+// Number of chars to insert, delete, replace or swap with the next one to turn `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // distances[i][j] is the distance between the first i chars of `a` and the first j chars of `b`
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+// The `candidates` closest to `name` if they are close enough to be what was meant
+// Longer names can have more typos
+pub fn similar<'a, I>(name: &str, candidates: I) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let max_distance = name.chars().count().div_ceil(3).min(3);
+
+    let mut matches: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+
+    matches.sort_unstable();
+    matches.dedup();
+
+    // Only the closest names are suggested
+    let closest = matches.first().map_or(0, |(distance, _)| *distance);
+
+    matches
+        .into_iter()
+        .take_while(|(distance, _)| *distance == closest)
+        .take(3)
+        .map(|(_, candidate)| candidate.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("git", "git"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(edit_distance("gt", "git"), 1);
+        assert_eq!(edit_distance("gitt", "git"), 1);
+        assert_eq!(edit_distance("gat", "git"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("ca", "abc"), 3);
+        assert_eq!(edit_distance("école", "ecole"), 1);
+    }
+
+    #[test]
+    fn suggestions() {
+        let commands = [
+            "git", "grep", "gzip", "ls", "less", "cargo", "rustc", "rustup",
+        ];
+
+        assert_eq!(similar("gti", commands.iter().copied()), ["git"]);
+        assert_eq!(similar("sl", commands.iter().copied()), ["ls"]);
+        assert_eq!(similar("carg", commands.iter().copied()), ["cargo"]);
+
+        // Only the closest names are suggested, sorted
+        assert_eq!(similar("rustx", commands.iter().copied()), ["rustc"]);
+        assert_eq!(similar("rust", commands.iter().copied()), ["rustc"]);
+        assert_eq!(similar("gsip", commands.iter().copied()), ["gzip"]);
+        assert_eq!(similar("lss", commands.iter().copied()), ["less", "ls"]);
+
+        // The name itself and duplicates are not suggested
+        assert!(similar("git", commands.iter().copied()).is_empty());
+        assert_eq!(similar("gti", ["git", "git"].iter().copied()), ["git"]);
+
+        // At most three names are suggested
+        let many = ["ab", "ac", "ad", "ae"];
+        assert_eq!(similar("aa", many.iter().copied()), ["ab", "ac", "ad"]);
+    }
+
+    #[test]
+    fn suggestion_threshold() {
+        // One typo up to 3 chars, two up to 6 and three after that
+        assert_eq!(similar("xy", ["ab", "xb"].iter().copied()), ["xb"]);
+        assert!(similar("abc", ["axx"].iter().copied()).is_empty());
+        assert_eq!(similar("abcd", ["axxd"].iter().copied()), ["axxd"]);
+        assert!(similar("abcdef", ["axxxef"].iter().copied()).is_empty());
+        assert_eq!(similar("abcdefg", ["axxxefg"].iter().copied()), ["axxxefg"]);
+        assert!(similar("abcdefghijkl", ["axxxxfghijkl"].iter().copied()).is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Thin wrappers around the process related system calls used by the shell
+
+pub enum Fork {
+    Parent(libc::pid_t),
+    Child,
+}
+
+// Fork the shell process
+pub fn fork() -> io::Result<Fork> {
+    // Safe because the child only runs shell code and leaves through `exit_now`
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            // Let Ctrl-C interrupt the child even though the interactive shell ignores it
+            unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
+            Ok(Fork::Child)
+        }
+        pid => Ok(Fork::Parent(pid)),
+    }
+}
+
+// Create a pipe and return its read and write ends
+pub fn pipe() -> io::Result<(RawFd, RawFd)> {
+    let mut fds = [0; 2];
+
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((fds[0], fds[1]))
+}
+
+// Make `new_fd` refer to the same file as `old_fd`
+pub fn dup2(old_fd: RawFd, new_fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::dup2(old_fd, new_fd) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// Duplicate `fd` to a descriptor above the ones used by commands, closed when a program is executed
+// Returns None if `fd` is not open
+pub fn save_fd(fd: RawFd) -> Option<RawFd> {
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) } {
+        -1 => None,
+        saved => Some(saved),
+    }
+}
+
+pub fn close(fd: RawFd) {
+    unsafe { libc::close(fd) };
+}
+
+// Wait for the child `pid` to terminate and return its exit status
+// A child killed by a signal gets the status 128 + signal number like in other shells
+pub fn wait_pid(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } != -1 {
+            break;
+        }
+
+        // Retry if the wait was interrupted by a signal
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return 1;
+        }
+    }
+
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        1
+    }
+}
+
+// Collect the exit status of finished background children so they do not stay zombies
+pub fn reap_children() {
+    let mut status = 0;
+
+    while unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) } > 0 {}
+}
+
+// Set by the handler of SIGWINCH when the terminal changes size
+static WINDOW_RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_window_resize(_: libc::c_int) {
+    WINDOW_RESIZED.store(true, Ordering::SeqCst);
+}
+
+// Catch SIGWINCH, sent when the terminal changes size
+// The system calls it interrupts are not restarted, so that reading a key returns an error of
+// kind Interrupted and the line editor can draw the command again for the new size
+pub fn catch_window_resize() {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_window_resize as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut());
+    }
+}
+
+// Whether the terminal changed size since the last call
+pub fn window_resized() -> bool {
+    WINDOW_RESIZED.swap(false, Ordering::SeqCst)
+}
+
+// Terminate a forked child without running the destructors of the parent's state
+pub fn exit_now(status: i32) -> ! {
+    unsafe { libc::_exit(status) }
+}
@@ -0,0 +1,119 @@
+use std::io::{self, Stdout, Write};
+use termion::raw::{IntoRawMode, RawTerminal};
+
+// With bracketed paste the terminal sends pasted text between PASTE_START and PASTE_END, so that
+// it is not taken for keys typed by the user
+const ENABLE_BRACKETED_PASTE: &str = "\x1b[?2004h";
+const DISABLE_BRACKETED_PASTE: &str = "\x1b[?2004l";
+pub const PASTE_START: &[u8] = b"\x1b[200~";
+pub const PASTE_END: &[u8] = b"\x1b[201~";
+
+// Where the line editor is drawn
+// The renderer only writes text and escape sequences, so a fake terminal can record them
+pub trait Terminal {
+    fn write(&mut self, text: &str);
+
+    fn flush(&mut self);
+
+    // Number of columns and rows, long commands wrap to the next row after the last column
+    fn size(&self) -> (usize, usize);
+
+    // Give the terminal in its normal mode to the commands that are run
+    fn suspend(&mut self);
+
+    // Take the terminal back in raw mode to read keys
+    fn resume(&mut self);
+}
+
+// The terminal of the standard output, in raw mode and with bracketed paste while the line editor
+// uses it
+pub struct StdoutTerminal {
+    stdout: RawTerminal<Stdout>,
+}
+
+impl StdoutTerminal {
+    pub fn new() -> io::Result<Self> {
+        let mut terminal = StdoutTerminal {
+            stdout: io::stdout().into_raw_mode()?,
+        };
+        terminal.write(ENABLE_BRACKETED_PASTE);
+
+        Ok(terminal)
+    }
+}
+
+impl Terminal for StdoutTerminal {
+    fn write(&mut self, text: &str) {
+        // There is nowhere to report a terminal that can not be written
+        let _ = self.stdout.write_all(text.as_bytes());
+    }
+
+    fn flush(&mut self) {
+        let _ = self.stdout.flush();
+    }
+
+    fn size(&self) -> (usize, usize) {
+        // A terminal that does not know its size is given the usual 80 columns and 24 rows
+        match termion::terminal_size() {
+            Ok((columns, rows)) if columns > 0 && rows > 0 => (columns as usize, rows as usize),
+            _ => (80, 24),
+        }
+    }
+
+    fn suspend(&mut self) {
+        self.write(DISABLE_BRACKETED_PASTE);
+        self.flush();
+        let _ = self.stdout.suspend_raw_mode();
+    }
+
+    fn resume(&mut self) {
+        let _ = self.stdout.activate_raw_mode();
+        self.write(ENABLE_BRACKETED_PASTE);
+    }
+}
+
+impl Drop for StdoutTerminal {
+    fn drop(&mut self) {
+        self.write(DISABLE_BRACKETED_PASTE);
+        self.flush();
+    }
+}
+
+// A terminal that keeps what is written to it, for the tests of the renderer
+#[cfg(test)]
+pub struct RecordingTerminal {
+    pub output: String,
+    pub size: (usize, usize),
+}
+
+#[cfg(test)]
+impl RecordingTerminal {
+    pub fn new(columns: usize, rows: usize) -> Self {
+        RecordingTerminal {
+            output: String::new(),
+            size: (columns, rows),
+        }
+    }
+
+    // What was written since the last call
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+}
+
+#[cfg(test)]
+impl Terminal for RecordingTerminal {
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+
+    fn flush(&mut self) {}
+
+    fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    fn suspend(&mut self) {}
+
+    fn resume(&mut self) {}
+}
@@ -0,0 +1,41 @@
+// Helpers shared by the unit tests of the modules
+use crate::shell::Shell;
+use crate::user::User;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A shell of the current user with the default variables and options
+pub fn shell() -> Shell {
+    Shell::new(User::current())
+}
+
+// A directory for the files of a test, removed with its content when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    // Create an empty directory with `files` in it
+    // Tests run in parallel, so `name` must be unique to the test
+    pub fn new(name: &str, files: &[&str]) -> Self {
+        let path = std::env::temp_dir().join(format!("fsh-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        for file in files {
+            fs::write(path.join(file), "").unwrap();
+        }
+
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
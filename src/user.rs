@@ -0,0 +1,73 @@
+use std::env;
+use std::ffi::{CStr, CString};
+
+// Identity of the user running the shell, resolved once at startup
+pub struct User {
+    pub uid: u32,
+
+    // Name from the password database, or from $USER or $LOGNAME if the uid has no entry
+    pub name: Option<String>,
+
+    // $HOME, or the home directory from the password database if it is not set
+    pub home: Option<String>,
+}
+
+impl User {
+    pub fn current() -> Self {
+        let uid = unsafe { libc::getuid() };
+        let entry = PasswdEntry::by_uid(uid);
+
+        let name = entry
+            .as_ref()
+            .map(|entry| entry.name.clone())
+            .or_else(|| non_empty_var("USER"))
+            .or_else(|| non_empty_var("LOGNAME"));
+
+        let home = non_empty_var("HOME").or_else(|| entry.map(|entry| entry.home));
+
+        User { uid, name, home }
+    }
+}
+
+// Home directory of user `name` from the password database, used to expand ~name
+pub fn home_of(name: &str) -> Option<String> {
+    PasswdEntry::by_name(name).map(|entry| entry.home)
+}
+
+struct PasswdEntry {
+    name: String,
+    home: String,
+}
+
+impl PasswdEntry {
+    fn by_uid(uid: libc::uid_t) -> Option<Self> {
+        // Safe because the entry is copied before any other call to the password database
+        unsafe { Self::from_raw(libc::getpwuid(uid)) }
+    }
+
+    fn by_name(name: &str) -> Option<Self> {
+        let name = CString::new(name).ok()?;
+
+        // Safe because the entry is copied before any other call to the password database
+        unsafe { Self::from_raw(libc::getpwnam(name.as_ptr())) }
+    }
+
+    unsafe fn from_raw(entry: *const libc::passwd) -> Option<Self> {
+        if entry.is_null() {
+            return None;
+        }
+
+        let name = CStr::from_ptr((*entry).pw_name)
+            .to_string_lossy()
+            .into_owned();
+        let home = CStr::from_ptr((*entry).pw_dir)
+            .to_string_lossy()
+            .into_owned();
+
+        Some(PasswdEntry { name, home })
+    }
+}
+
+fn non_empty_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
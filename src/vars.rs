@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+
+// Default value of IFS: space, tab and newline
+pub const DEFAULT_IFS: &str = " \t\n";
+
+// Default prompt printed while the lines of an incomplete command are read
+const DEFAULT_PS2: &str = "> ";
+
+// Value of a shell variable
+#[derive(Clone)]
+pub enum Value {
+    Scalar(String),
+
+    // Array indexed by integers, it can be sparse: a=([1]=x [5]=y)
+    Indexed(BTreeMap<usize, String>),
+
+    // Array indexed by strings, declared with: declare -A name
+    Associative(BTreeMap<String, String>),
+}
+
+// A shell variable
+pub struct Variable {
+    pub value: Value,
+
+    // Exported variables are passed to the environment of launched programs
+    pub exported: bool,
+}
+
+// Stores the shell variables and the positional parameters
+pub struct Variables {
+    vars: HashMap<String, Variable>,
+
+    // $1, $2, ...
+    pub positional: Vec<String>,
+}
+
+impl Variables {
+    // Create a store that contains the environment of the shell as exported variables
+    pub fn from_environment() -> Self {
+        let mut vars: HashMap<String, Variable> = env::vars()
+            .map(|(name, value)| {
+                let var = Variable {
+                    value: Value::Scalar(value),
+                    exported: true,
+                };
+                (name, var)
+            })
+            .collect();
+
+        vars.insert(
+            String::from("IFS"),
+            Variable {
+                value: Value::Scalar(DEFAULT_IFS.to_string()),
+                exported: false,
+            },
+        );
+
+        vars.entry(String::from("PS2")).or_insert(Variable {
+            value: Value::Scalar(DEFAULT_PS2.to_string()),
+            exported: false,
+        });
+
+        Variables {
+            vars,
+            positional: Vec::new(),
+        }
+    }
+
+    // Get value of variable `name`
+    // The value of an array is its element at index or key 0
+    pub fn get(&self, name: &str) -> Option<&str> {
+        match &self.vars.get(name)?.value {
+            Value::Scalar(value) => Some(value),
+            Value::Indexed(items) => items.get(&0).map(|item| item.as_str()),
+            Value::Associative(items) => items.get("0").map(|item| item.as_str()),
+        }
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&Variable> {
+        self.vars.get(name)
+    }
+
+    // Set value of variable `name`, keeping its export attribute if it already exists
+    // Setting an array without a subscript sets its element at index or key 0
+    pub fn set(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(var) => match &mut var.value {
+                Value::Scalar(old_value) => *old_value = value,
+                Value::Indexed(items) => {
+                    items.insert(0, value);
+                }
+                Value::Associative(items) => {
+                    items.insert(String::from("0"), value);
+                }
+            },
+            None => self.insert(name, Value::Scalar(value)),
+        }
+    }
+
+    // Set element `index` of indexed array `name`
+    // A scalar variable becomes an array whose element 0 is its old value
+    pub fn set_indexed(&mut self, name: &str, index: usize, value: String) {
+        self.declare_indexed(name);
+
+        if let Some(Value::Indexed(items)) = self.vars.get_mut(name).map(|var| &mut var.value) {
+            items.insert(index, value);
+        }
+    }
+
+    // Set element `key` of associative array `name`
+    pub fn set_associative(&mut self, name: &str, key: String, value: String) {
+        self.declare_associative(name);
+
+        if let Some(Value::Associative(items)) = self.vars.get_mut(name).map(|var| &mut var.value) {
+            items.insert(key, value);
+        }
+    }
+
+    // Make `name` an indexed array if it is not an array already
+    pub fn declare_indexed(&mut self, name: &str) {
+        match self.vars.get_mut(name) {
+            Some(var) => {
+                if let Value::Scalar(value) = &var.value {
+                    let mut items = BTreeMap::new();
+                    items.insert(0, value.clone());
+                    var.value = Value::Indexed(items);
+                }
+            }
+            None => self.insert(name, Value::Indexed(BTreeMap::new())),
+        }
+    }
+
+    // Make `name` an associative array if it is not an array already
+    pub fn declare_associative(&mut self, name: &str) {
+        match self.vars.get_mut(name) {
+            Some(var) => {
+                if let Value::Scalar(value) = &var.value {
+                    let mut items = BTreeMap::new();
+                    items.insert(String::from("0"), value.clone());
+                    var.value = Value::Associative(items);
+                }
+            }
+            None => self.insert(name, Value::Associative(BTreeMap::new())),
+        }
+    }
+
+    // Remove all elements of array `name`, it stays associative if it was
+    pub fn clear_array(&mut self, name: &str) {
+        match self.vars.get_mut(name) {
+            Some(var) => match &mut var.value {
+                Value::Associative(items) => items.clear(),
+                value => *value = Value::Indexed(BTreeMap::new()),
+            },
+            None => self.insert(name, Value::Indexed(BTreeMap::new())),
+        }
+    }
+
+    // Append `values` after the last element of indexed array `name`
+    pub fn append_indexed(&mut self, name: &str, values: Vec<String>) {
+        for value in values {
+            let next_index = self.next_index(name);
+            self.set_indexed(name, next_index, value);
+        }
+    }
+
+    // Index after the last element of indexed array `name`
+    pub fn next_index(&self, name: &str) -> usize {
+        match self.vars.get(name).map(|var| &var.value) {
+            Some(Value::Indexed(items)) => {
+                items.keys().next_back().map(|index| index + 1).unwrap_or(0)
+            }
+            Some(Value::Scalar(_)) => 1,
+            _ => 0,
+        }
+    }
+
+    pub fn is_associative(&self, name: &str) -> bool {
+        matches!(
+            self.vars.get(name).map(|var| &var.value),
+            Some(Value::Associative(_))
+        )
+    }
+
+    // Values of all elements of `name` ordered by index or key
+    // A scalar variable is the same as an array with one element
+    pub fn items(&self, name: &str) -> Vec<String> {
+        match self.vars.get(name).map(|var| &var.value) {
+            Some(Value::Scalar(value)) => vec![value.clone()],
+            Some(Value::Indexed(items)) => items.values().cloned().collect(),
+            Some(Value::Associative(items)) => items.values().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Indexes or keys of all elements of `name`
+    pub fn keys(&self, name: &str) -> Vec<String> {
+        match self.vars.get(name).map(|var| &var.value) {
+            Some(Value::Scalar(_)) => vec![String::from("0")],
+            Some(Value::Indexed(items)) => items.keys().map(|index| index.to_string()).collect(),
+            Some(Value::Associative(items)) => items.keys().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Element `index` of indexed array `name`
+    pub fn get_indexed(&self, name: &str, index: usize) -> Option<&str> {
+        match &self.vars.get(name)?.value {
+            Value::Scalar(value) if index == 0 => Some(value),
+            Value::Indexed(items) => items.get(&index).map(|item| item.as_str()),
+            _ => None,
+        }
+    }
+
+    // Element `key` of associative array `name`
+    pub fn get_associative(&self, name: &str, key: &str) -> Option<&str> {
+        match &self.vars.get(name)?.value {
+            Value::Associative(items) => items.get(key).map(|item| item.as_str()),
+            _ => None,
+        }
+    }
+
+    // Mark variable `name` as exported and set its value if one is provided
+    pub fn export(&mut self, name: &str, value: Option<String>) {
+        if !self.vars.contains_key(name) {
+            self.insert(name, Value::Scalar(String::new()));
+        }
+
+        if let Some(value) = value {
+            self.set(name, value);
+        }
+
+        if let Some(var) = self.vars.get_mut(name) {
+            var.exported = true;
+        }
+    }
+
+    // Remove the export attribute of variable `name`
+    pub fn unexport(&mut self, name: &str) {
+        if let Some(var) = self.vars.get_mut(name) {
+            var.exported = false;
+        }
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    // Remove element `key` of array `name`, the key of an indexed array is already an index
+    pub fn unset_element(&mut self, name: &str, key: &str) {
+        match self.vars.get_mut(name).map(|var| &mut var.value) {
+            Some(Value::Indexed(items)) => {
+                if let Ok(index) = key.parse::<usize>() {
+                    items.remove(&index);
+                }
+            }
+            Some(Value::Associative(items)) => {
+                items.remove(key);
+            }
+            Some(Value::Scalar(_)) if key == "0" => self.unset(name),
+            _ => {}
+        }
+    }
+
+    // All variables sorted by name
+    pub fn sorted(&self) -> Vec<(&String, &Variable)> {
+        let mut vars: Vec<(&String, &Variable)> = self.vars.iter().collect();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+
+        vars
+    }
+
+    // Name and value of exported variables overridden by `assignments`
+    // This is the environment of a launched program, arrays can not be exported
+    pub fn environment(&self, assignments: Vec<(String, String)>) -> Vec<(String, String)> {
+        let mut environment: HashMap<String, String> = self
+            .vars
+            .iter()
+            .filter_map(|(name, var)| match &var.value {
+                Value::Scalar(value) if var.exported => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect();
+
+        environment.extend(assignments);
+
+        environment.into_iter().collect()
+    }
+
+    fn insert(&mut self, name: &str, value: Value) {
+        self.vars.insert(
+            name.to_string(),
+            Variable {
+                value,
+                exported: false,
+            },
+        );
+    }
+}
+
+impl Variable {
+    // Value formatted the way it is written in an assignment: "value" or ([key]="value" ...)
+    pub fn quoted_value(&self) -> String {
+        match &self.value {
+            Value::Scalar(value) => quote(value),
+            Value::Indexed(items) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|(index, value)| format!("[{}]={}", index, quote(value)))
+                    .collect();
+                format!("({})", items.join(" "))
+            }
+            Value::Associative(items) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|(key, value)| format!("[{}]={}", quote(key), quote(value)))
+                    .collect();
+                format!("({})", items.join(" "))
+            }
+        }
+    }
+}
+
+// Put `value` in double quotes so it can be read back by the shell
+pub(crate) fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+// Whether `name` can be used as a variable name
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::process::run_line;
+    use crate::testing::shell;
+
+    #[test]
+    fn quoted_values() {
+        let mut shell = shell();
+        let setup = "s='a \"b\" $c'; a=([2]=x [7]='y z'); declare -A m=(['a b']=1 ['$k\"']=2)";
+        assert_eq!(run_line(setup, &mut shell), 0);
+
+        let quoted = |name| shell.vars.get_var(name).unwrap().quoted_value();
+        assert_eq!(quoted("s"), r#""a \"b\" \$c""#);
+        assert_eq!(quoted("a"), r#"([2]="x" [7]="y z")"#);
+        assert_eq!(quoted("m"), r#"(["\$k\""]="2" ["a b"]="1")"#);
+    }
+
+    #[test]
+    fn quoted_values_are_read_back() {
+        let mut shell = shell();
+        let setup = "declare -A m=(['a b']=1 ['$k\"']='x y' [']']=3)";
+        assert_eq!(run_line(setup, &mut shell), 0);
+
+        let assignment = format!(
+            "declare -A copy={}",
+            shell.vars.get_var("m").unwrap().quoted_value()
+        );
+        assert_eq!(run_line(&assignment, &mut shell), 0);
+        assert_eq!(shell.vars.keys("copy"), shell.vars.keys("m"));
+        assert_eq!(shell.vars.items("copy"), shell.vars.items("m"));
+    }
+}
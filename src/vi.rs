@@ -0,0 +1,1082 @@
+use crate::graphemes;
+use crate::keymap::Function;
+use termion::event::Key;
+
+// Modal editing of the line editor with set -o vi
+// Keys typed in insert mode are handled by the line editor like in emacs mode, those typed in
+// normal and visual mode are vi commands: [count] motion, [count] operator [count] motion or
+// text object, and [count] command
+//
+// Motions: h l w W b B e E 0 ^ $ f t F T ; ,
+// Operators: d c y, doubled like dd they work on the whole line
+// Text objects: iw aw iW aW i" a" i' a' i` a` i( a( ib ab i[ a[ i{ a{ iB aB i< a<
+// Commands: x X D C s S r ~ p P i a I A v . j k
+// u and Ctrl-R undo and redo with the undo stack of the line editor
+
+// Esc as it is recorded in the keys of a change
+const ESCAPE: char = '\x1b';
+
+// Backspace as it is recorded in the keys of a change
+const BACKSPACE: char = '\x7f';
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViMode {
+    Insert,
+    Normal,
+    // Selecting the text between where visual mode started and the cursor
+    Visual,
+}
+
+// What the line editor does after a key in vi mode
+pub enum ViResult {
+    // The key was a vi command, the buffer or the cursor may have changed
+    Handled,
+
+    // The line editor runs a function of its own, like previous-history for k
+    Function(Function),
+
+    // The line editor handles the key with its keymap, it is typed in insert mode or it is not
+    // a vi command
+    Pass(Key),
+}
+
+pub struct Vi {
+    mode: ViMode,
+
+    // Keys of the command being typed in normal or visual mode, like 2d or ci
+    pending: Vec<char>,
+
+    // Keys of the last change, with the text typed in insert mode after it, repeated by .
+    last_change: Vec<char>,
+    // Keys of the change in progress until insert mode is left
+    recording: Option<Vec<char>>,
+    // Whether . is running the keys of the last change, they are not recorded again
+    replaying: bool,
+
+    // Text deleted or yanked, put back by p and P
+    register: String,
+
+    // Last character search, the motion f, F, t or T and the character, repeated by ; and ,
+    last_find: Option<(char, char)>,
+
+    // Where the selection started in visual mode
+    anchor: usize,
+}
+
+// A command typed in normal mode
+struct Command {
+    count: Option<usize>,
+    kind: CommandKind,
+}
+
+enum CommandKind {
+    Move(Motion),
+    // An operator and the text it works on
+    Operate(char, Target),
+    // A command and the character it takes, like r
+    Simple(char, Option<char>),
+}
+
+enum Target {
+    Motion(Motion),
+    Line,
+    // i or a and the kind of object
+    Object(char, char),
+}
+
+// A motion and the character it takes, like f
+#[derive(Clone, Copy)]
+struct Motion {
+    key: char,
+    arg: Option<char>,
+}
+
+enum Parse {
+    Incomplete,
+    Invalid,
+    Command(Command),
+}
+
+impl Default for Vi {
+    fn default() -> Self {
+        Vi {
+            mode: ViMode::Insert,
+            pending: Vec::new(),
+            last_change: Vec::new(),
+            recording: None,
+            replaying: false,
+            register: String::new(),
+            last_find: None,
+            anchor: 0,
+        }
+    }
+}
+
+impl Vi {
+    pub fn mode(&self) -> ViMode {
+        self.mode
+    }
+
+    // Range of the selected text in visual mode
+    pub fn selection(&self, buffer: &[char], cursor: usize) -> Option<(usize, usize)> {
+        if self.mode != ViMode::Visual || buffer.is_empty() {
+            return None;
+        }
+
+        let end = graphemes::next_boundary(buffer, self.anchor.max(cursor));
+        Some((self.anchor.min(cursor), end))
+    }
+
+    // A new command starts in insert mode, the register and the last change are kept
+    pub fn start_command(&mut self) {
+        self.mode = ViMode::Insert;
+        self.pending.clear();
+        self.recording = None;
+    }
+
+    // In normal mode the cursor is on a character, not after the last one
+    pub fn clamp(&self, buffer: &[char], cursor: &mut usize) {
+        if self.mode != ViMode::Insert && !buffer.is_empty() && *cursor >= buffer.len() {
+            *cursor = graphemes::previous_boundary(buffer, buffer.len());
+        }
+    }
+
+    pub fn handle_key(&mut self, key: Key, buffer: &mut Vec<char>, cursor: &mut usize) -> ViResult {
+        if self.mode == ViMode::Insert {
+            return match key {
+                Key::Esc => {
+                    self.leave_insert(buffer, cursor);
+                    ViResult::Handled
+                }
+                // Alt and a key is how the terminal sends Esc quickly followed by the key
+                Key::Alt(c) => {
+                    self.leave_insert(buffer, cursor);
+                    self.command_key(c, buffer, cursor)
+                }
+                Key::Char(c) if c != '\n' && c != '\t' => {
+                    self.record(c);
+                    ViResult::Pass(key)
+                }
+                Key::Backspace => {
+                    self.record(BACKSPACE);
+                    ViResult::Pass(key)
+                }
+                _ => ViResult::Pass(key),
+            };
+        }
+
+        let c = match key {
+            Key::Char('\n') => return ViResult::Pass(key),
+            Key::Char(c) => c,
+            Key::Esc => ESCAPE,
+            Key::Alt(c) => {
+                self.pending.clear();
+                c
+            }
+            Key::Left | Key::Backspace => 'h',
+            Key::Right => 'l',
+            Key::Home => '0',
+            Key::End => '$',
+            Key::Delete => 'x',
+            Key::Ctrl('r') => return ViResult::Function(Function::Redo),
+            _ => return ViResult::Pass(key),
+        };
+
+        self.command_key(c, buffer, cursor)
+    }
+
+    fn command_key(&mut self, c: char, buffer: &mut Vec<char>, cursor: &mut usize) -> ViResult {
+        let result = if self.mode == ViMode::Visual {
+            self.visual_key(c, buffer, cursor);
+            ViResult::Handled
+        } else {
+            self.normal_key(c, buffer, cursor)
+        };
+
+        self.clamp(buffer, cursor);
+        result
+    }
+
+    fn normal_key(&mut self, c: char, buffer: &mut Vec<char>, cursor: &mut usize) -> ViResult {
+        if c == ESCAPE {
+            self.pending.clear();
+            return ViResult::Handled;
+        }
+
+        // j and k go through history, u undoes
+        if self.pending.is_empty() {
+            match c {
+                'j' => return ViResult::Function(Function::NextHistory),
+                'k' => return ViResult::Function(Function::PreviousHistory),
+                'u' => return ViResult::Function(Function::Undo),
+                _ => {}
+            }
+        }
+
+        self.pending.push(c);
+        let command = match parse(&self.pending) {
+            Parse::Incomplete => return ViResult::Handled,
+            Parse::Invalid => {
+                self.pending.clear();
+                return ViResult::Handled;
+            }
+            Parse::Command(command) => command,
+        };
+        let keys = std::mem::take(&mut self.pending);
+
+        // Changes can be repeated
+        let is_change = match &command.kind {
+            CommandKind::Move(_) => false,
+            CommandKind::Operate(operator, _) => *operator != 'y',
+            CommandKind::Simple(command, _) => !"v.".contains(*command),
+        };
+        if is_change && !self.replaying {
+            self.last_change = keys;
+        }
+
+        self.execute(command, buffer, cursor);
+
+        // The text typed in insert mode is part of the change
+        if is_change && !self.replaying && self.mode == ViMode::Insert {
+            self.recording = Some(self.last_change.clone());
+        }
+
+        ViResult::Handled
+    }
+
+    fn execute(&mut self, command: Command, buffer: &mut Vec<char>, cursor: &mut usize) {
+        let count = command.count.unwrap_or(1);
+
+        match command.kind {
+            CommandKind::Move(motion) => {
+                if let Some((target, _)) = self.motion(motion, count, buffer, *cursor) {
+                    *cursor = target;
+                }
+            }
+            CommandKind::Operate(operator, target) => {
+                let range = match target {
+                    Target::Line => Some((0, buffer.len())),
+                    Target::Object(kind, object) => text_object(kind, object, buffer, *cursor),
+                    Target::Motion(motion) => {
+                        // cw changes to the end of the word like ce, the blanks after it are kept
+                        let motion = match motion.key {
+                            'w' | 'W'
+                                if operator == 'c'
+                                    && buffer.get(*cursor).is_some_and(|c| !c.is_whitespace()) =>
+                            {
+                                Motion {
+                                    key: if motion.key == 'w' { 'e' } else { 'E' },
+                                    arg: None,
+                                }
+                            }
+                            _ => motion,
+                        };
+
+                        self.motion(motion, count, buffer, *cursor)
+                            .map(|(target, inclusive)| {
+                                let end = target.max(*cursor);
+                                let end = if inclusive {
+                                    graphemes::next_boundary(buffer, end)
+                                } else {
+                                    end
+                                };
+                                (target.min(*cursor), end.min(buffer.len()))
+                            })
+                    }
+                };
+
+                if let Some((start, end)) = range {
+                    self.operate(operator, start, end, buffer, cursor);
+                }
+            }
+            CommandKind::Simple(key, arg) => {
+                self.simple(key, arg, count, command.count, buffer, cursor)
+            }
+        }
+    }
+
+    fn simple(
+        &mut self,
+        command: char,
+        arg: Option<char>,
+        count: usize,
+        explicit_count: Option<usize>,
+        buffer: &mut Vec<char>,
+        cursor: &mut usize,
+    ) {
+        let len = buffer.len();
+
+        match command {
+            'x' => self.operate(
+                'd',
+                *cursor,
+                forward(buffer, *cursor, count),
+                buffer,
+                cursor,
+            ),
+            'X' => self.operate(
+                'd',
+                backward(buffer, *cursor, count),
+                *cursor,
+                buffer,
+                cursor,
+            ),
+            'D' => self.operate('d', *cursor, len, buffer, cursor),
+            'C' => self.operate('c', *cursor, len, buffer, cursor),
+            's' => self.operate(
+                'c',
+                *cursor,
+                forward(buffer, *cursor, count),
+                buffer,
+                cursor,
+            ),
+            'S' => self.operate('c', 0, len, buffer, cursor),
+            'r' => {
+                // Each of the `count` characters is replaced with its combining marks
+                let end = forward(buffer, *cursor, count);
+                let enough = graphemes::split(&buffer[*cursor..end]).len() == count;
+                if let Some(replacement) = arg.filter(|_| enough) {
+                    buffer.splice(*cursor..end, std::iter::repeat_n(replacement, count));
+                    *cursor += count - 1;
+                }
+            }
+            '~' => {
+                let end = forward(buffer, *cursor, count);
+                for c in &mut buffer[*cursor..end] {
+                    *c = toggle_case(*c);
+                }
+                *cursor = end;
+            }
+            'p' | 'P' => {
+                if self.register.is_empty() {
+                    return;
+                }
+
+                let start = if command == 'p' {
+                    graphemes::next_boundary(buffer, *cursor)
+                } else {
+                    *cursor
+                };
+                let text: Vec<char> = self.register.repeat(count).chars().collect();
+                let text_len = text.len();
+                buffer.splice(start..start, text);
+                *cursor = graphemes::previous_boundary(buffer, start + text_len);
+            }
+            'i' => self.mode = ViMode::Insert,
+            'a' => {
+                *cursor = graphemes::next_boundary(buffer, *cursor);
+                self.mode = ViMode::Insert;
+            }
+            'I' => {
+                *cursor = first_non_blank(buffer);
+                self.mode = ViMode::Insert;
+            }
+            'A' => {
+                *cursor = len;
+                self.mode = ViMode::Insert;
+            }
+            'v' => {
+                self.anchor = *cursor;
+                self.mode = ViMode::Visual;
+            }
+            '.' => self.repeat(explicit_count, buffer, cursor),
+            _ => {}
+        }
+    }
+
+    // Run the keys of the last change again, a count replaces the count it was given
+    fn repeat(&mut self, count: Option<usize>, buffer: &mut Vec<char>, cursor: &mut usize) {
+        if self.last_change.is_empty() {
+            return;
+        }
+
+        let mut keys = self.last_change.clone();
+        if let Some(count) = count {
+            let digits = keys.iter().take_while(|c| c.is_ascii_digit()).count();
+            keys.splice(0..digits, count.to_string().chars());
+        }
+
+        self.replaying = true;
+
+        for c in keys {
+            match self.mode {
+                ViMode::Insert if c == ESCAPE => self.leave_insert(buffer, cursor),
+                ViMode::Insert if c == BACKSPACE => {
+                    if *cursor > 0 {
+                        let start = graphemes::previous_boundary(buffer, *cursor);
+                        buffer.drain(start..*cursor);
+                        *cursor = start;
+                    }
+                }
+                ViMode::Insert => {
+                    buffer.insert(*cursor, c);
+                    *cursor += 1;
+                }
+                _ => {
+                    self.normal_key(c, buffer, cursor);
+                }
+            }
+        }
+
+        if self.mode == ViMode::Insert {
+            self.leave_insert(buffer, cursor);
+        }
+        self.replaying = false;
+    }
+
+    fn visual_key(&mut self, c: char, buffer: &mut Vec<char>, cursor: &mut usize) {
+        let (start, end) = match self.selection(buffer, *cursor) {
+            Some(selection) => selection,
+            None => (*cursor, *cursor),
+        };
+
+        if self.pending.is_empty() {
+            match c {
+                ESCAPE | 'v' => {
+                    self.mode = ViMode::Normal;
+                    return;
+                }
+                'o' => {
+                    std::mem::swap(&mut self.anchor, cursor);
+                    return;
+                }
+                'd' | 'x' | 'c' | 's' | 'y' | '~' => {
+                    self.mode = ViMode::Normal;
+
+                    match c {
+                        'd' | 'x' => self.operate('d', start, end, buffer, cursor),
+                        'c' | 's' => self.operate('c', start, end, buffer, cursor),
+                        'y' => self.operate('y', start, end, buffer, cursor),
+                        _ => {
+                            for c in &mut buffer[start..end] {
+                                *c = toggle_case(*c);
+                            }
+                            *cursor = start;
+                        }
+                    }
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        // Motions move the cursor and with it the end of the selection
+        self.pending.push(c);
+        match parse(&self.pending) {
+            Parse::Incomplete => return,
+            Parse::Command(Command {
+                count,
+                kind: CommandKind::Move(motion),
+            }) => {
+                if let Some((target, _)) = self.motion(motion, count.unwrap_or(1), buffer, *cursor)
+                {
+                    *cursor = target;
+                }
+            }
+            _ => {}
+        }
+        self.pending.clear();
+    }
+
+    // Apply `operator` to the text from `start` to `end`
+    fn operate(
+        &mut self,
+        operator: char,
+        start: usize,
+        end: usize,
+        buffer: &mut Vec<char>,
+        cursor: &mut usize,
+    ) {
+        if start < end {
+            self.register = buffer[start..end].iter().collect();
+        }
+        if operator != 'y' {
+            buffer.drain(start..end);
+        }
+        *cursor = start;
+
+        if operator == 'c' {
+            self.mode = ViMode::Insert;
+        }
+    }
+
+    fn leave_insert(&mut self, buffer: &[char], cursor: &mut usize) {
+        self.mode = ViMode::Normal;
+        *cursor = graphemes::previous_boundary(buffer, *cursor);
+
+        if let Some(mut keys) = self.recording.take() {
+            keys.push(ESCAPE);
+            self.last_change = keys;
+        }
+    }
+
+    // Record a key typed in insert mode as part of the change in progress
+    fn record(&mut self, c: char) {
+        if let Some(keys) = &mut self.recording {
+            keys.push(c);
+        }
+    }
+
+    // Where `motion` repeated `count` times moves the cursor and whether the character there is
+    // included when an operator uses the motion
+    fn motion(
+        &mut self,
+        motion: Motion,
+        count: usize,
+        buffer: &[char],
+        cursor: usize,
+    ) -> Option<(usize, bool)> {
+        let len = buffer.len();
+
+        let target = match motion.key {
+            'h' => (backward(buffer, cursor, count), false),
+            'l' => (forward(buffer, cursor, count), false),
+            '0' => (0, false),
+            '^' => (first_non_blank(buffer), false),
+            '$' => (len, false),
+            'w' | 'W' => {
+                let big = motion.key == 'W';
+                let target =
+                    (0..count).fold(cursor, |position, _| next_word_start(buffer, position, big));
+                (target, false)
+            }
+            'b' | 'B' => {
+                let big = motion.key == 'B';
+                let target = (0..count).fold(cursor, |position, _| {
+                    previous_word_start(buffer, position, big)
+                });
+                (target, false)
+            }
+            'e' | 'E' => {
+                let big = motion.key == 'E';
+                let target = (0..count).fold(cursor, |position, _| word_end(buffer, position, big));
+                (target, true)
+            }
+            'f' | 'F' | 't' | 'T' => {
+                let target = motion.arg?;
+                self.last_find = Some((motion.key, target));
+                find(motion.key, target, count, buffer, cursor)?
+            }
+            ';' | ',' => {
+                let (key, target) = self.last_find?;
+                let key = if motion.key == ';' {
+                    key
+                } else {
+                    match key {
+                        'f' => 'F',
+                        'F' => 'f',
+                        't' => 'T',
+                        _ => 't',
+                    }
+                };
+                find(key, target, count, buffer, cursor)?
+            }
+            _ => return None,
+        };
+
+        Some(target)
+    }
+}
+
+// Parse the keys of a command typed in normal mode
+fn parse(keys: &[char]) -> Parse {
+    let (count, mut index) = parse_count(keys);
+
+    let key = match keys.get(index) {
+        Some(key) => *key,
+        None => return Parse::Incomplete,
+    };
+    index += 1;
+
+    let command = |kind| Parse::Command(Command { count, kind });
+
+    match key {
+        'd' | 'c' | 'y' => {
+            let (operator_count, length) = parse_count(&keys[index..]);
+            index += length;
+
+            let count = match (count, operator_count) {
+                (Some(count), Some(operator_count)) => Some(count * operator_count),
+                (count, operator_count) => count.or(operator_count),
+            };
+
+            let target = match keys.get(index) {
+                None => return Parse::Incomplete,
+                Some(target) if *target == key => Target::Line,
+                Some('i') | Some('a') => match keys.get(index + 1) {
+                    None => return Parse::Incomplete,
+                    Some(object) if "wW\"'`()b[]{}B<>".contains(*object) => {
+                        Target::Object(keys[index], *object)
+                    }
+                    Some(_) => return Parse::Invalid,
+                },
+                Some(_) => match parse_motion(&keys[index..]) {
+                    Parse::Command(Command {
+                        kind: CommandKind::Move(motion),
+                        ..
+                    }) => Target::Motion(motion),
+                    Parse::Incomplete => return Parse::Incomplete,
+                    _ => return Parse::Invalid,
+                },
+            };
+
+            Parse::Command(Command {
+                count,
+                kind: CommandKind::Operate(key, target),
+            })
+        }
+        'r' => match keys.get(index) {
+            Some(c) => command(CommandKind::Simple('r', Some(*c))),
+            None => Parse::Incomplete,
+        },
+        'x' | 'X' | 'D' | 'C' | 's' | 'S' | '~' | 'p' | 'P' | 'i' | 'a' | 'I' | 'A' | 'v' | '.' => {
+            command(CommandKind::Simple(key, None))
+        }
+        _ => match parse_motion(&keys[index - 1..]) {
+            Parse::Command(motion) => command(motion.kind),
+            other => other,
+        },
+    }
+}
+
+// Parse a motion at the start of `keys`
+fn parse_motion(keys: &[char]) -> Parse {
+    let key = match keys.first() {
+        Some(key) => *key,
+        None => return Parse::Incomplete,
+    };
+
+    let arg = match key {
+        'f' | 'F' | 't' | 'T' => match keys.get(1) {
+            Some(arg) => Some(*arg),
+            None => return Parse::Incomplete,
+        },
+        'h' | 'l' | 'w' | 'W' | 'b' | 'B' | 'e' | 'E' | '0' | '^' | '$' | ';' | ',' => None,
+        _ => return Parse::Invalid,
+    };
+
+    Parse::Command(Command {
+        count: None,
+        kind: CommandKind::Move(Motion { key, arg }),
+    })
+}
+
+// A count at the start of `keys` and the number of keys it takes, 0 is a motion and not a count
+fn parse_count(keys: &[char]) -> (Option<usize>, usize) {
+    if !keys.first().is_some_and(|c| ('1'..='9').contains(c)) {
+        return (None, 0);
+    }
+
+    let digits: String = keys.iter().take_while(|c| c.is_ascii_digit()).collect();
+    (digits.parse().ok(), digits.len())
+}
+
+// Index in `buffer` `count` grapheme clusters after `index`
+fn forward(buffer: &[char], index: usize, count: usize) -> usize {
+    (0..count).fold(index, |index, _| graphemes::next_boundary(buffer, index))
+}
+
+// Index in `buffer` `count` grapheme clusters before `index`
+fn backward(buffer: &[char], index: usize, count: usize) -> usize {
+    (0..count).fold(index, |index, _| {
+        graphemes::previous_boundary(buffer, index)
+    })
+}
+
+// Words are runs of letters, digits and underscores or runs of other characters that are not
+// blank, big words are runs of characters that are not blank
+// A grapheme cluster is in the class of its first char, the one at the index where it starts
+fn class(c: char, big: bool) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if big || c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+// First char of the grapheme cluster before `position`
+fn char_before(buffer: &[char], position: usize) -> char {
+    buffer[graphemes::previous_boundary(buffer, position)]
+}
+
+fn next_word_start(buffer: &[char], position: usize, big: bool) -> usize {
+    let len = buffer.len();
+    let mut position = position;
+
+    if position < len {
+        let start_class = class(buffer[position], big);
+        while position < len && class(buffer[position], big) == start_class {
+            position = graphemes::next_boundary(buffer, position);
+        }
+    }
+    while position < len && buffer[position].is_whitespace() {
+        position = graphemes::next_boundary(buffer, position);
+    }
+
+    position
+}
+
+fn previous_word_start(buffer: &[char], position: usize, big: bool) -> usize {
+    let mut position = position;
+
+    while position > 0 && char_before(buffer, position).is_whitespace() {
+        position = graphemes::previous_boundary(buffer, position);
+    }
+    if position > 0 {
+        let word_class = class(char_before(buffer, position), big);
+        while position > 0 && class(char_before(buffer, position), big) == word_class {
+            position = graphemes::previous_boundary(buffer, position);
+        }
+    }
+
+    position
+}
+
+fn word_end(buffer: &[char], position: usize, big: bool) -> usize {
+    let len = buffer.len();
+    let mut position = graphemes::next_boundary(buffer, position);
+
+    while position < len && buffer[position].is_whitespace() {
+        position = graphemes::next_boundary(buffer, position);
+    }
+    if position >= len {
+        return graphemes::previous_boundary(buffer, len);
+    }
+
+    let word_class = class(buffer[position], big);
+    loop {
+        let next = graphemes::next_boundary(buffer, position);
+        if next >= len || class(buffer[next], big) != word_class {
+            return position;
+        }
+        position = next;
+    }
+}
+
+fn first_non_blank(buffer: &[char]) -> usize {
+    buffer
+        .iter()
+        .position(|c| !c.is_whitespace())
+        .unwrap_or(buffer.len())
+}
+
+// Where f, F, t or T with `target` moves the cursor, the count-th occurrence is used
+// Only grapheme clusters that start with `target` are found
+fn find(
+    key: char,
+    target: char,
+    count: usize,
+    buffer: &[char],
+    cursor: usize,
+) -> Option<(usize, bool)> {
+    let starts = graphemes::split(buffer)
+        .into_iter()
+        .map(|(start, _)| start)
+        .filter(|&start| buffer[start] == target);
+
+    match key {
+        'f' | 't' => {
+            let position = starts.filter(|&start| start > cursor).nth(count - 1)?;
+            if key == 'f' {
+                Some((position, true))
+            } else {
+                Some((graphemes::previous_boundary(buffer, position), true))
+            }
+        }
+        _ => {
+            let position = starts
+                .filter(|&start| start < cursor)
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .nth(count - 1)?;
+            if key == 'F' {
+                Some((position, false))
+            } else {
+                Some((graphemes::next_boundary(buffer, position), false))
+            }
+        }
+    }
+}
+
+// Range of the text object `object` at the cursor, `kind` is i for its inside and a for all of it
+fn text_object(kind: char, object: char, buffer: &[char], cursor: usize) -> Option<(usize, usize)> {
+    if buffer.is_empty() {
+        return None;
+    }
+    let cursor = cursor.min(buffer.len() - 1);
+
+    match object {
+        'w' | 'W' => {
+            let big = object == 'W';
+            let word_class = class(buffer[cursor], big);
+
+            let mut start = cursor;
+            while start > 0 && class(char_before(buffer, start), big) == word_class {
+                start = graphemes::previous_boundary(buffer, start);
+            }
+            let mut end = graphemes::next_boundary(buffer, cursor);
+            while end < buffer.len() && class(buffer[end], big) == word_class {
+                end = graphemes::next_boundary(buffer, end);
+            }
+
+            if kind == 'a' {
+                // The blanks after the word are included, or those before it if there are none
+                let blanks_end = (end..buffer.len())
+                    .find(|&i| !buffer[i].is_whitespace())
+                    .unwrap_or(buffer.len());
+                if blanks_end > end {
+                    end = blanks_end;
+                } else {
+                    while start > 0 && char_before(buffer, start).is_whitespace() {
+                        start = graphemes::previous_boundary(buffer, start);
+                    }
+                }
+            }
+
+            Some((start, end))
+        }
+        '"' | '\'' | '`' => {
+            // Quotes are paired from the start of the line
+            let quotes: Vec<usize> = (0..buffer.len()).filter(|&i| buffer[i] == object).collect();
+            let (open, close) = quotes
+                .chunks_exact(2)
+                .map(|pair| (pair[0], pair[1]))
+                .find(|&(open, close)| open <= cursor && cursor <= close)
+                .or_else(|| {
+                    quotes
+                        .chunks_exact(2)
+                        .map(|pair| (pair[0], pair[1]))
+                        .find(|&(open, _)| open > cursor)
+                })?;
+
+            if kind == 'i' {
+                Some((open + 1, close))
+            } else {
+                // Like aw, the blanks after the quotes are included
+                let end = (close + 1..buffer.len())
+                    .find(|&i| !buffer[i].is_whitespace())
+                    .unwrap_or(buffer.len());
+                Some((open, end))
+            }
+        }
+        _ => {
+            let (open_char, close_char) = match object {
+                '(' | ')' | 'b' => ('(', ')'),
+                '[' | ']' => ('[', ']'),
+                '{' | '}' | 'B' => ('{', '}'),
+                _ => ('<', '>'),
+            };
+
+            // The opening bracket that is not closed before the cursor
+            let mut depth = 0;
+            let mut open = None;
+            for i in (0..=cursor).rev() {
+                if buffer[i] == close_char && i != cursor {
+                    depth += 1;
+                } else if buffer[i] == open_char {
+                    if depth == 0 {
+                        open = Some(i);
+                        break;
+                    }
+                    depth -= 1;
+                }
+            }
+            let open = open?;
+
+            let mut depth = 0;
+            let mut close = None;
+            for (i, c) in buffer.iter().enumerate().skip(open + 1) {
+                if *c == open_char {
+                    depth += 1;
+                } else if *c == close_char {
+                    if depth == 0 {
+                        close = Some(i);
+                        break;
+                    }
+                    depth -= 1;
+                }
+            }
+            let close = close?;
+
+            if kind == 'i' {
+                Some((open + 1, close))
+            } else {
+                Some((open, close + 1))
+            }
+        }
+    }
+}
+
+fn toggle_case(c: char) -> char {
+    if c.is_uppercase() {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c.to_uppercase().next().unwrap_or(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs the keys from normal mode with the cursor at `cursor`
+    fn run(text: &str, cursor: usize, keys: &str) -> (String, usize) {
+        let (text, cursor, _) = run_in(&mut Vi::default(), text, cursor, keys);
+        (text, cursor)
+    }
+
+    // Runs the keys with `vi` from normal mode and returns the mode it ends in
+    // ESCAPE is typed as Esc, characters passed back in insert mode are inserted like the line
+    // editor does
+    fn run_in(vi: &mut Vi, text: &str, cursor: usize, keys: &str) -> (String, usize, ViMode) {
+        let mut buffer: Vec<char> = text.chars().collect();
+        let mut start = 0;
+        vi.handle_key(Key::Esc, &mut buffer, &mut start);
+        let mut cursor = cursor;
+        for c in keys.chars() {
+            let key = if c == ESCAPE { Key::Esc } else { Key::Char(c) };
+            if let ViResult::Pass(Key::Char(c)) = vi.handle_key(key, &mut buffer, &mut cursor) {
+                buffer.insert(cursor, c);
+                cursor += 1;
+            }
+        }
+
+        (buffer.iter().collect(), cursor, vi.mode())
+    }
+
+    #[test]
+    fn word_motions_over_combining_marks() {
+        // x e U+0301 y, space, z
+        assert_eq!(run("xe\u{301}y z", 0, "w").1, 5);
+        assert_eq!(run("xe\u{301}y z", 5, "b").1, 0);
+        assert_eq!(run("xe\u{301}y z", 0, "e").1, 3);
+        assert_eq!(run("ab xe\u{301}", 0, "e").1, 1);
+        assert_eq!(run("ab xe\u{301}", 0, "ee").1, 4);
+    }
+
+    #[test]
+    fn operators_keep_clusters_whole() {
+        assert_eq!(run("ab xe\u{301} c", 3, "dw").0, "ab c");
+        assert_eq!(run("ab xe\u{301} c", 3, "diw").0, "ab  c");
+        assert_eq!(run("xe\u{301}y", 0, "dty").0, "y");
+        assert_eq!(run("xe\u{301}y", 3, "dTx").0, "xy");
+        assert_eq!(run("xe\u{301}y", 0, "d2l").0, "y");
+        assert_eq!(run("xe\u{301}y", 1, "ra").0, "xay");
+    }
+
+    #[test]
+    fn counts() {
+        let text = "one two three four five";
+        assert_eq!(run(text, 0, "2dw"), (String::from("three four five"), 0));
+        assert_eq!(run(text, 0, "d2w"), (String::from("three four five"), 0));
+        assert_eq!(run(text, 0, "2d2w"), (String::from("five"), 0));
+        assert_eq!(run(text, 0, "3w"), (String::from(text), 14));
+        assert_eq!(run(text, 0, "2e"), (String::from(text), 6));
+        assert_eq!(run(text, 4, "c2eX\x1b").0, "one X four five");
+        assert_eq!(run("abcdef", 1, "3x"), (String::from("aef"), 1));
+        assert_eq!(run("abcdef", 4, "2X"), (String::from("abef"), 2));
+        assert_eq!(run("abcdef", 0, "3rx"), (String::from("xxxdef"), 2));
+        assert_eq!(run("ab", 0, "x3p"), (String::from("baaa"), 3));
+
+        // A count larger than what is left goes as far as it can
+        assert_eq!(run("abc", 1, "9x").0, "a");
+        assert_eq!(run("abc", 0, "9rx").0, "abc");
+    }
+
+    #[test]
+    fn text_objects() {
+        let text = "echo hello world";
+        assert_eq!(
+            run_in(&mut Vi::default(), text, 7, "ciw"),
+            (String::from("echo  world"), 5, ViMode::Insert)
+        );
+        assert_eq!(
+            run(text, 7, "ciwbye\x1b"),
+            (String::from("echo bye world"), 7)
+        );
+        assert_eq!(run(text, 7, "daw").0, "echo world");
+
+        // Parentheses, with the innermost pair around the cursor
+        let text = "f(a, (b), c)";
+        assert_eq!(run(text, 2, "di(").0, "f()");
+        assert_eq!(run(text, 6, "di(").0, "f(a, (), c)");
+        assert_eq!(run(text, 6, "da(").0, "f(a, , c)");
+        assert_eq!(run(text, 2, "dib").0, "f()");
+        assert_eq!(run(text, 0, "di(").0, text);
+
+        // Quotes
+        assert_eq!(run("echo \"a b\" c", 7, "ci\"x\x1b").0, "echo \"x\" c");
+        assert_eq!(run("echo 'a b' c", 7, "da'").0, "echo c");
+    }
+
+    #[test]
+    fn repeat_changes() {
+        let text = "a b c d e f";
+        assert_eq!(run(text, 0, "dw.").0, "c d e f");
+
+        // A count given to . replaces the count of the change
+        assert_eq!(run(text, 0, "2dw3.").0, "f");
+        assert_eq!(run(text, 0, "dw2.").0, "d e f");
+
+        // The text typed in insert mode is repeated with the change
+        assert_eq!(run("one two", 0, "cwxy\x1bw.").0, "xy xy");
+        assert_eq!(run("ab", 0, "ix\x1b.").0, "xxab");
+        assert_eq!(run("ab", 0, "A!\x1b0.").0, "ab!!");
+
+        // Motions and yanks are not changes
+        assert_eq!(run(text, 0, "dwwyw.").0, "b d e f");
+    }
+
+    #[test]
+    fn repeat_character_searches() {
+        let text = "a,b,c,d";
+        assert_eq!(run(text, 0, "f,").1, 1);
+        assert_eq!(run(text, 0, "f,;").1, 3);
+        assert_eq!(run(text, 0, "f,;;,").1, 3);
+        assert_eq!(run(text, 6, "F,;").1, 3);
+        assert_eq!(run(text, 6, "F,;,").1, 5);
+        assert_eq!(run(text, 0, "2f,").1, 3);
+        assert_eq!(run(text, 0, "f,2;").1, 5);
+
+        // The search is repeated by operators too, up to and including the character found
+        assert_eq!(run(text, 0, "f,d;").0, "ac,d");
+    }
+
+    #[test]
+    fn visual_mode() {
+        let text = "echo hello world";
+        let mut vi = Vi::default();
+        assert_eq!(
+            run_in(&mut vi, text, 5, "ve"),
+            (String::from(text), 9, ViMode::Visual)
+        );
+        assert_eq!(
+            vi.selection(&text.chars().collect::<Vec<_>>(), 9),
+            Some((5, 10))
+        );
+
+        assert_eq!(run(text, 5, "ved"), (String::from("echo  world"), 5));
+        assert_eq!(run(text, 5, "vex"), (String::from("echo  world"), 5));
+        assert_eq!(run(text, 9, "vbd"), (String::from("echo  world"), 5));
+        assert_eq!(run(text, 5, "vlly$p").0, "echo hello worldhel");
+        assert_eq!(run(text, 0, "v3l~").0, "ECHO hello world");
+        assert_eq!(run(text, 5, "veoy0P").0, "helloecho hello world");
+        assert_eq!(
+            run_in(&mut Vi::default(), text, 5, "vecbye"),
+            (String::from("echo bye world"), 8, ViMode::Insert)
+        );
+
+        // Esc and v leave visual mode without a change
+        assert_eq!(
+            run_in(&mut Vi::default(), text, 5, "vl\x1b"),
+            (String::from(text), 6, ViMode::Normal)
+        );
+        assert_eq!(
+            run_in(&mut Vi::default(), text, 5, "vlvx"),
+            (String::from("echo hllo world"), 6, ViMode::Normal)
+        );
+    }
+}